@@ -0,0 +1,511 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+
+use crate::char::{
+    AAPlayerDescriptor, AttackBuffer, AttackKey, CharComponentMap, DirectionFacingFlags,
+    PlayerHealth, PlayerIdentifier, PlayerStocks,
+};
+use crate::game::*;
+use crate::netplay::NetplaySession;
+use crate::projectile::ProjectileIdentifier;
+use crate::replay::ReplayPlayer;
+use crate::AppStates;
+
+// Agents get one of these as a line of json after every frame of gameplay
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Observation {
+    pub tick: u64,
+    pub gameplay_frame: u64,
+    pub players: Vec<PlayerObservation>,
+    pub projectiles: Vec<ProjectileObservation>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlayerObservation {
+    pub player_id: u64,
+    pub translation: [f32; 2],
+    pub rotation: f32,
+    pub linvel: [f32; 2],
+    pub angvel: f32,
+    pub current_health: f32,
+    pub maximum_health: f32,
+    pub available_jumps: u32,
+
+    // Only set in percentage mode
+    pub percentage: Option<f32>,
+    pub stocks: Option<u32>,
+
+    // Names of the direction flags that are set, e.g. ["UP", "LEFT"]
+    pub direction_facing: Vec<String>,
+
+    // The attack keys pressed so far, 1 or 2 for each
+    pub attack_buffer: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProjectileObservation {
+    // Player who fired it, None if they have since died
+    pub owner: Option<u64>,
+    pub translation: [f32; 2],
+    pub linvel: [f32; 2],
+    pub damage: f32,
+
+    // Hits it can still land
+    pub pierce: u32,
+    pub remaining_lifetime: f64,
+}
+
+// What agents send back, an empty inputs list is still a response as far as lock step is concerned
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AgentResponse {
+    pub tick: u64,
+
+    #[serde(default)]
+    pub inputs: Vec<PlayerInput>,
+}
+
+pub enum AgentEvent {
+    Connected(u64),
+    Disconnected(u64),
+    Response(u64, AgentResponse),
+}
+
+pub struct AgentServer {
+    observations: broadcast::Sender<String>,
+    inbound: Mutex<Receiver<AgentEvent>>,
+
+    // The last tick each connected agent responded to
+    pub agents: HashMap<u64, u64>,
+    pub tick: u64,
+
+    // Every input held back in lock step until everyone has answered, not just the agents' ones
+    pub pending_inputs: Vec<PlayerInput>,
+    pub stalled: bool,
+}
+
+// Reads responses off of one connection and writes every observation to it
+async fn serve_agent<S>(
+    stream: S,
+    agent_id: u64,
+    mut observations: broadcast::Receiver<String>,
+    events: Sender<AgentEvent>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+
+    if events.send(AgentEvent::Connected(agent_id)).is_err() {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            observation = observations.recv() => {
+                let observation = match observation {
+                    Ok(observation) => observation,
+                    // Too slow to keep up, carry on from the newest observation
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Agent {} skipped {} observations", agent_id, skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+
+                if writer.write_all(observation.as_bytes()).await.is_err()
+                    || writer.write_all(b"\n").await.is_err()
+                {
+                    break;
+                }
+            }
+            line = lines.next_line() => {
+                let line = match line {
+                    Ok(Some(line)) => line,
+                    _ => break,
+                };
+
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                match serde_json::from_str::<AgentResponse>(&line) {
+                    Ok(response) => {
+                        if events.send(AgentEvent::Response(agent_id, response)).is_err() {
+                            return;
+                        }
+                    }
+                    Err(error) => warn!("Got a malformed response from agent {}: {}", agent_id, error),
+                }
+            }
+        }
+    }
+
+    let _ = events.send(AgentEvent::Disconnected(agent_id));
+}
+
+impl AgentServer {
+    pub fn bind(settings: &AgentSettings) -> anyhow::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        let (observations, _) = broadcast::channel::<String>(settings.observation_backlog);
+        let (events, inbound) = channel::<AgentEvent>();
+
+        let thread_observations = observations.clone();
+
+        // Addresses starting with unix: are a socket path instead of a tcp address
+        match settings.address.strip_prefix("unix:") {
+            Some(path) => {
+                #[cfg(unix)]
+                {
+                    // Left over from the last run, binding fails if it's still there
+                    let _ = std::fs::remove_file(path);
+                    let listener =
+                        runtime.block_on(async { tokio::net::UnixListener::bind(path) })?;
+
+                    std::thread::spawn(move || {
+                        runtime.block_on(async move {
+                            let mut next_agent_id = 0;
+                            loop {
+                                match listener.accept().await {
+                                    Ok((stream, _)) => {
+                                        next_agent_id += 1;
+                                        tokio::spawn(serve_agent(
+                                            stream,
+                                            next_agent_id,
+                                            thread_observations.subscribe(),
+                                            events.clone(),
+                                        ));
+                                    }
+                                    Err(error) => warn!("Failed to accept agent: {}", error),
+                                }
+                            }
+                        });
+                    });
+                }
+
+                #[cfg(not(unix))]
+                {
+                    anyhow::bail!(
+                        "unix sockets arent supported here, use a tcp address for {}",
+                        path
+                    );
+                }
+            }
+            None => {
+                let listener = runtime.block_on(TcpListener::bind(&settings.address))?;
+
+                std::thread::spawn(move || {
+                    runtime.block_on(async move {
+                        let mut next_agent_id = 0;
+                        loop {
+                            match listener.accept().await {
+                                Ok((stream, _)) => {
+                                    let _ = stream.set_nodelay(true);
+                                    next_agent_id += 1;
+                                    tokio::spawn(serve_agent(
+                                        stream,
+                                        next_agent_id,
+                                        thread_observations.subscribe(),
+                                        events.clone(),
+                                    ));
+                                }
+                                Err(error) => warn!("Failed to accept agent: {}", error),
+                            }
+                        }
+                    });
+                });
+            }
+        }
+
+        println!("Listening for agents on {}", settings.address);
+        Ok(Self {
+            observations,
+            inbound: Mutex::new(inbound),
+            agents: HashMap::new(),
+            tick: 0,
+            pending_inputs: vec![],
+            stalled: false,
+        })
+    }
+
+    pub fn receive(&self) -> Vec<AgentEvent> {
+        match self.inbound.lock() {
+            Ok(inbound) => inbound.try_iter().collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    pub fn waiting_on_agents(&self) -> bool {
+        self.agents.values().any(|responded| *responded < self.tick)
+    }
+}
+
+pub fn start_agent_server(mut commands: Commands, settings: Res<GameSettings>) {
+    if !settings.agent.enabled {
+        return;
+    }
+
+    match AgentServer::bind(&settings.agent) {
+        Ok(server) => commands.insert_resource(server),
+        Err(error) => error!(
+            "Failed to listen for agents on {}: {}",
+            settings.agent.address, error
+        ),
+    }
+}
+
+pub fn agent_input_system(
+    server: Option<ResMut<AgentServer>>,
+    netplay_session: Option<Res<NetplaySession>>,
+    replay_player: Option<Res<ReplayPlayer>>,
+    state: Res<State<AppStates>>,
+    settings: Res<GameSettings>,
+    char_map: Res<CharComponentMap>,
+    gameplay_toggle: Res<AAGamePlayToggle>,
+    mut physics_config: ResMut<RapierConfiguration>,
+    mut clock: ResMut<GameClock>,
+    mut input_queue: ResMut<PlayerInputQueue>,
+) {
+    let mut server = match server {
+        None => return,
+        Some(server) => server,
+    };
+
+    let in_game = state.current() == &AppStates::LoadGame;
+
+    for event in server.receive() {
+        match event {
+            AgentEvent::Connected(agent_id) => {
+                println!("Agent {} connected", agent_id);
+                // Nothing has been sent to it yet, so there's nothing to wait on until the next observation
+                let tick = server.tick;
+                server.agents.insert(agent_id, tick);
+            }
+            AgentEvent::Disconnected(agent_id) => {
+                println!("Agent {} disconnected", agent_id);
+                server.agents.remove(&agent_id);
+            }
+            AgentEvent::Response(agent_id, response) => {
+                if let Some(responded) = server.agents.get_mut(&agent_id) {
+                    *responded = (*responded).max(response.tick);
+                }
+
+                // Replays play back what was recorded, anything else would change how they turn out
+                if !in_game || replay_player.is_some() {
+                    continue;
+                }
+
+                // Agents can send input for any player id, ones for players who arent in this match have no character to move so they get dropped
+                for input in response.inputs {
+                    if char_map.contains_key(&input.player_id) {
+                        server.pending_inputs.push(input);
+                    } else {
+                        warn!(
+                            "Agent {} sent input for player {} who isnt in the match",
+                            agent_id, input.player_id
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    // Netplay already decides when the simulation moves forward, so lock step is left out of it
+    let lock_step = settings.agent.lock_step
+        && netplay_session.is_none()
+        && in_game
+        && gameplay_toggle.process_movement;
+
+    let stalled = lock_step && server.waiting_on_agents();
+    if stalled && !server.stalled {
+        // Same as netplay, the clock stops with physics so cooldowns dont run on while waiting
+        physics_config.physics_pipeline_active = false;
+        clock.paused = true;
+    } else if !stalled && server.stalled && in_game && gameplay_toggle.process_movement {
+        // Only undoes the pause from above, the round ending or a menu opening while stalled paused things for themselves
+        physics_config.physics_pipeline_active = true;
+        clock.paused = false;
+    }
+    server.stalled = stalled;
+
+    // Runs after every other input system, so keyboard, gamepad and cpu presses are held back with the agents' ones
+    // and get applied in the order they came in once everyone has answered
+    let frame_inputs = std::mem::take(&mut input_queue.inputs);
+    server.pending_inputs.extend(frame_inputs);
+    if stalled {
+        return;
+    }
+
+    input_queue.inputs = std::mem::take(&mut server.pending_inputs);
+}
+
+fn facing_names(facing: DirectionFacingFlags) -> Vec<String> {
+    [
+        (DirectionFacingFlags::UP, "UP"),
+        (DirectionFacingFlags::DOWN, "DOWN"),
+        (DirectionFacingFlags::LEFT, "LEFT"),
+        (DirectionFacingFlags::RIGHT, "RIGHT"),
+        (DirectionFacingFlags::NONE, "NONE"),
+    ]
+    .into_iter()
+    .filter(|(flag, _)| facing.contains(*flag))
+    .map(|(_, name)| name.to_string())
+    .collect()
+}
+
+pub fn agent_observation_system(
+    server: Option<ResMut<AgentServer>>,
+    state: Res<State<AppStates>>,
+    clock: Res<GameClock>,
+    players: Query<(
+        Entity,
+        &PlayerIdentifier,
+        &Transform,
+        &Velocity,
+        &PlayerHealth,
+        &AAPlayerDescriptor,
+        &AttackBuffer,
+        Option<&PlayerStocks>,
+    )>,
+    projectiles: Query<(&ProjectileIdentifier, &Transform, &Velocity)>,
+) {
+    let mut server = match server {
+        None => return,
+        Some(server) => server,
+    };
+
+    // Nothing moved this frame so there's nothing new to tell the agents
+    if server.stalled || clock.frames_due == 0 || state.current() != &AppStates::LoadGame {
+        return;
+    }
+
+    let mut owners = HashMap::new();
+    let mut player_observations = vec![];
+    for (entity, identifier, transform, velocity, health, descriptor, attack_buffer, stocks) in
+        players.iter()
+    {
+        owners.insert(entity, identifier.player_id);
+        player_observations.push(PlayerObservation {
+            player_id: identifier.player_id,
+            translation: transform.translation.truncate().into(),
+            rotation: transform.rotation.to_euler(EulerRot::XYZ).2,
+            linvel: velocity.linvel.into(),
+            angvel: velocity.angvel,
+            current_health: health.current_health,
+            maximum_health: health.maximum_health,
+            available_jumps: descriptor.available_jumps,
+            percentage: stocks.map(|stocks| stocks.percentage),
+            stocks: stocks.map(|stocks| stocks.stocks),
+            direction_facing: facing_names(descriptor.direction_facing),
+            attack_buffer: attack_buffer
+                .buffer
+                .iter()
+                .map(|key| match key {
+                    AttackKey::One => 1,
+                    AttackKey::Two => 2,
+                })
+                .collect(),
+        });
+    }
+    player_observations.sort_by_key(|player| player.player_id);
+
+    let projectile_observations = projectiles
+        .iter()
+        .map(|(projectile, transform, velocity)| ProjectileObservation {
+            owner: owners.get(&projectile.parent).copied(),
+            translation: transform.translation.truncate().into(),
+            linvel: velocity.linvel.into(),
+            damage: projectile.damage,
+            pierce: projectile.pierce_left(),
+            remaining_lifetime: (projectile.created_timestamp + projectile.lifetime
+                - clock.gameplay_seconds())
+            .max(0.0),
+        })
+        .collect();
+
+    server.tick += 1;
+    let observation = Observation {
+        tick: server.tick,
+        gameplay_frame: clock.gameplay_frame,
+        players: player_observations,
+        projectiles: projectile_observations,
+    };
+
+    match serde_json::to_string(&observation) {
+        // Only fails when no agents are connected, which is fine
+        Ok(observation) => {
+            let _ = server.observations.send(observation);
+        }
+        Err(error) => warn!("Failed to serialize observation: {}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pause_physics_and_movement;
+
+    // An agent that has been sent tick 1 and hasnt answered it yet
+    fn stalled_server() -> AgentServer {
+        let (observations, _) = broadcast::channel::<String>(1);
+        let (_, inbound) = channel::<AgentEvent>();
+
+        AgentServer {
+            observations,
+            inbound: Mutex::new(inbound),
+            agents: HashMap::from([(1, 0)]),
+            tick: 1,
+            pending_inputs: vec![],
+            stalled: false,
+        }
+    }
+
+    #[test]
+    fn round_ending_mid_stall_keeps_physics_paused() {
+        let mut settings = GameSettings::default();
+        settings.agent.lock_step = true;
+
+        let mut world = World::new();
+        world.insert_resource(stalled_server());
+        world.insert_resource(State::new(AppStates::LoadGame));
+        world.insert_resource(settings);
+        world.insert_resource(CharComponentMap::new());
+        world.insert_resource(AAGamePlayToggle {
+            process_movement: true,
+        });
+        world.insert_resource(RapierConfiguration::default());
+        world.insert_resource(GameClock::new(60.0));
+        world.insert_resource(PlayerInputQueue::default());
+        world.resource_mut::<GameClock>().paused = false;
+
+        let mut input_stage = SystemStage::single_threaded();
+        input_stage.add_system(agent_input_system);
+
+        input_stage.run(&mut world);
+        assert!(world.resource::<AgentServer>().stalled);
+        assert!(!world.resource::<RapierConfiguration>().physics_pipeline_active);
+        assert!(world.resource::<GameClock>().paused);
+
+        // The round ends while the agent still hasnt answered
+        let mut victory_stage = SystemStage::single_threaded();
+        victory_stage.add_system(pause_physics_and_movement);
+        victory_stage.run(&mut world);
+        world.insert_resource(State::new(AppStates::Victory));
+
+        input_stage.run(&mut world);
+        assert!(!world.resource::<AgentServer>().stalled);
+        assert!(!world.resource::<RapierConfiguration>().physics_pipeline_active);
+        assert!(world.resource::<GameClock>().paused);
+    }
+}
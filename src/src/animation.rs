@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use bevy_svg::prelude::Svg;
+use serde::{Deserialize, Serialize};
+
+use crate::action_traits::ScaleToRatio;
+use crate::assets::AssetType;
+use crate::char::{AAPlayerDescriptor, CharComponentMap, PlayerIdentifier};
+use crate::game::*;
+
+// Where a part is moved to on top of where it would normally be, translation is in the character's base dimensions
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct PartPose {
+    pub translation: [f32; 2],
+    pub rotation: f32,
+    pub scale: [f32; 2],
+}
+
+impl Default for PartPose {
+    fn default() -> Self {
+        Self {
+            translation: [0.0, 0.0],
+            rotation: 0.0,
+            scale: [1.0, 1.0],
+        }
+    }
+}
+
+impl PartPose {
+    pub fn lerp(&self, other: &PartPose, t: f32) -> PartPose {
+        let translation = Vec2::from(self.translation).lerp(Vec2::from(other.translation), t);
+        let scale = Vec2::from(self.scale).lerp(Vec2::from(other.scale), t);
+
+        PartPose {
+            translation: translation.to_array(),
+            rotation: self.rotation + (other.rotation - self.rotation) * t,
+            scale: scale.to_array(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AnimationFrame {
+    pub duration: f64,
+
+    // Part id to the asset it shows for this frame, one per player like character_assets or just one for everyone.
+    // Parts left out go back to their normal asset
+    pub assets: HashMap<String, Vec<String>>,
+
+    // Part id to the pose it gets to by the end of this frame, parts left out go back to how they normally are
+    pub poses: HashMap<String, PartPose>,
+
+    // Moves smoothly from the last frame's poses, otherwise it jumps straight to these ones
+    pub tween: bool,
+}
+
+impl Default for AnimationFrame {
+    fn default() -> Self {
+        Self {
+            duration: 0.1,
+            assets: HashMap::new(),
+            poses: HashMap::new(),
+            tween: true,
+        }
+    }
+}
+
+impl AnimationFrame {
+    pub fn pose(&self, part_id: &str) -> PartPose {
+        self.poses.get(part_id).copied().unwrap_or_default()
+    }
+
+    pub fn asset(&self, part_id: &str, player_id: u64) -> Option<&String> {
+        let assets = self.assets.get(part_id)?;
+        assets
+            .get((player_id - 1) as usize)
+            .or_else(|| assets.first())
+    }
+}
+
+// [[animation.clip]] in main.toml. idle, run, jump, fall and hurt get picked by the player's state,
+// anything else is for attacks and combos to name with their animation
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AnimationClip {
+    pub id: String,
+    pub frame: Vec<AnimationFrame>,
+    pub looping: bool,
+}
+
+impl Default for AnimationClip {
+    fn default() -> Self {
+        Self {
+            id: String::default(),
+            frame: vec![],
+            looping: true,
+        }
+    }
+}
+
+impl AnimationClip {
+    pub fn duration(&self) -> f64 {
+        self.frame.iter().map(|frame| frame.duration.max(0.0)).sum()
+    }
+
+    // The frame it's on after elapsed seconds and how far through that frame it is, from 0 to 1
+    pub fn sample(&self, elapsed: f64) -> Option<(usize, f32)> {
+        let duration = self.duration();
+        if self.frame.is_empty() || duration <= 0.0 {
+            return None;
+        }
+
+        let mut elapsed = match self.looping {
+            true => elapsed.max(0.0) % duration,
+            false => elapsed.clamp(0.0, duration),
+        };
+
+        for (index, frame) in self.frame.iter().enumerate() {
+            let frame_duration = frame.duration.max(0.0);
+            if elapsed < frame_duration {
+                return Some((index, (elapsed / frame_duration) as f32));
+            }
+            elapsed -= frame_duration;
+        }
+
+        // Only clips that have finished get this far
+        Some((self.frame.len() - 1, 1.0))
+    }
+
+    pub fn pose(&self, part_id: &str, index: usize, t: f32) -> PartPose {
+        let frame = &self.frame[index];
+        if !frame.tween {
+            return frame.pose(part_id);
+        }
+
+        // Looping clips tween from their last frame back round to the first
+        let previous = match index {
+            0 if self.looping => self.frame[self.frame.len() - 1].pose(part_id),
+            0 => PartPose::default(),
+            _ => self.frame[index - 1].pose(part_id),
+        };
+
+        previous.lerp(&frame.pose(part_id), t)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CharacterAnimation {
+    // Sideways speed it has to be going faster than for run to play instead of idle
+    pub run_speed: f32,
+
+    // Up or down speed that counts as being in the air even with all the jumps left
+    pub air_speed: f32,
+
+    pub clip: Vec<AnimationClip>,
+}
+
+impl Default for CharacterAnimation {
+    fn default() -> Self {
+        Self {
+            run_speed: 40.0,
+            air_speed: 40.0,
+            clip: vec![],
+        }
+    }
+}
+
+impl CharacterAnimation {
+    pub fn clip(&self, id: &str) -> Option<&AnimationClip> {
+        self.clip.iter().find(|clip| clip.id == id)
+    }
+}
+
+impl ScaleToRatio for CharacterAnimation {
+    fn scale_to_ratio(&self, ratio: &Vec3) -> Self {
+        let mut animation = self.clone();
+        animation.run_speed /= ratio.y;
+        animation.air_speed /= ratio.y;
+
+        for clip in animation.clip.iter_mut() {
+            for frame in clip.frame.iter_mut() {
+                for pose in frame.poses.values_mut() {
+                    pose.translation[0] /= ratio.y;
+                    pose.translation[1] /= ratio.y;
+                }
+            }
+        }
+
+        animation
+    }
+}
+
+// On the core, which clip is playing and the gameplay second it started on
+#[derive(Debug, Clone, Component, Default)]
+pub struct PlayerAnimation {
+    pub clip: String,
+    pub started: f64,
+}
+
+// On the core's and char_components' sprites, the core is always "core" and char_components go by their id
+#[derive(Debug, Clone, Component)]
+pub struct AnimatedPart {
+    pub player_id: u64,
+    pub part_id: String,
+    pub default_asset: String,
+    pub current_asset: String,
+    pub base_rotation: Quat,
+    pub base_scale: Vec3,
+    pub pose: PartPose,
+}
+
+impl AnimatedPart {
+    pub fn new(
+        player_id: u64,
+        part_id: String,
+        asset: String,
+        spawn_transform: &Transform,
+    ) -> Self {
+        Self {
+            player_id,
+            part_id,
+            default_asset: asset.clone(),
+            current_asset: asset,
+            base_rotation: spawn_transform.rotation,
+            base_scale: spawn_transform.scale,
+            pose: PartPose::default(),
+        }
+    }
+
+    // Called by sync_objects_colliders once the part has been put where it normally goes
+    pub fn apply_pose(&self, transform: &mut Transform, synced_rotation: Option<Quat>) {
+        transform.translation += Vec2::from(self.pose.translation).extend(0.0);
+        transform.rotation = synced_rotation.unwrap_or(self.base_rotation)
+            * Quat::from_rotation_z(self.pose.rotation);
+        transform.scale = self.base_scale * Vec2::from(self.pose.scale).extend(1.0);
+    }
+}
+
+pub fn animation_state_update(
+    clock: Res<GameClock>,
+    char_map: Res<CharComponentMap>,
+    mut player_query: Query<(
+        &PlayerIdentifier,
+        &AAPlayerDescriptor,
+        &Velocity,
+        &mut PlayerAnimation,
+    )>,
+) {
+    let now = clock.gameplay_seconds();
+
+    for (player_id, descriptor, velocity, mut player_animation) in player_query.iter_mut() {
+        let char_entities = match char_map.get(&player_id.player_id) {
+            Some(char_entities) => char_entities,
+            None => continue,
+        };
+        let animation = &char_entities.animation;
+
+        // Attack clips play through once from when the attack fired
+        let attack_clip = descriptor.last_attack.and_then(|(combo_id, frame)| {
+            let combo = char_entities.combos.combos.get(combo_id)?;
+            let clip = animation.clip(&combo.animation)?;
+            let started = frame as f64 * clock.timestep;
+            (now - started < clip.duration()).then(|| (clip.id.clone(), started))
+        });
+
+        let airborne = descriptor.available_jumps < descriptor.maximum_jumps
+            || velocity.linvel.y.abs() > animation.air_speed;
+
+        let hurt = descriptor.in_hitstun(&clock) && animation.clip("hurt").is_some();
+
+        let (clip, started) = match attack_clip {
+            Some((clip, started)) if !hurt => (clip, Some(started)),
+            _ => {
+                let state = if hurt {
+                    "hurt"
+                } else if airborne {
+                    match velocity.linvel.y <= 0.0 && animation.clip("fall").is_some() {
+                        true => "fall",
+                        false => "jump",
+                    }
+                } else if velocity.linvel.x.abs() > animation.run_speed {
+                    "run"
+                } else {
+                    "idle"
+                };
+
+                // Anything without a clip of its own just stays idle
+                match animation.clip(state) {
+                    Some(_) => (state.to_string(), None),
+                    None => ("idle".to_string(), None),
+                }
+            }
+        };
+
+        // Firing the same attack again starts its clip over
+        let restarted = match started {
+            Some(started) => started != player_animation.started,
+            None => false,
+        };
+
+        if clip != player_animation.clip || restarted {
+            player_animation.clip = clip;
+            player_animation.started = started.unwrap_or(now);
+        }
+    }
+}
+
+pub fn animate_parts(
+    clock: Res<GameClock>,
+    char_map: Res<CharComponentMap>,
+    animation_query: Query<&PlayerAnimation>,
+    mut part_query: Query<(
+        &mut AnimatedPart,
+        Option<&mut Handle<Svg>>,
+        Option<&mut Handle<Image>>,
+    )>,
+) {
+    let now = clock.gameplay_seconds();
+
+    for (mut part, svg_handle, image_handle) in part_query.iter_mut() {
+        let char_entities = match char_map.get(&part.player_id) {
+            Some(char_entities) => char_entities,
+            None => continue,
+        };
+
+        let player_animation = match animation_query.get(char_entities.core) {
+            Ok(player_animation) => player_animation,
+            Err(_) => continue,
+        };
+
+        let sampled = char_entities
+            .animation
+            .clip(&player_animation.clip)
+            .and_then(|clip| {
+                clip.sample(now - player_animation.started)
+                    .map(|(index, t)| (clip, index, t))
+            });
+
+        let (pose, asset) = match sampled {
+            None => (PartPose::default(), part.default_asset.clone()),
+            Some((clip, index, t)) => (
+                clip.pose(&part.part_id, index, t),
+                clip.frame[index]
+                    .asset(&part.part_id, part.player_id)
+                    .unwrap_or(&part.default_asset)
+                    .clone(),
+            ),
+        };
+
+        part.pose = pose;
+        if asset == part.current_asset {
+            continue;
+        }
+
+        match (char_entities.asset_map.get(&asset), svg_handle, image_handle) {
+            (Some(AssetType::Svg(handle)), Some(mut svg_handle), _) => {
+                *svg_handle = handle.clone();
+            }
+            (Some(AssetType::Image(handle)), _, Some(mut image_handle)) => {
+                *image_handle = handle.clone();
+            }
+            (None, _, _) => warn!("No asset {:#?} for part {:#?}", asset, part.part_id),
+            _ => warn!(
+                "Asset {:#?} isnt the same kind of asset as part {:#?} started with so it cant be swapped to",
+                asset, part.part_id
+            ),
+        }
+
+        // Even when it couldnt be swapped, so the warning only comes up once
+        part.current_asset = asset;
+    }
+}
@@ -263,6 +263,29 @@ impl Default for Asset {
     }
 }
 
+impl Asset {
+    // Icons either name one of the pack's images or are a path from the game folder like char/Euclid/icon.png,
+    // those paths get moved onto the base path so they still work when the pack is zipped
+    pub fn icon_path(&self, icon: &str, base_path: &PathBuf) -> PathBuf {
+        if let Some(image) = self.image.iter().find(|image| image.name == icon) {
+            return base_path.join(&image.path);
+        }
+
+        let pack_folder = match (
+            base_path.parent().and_then(|zip| zip.parent()),
+            base_path.file_name(),
+        ) {
+            (Some(folder), Some(pack_name)) => folder.join(pack_name),
+            _ => return PathBuf::from(icon),
+        };
+
+        match PathBuf::from(icon).strip_prefix(&pack_folder) {
+            Ok(inner_path) if base_path != &pack_folder => base_path.join(inner_path),
+            _ => PathBuf::from(icon),
+        }
+    }
+}
+
 // Gets all the assets for the selected characters and maps to prepare them for use later on
 pub fn retrieve_asset_maps(
     mut commands: Commands,
@@ -0,0 +1,423 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use bevy::audio::AudioSink;
+use bevy::prelude::*;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::char::{AAPlayerDescriptor, CharComponentMap, PlayerIdentifier};
+use crate::combo::ComboId;
+use crate::game::*;
+
+// [[music]] in a map's main.toml, one gets picked at random each time the map is loaded.
+// bevy cant start a file partway through so loop points are done with an intro file that plays once
+// and a loop_end that cuts the looping file short and starts it over
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct MusicTrack {
+    pub path: String,
+    pub volume: f32,
+
+    // Played once before path starts looping, intro_length is how many seconds it goes on for
+    pub intro: String,
+    pub intro_length: f64,
+
+    // Seconds into path it goes back to the start on, 0 plays the whole file every time
+    pub loop_end: f64,
+}
+
+impl Default for MusicTrack {
+    fn default() -> Self {
+        Self {
+            path: String::default(),
+            volume: 1.0,
+            intro: String::default(),
+            intro_length: 0.0,
+            loop_end: 0.0,
+        }
+    }
+}
+
+// [sound] in a character's main.toml, paths are from the character's folder like assets are and empty ones are silent
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CharacterSounds {
+    pub jump: String,
+
+    // Played when any attack or combo goes off, ones with a sound of their own play that instead
+    pub attack: String,
+
+    // Played when one of the character's projectiles hits someone
+    pub hit: String,
+
+    pub death: String,
+    pub volume: f32,
+
+    #[serde(skip)]
+    pub base_path: PathBuf,
+}
+
+impl Default for CharacterSounds {
+    fn default() -> Self {
+        Self {
+            jump: String::default(),
+            attack: String::default(),
+            hit: String::default(),
+            death: String::default(),
+            volume: 1.0,
+            base_path: PathBuf::default(),
+        }
+    }
+}
+
+impl CharacterSounds {
+    pub fn path(&self, sound: &str) -> Option<PathBuf> {
+        match sound.is_empty() {
+            true => None,
+            false => Some(self.base_path.join(sound)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CharacterSound {
+    Jump,
+    Attack(ComboId),
+    Hit,
+    Death,
+}
+
+pub struct CharacterSoundEvent {
+    pub player_id: u64,
+    pub sound: CharacterSound,
+}
+
+// The map's music, it carries on through every round until the map changes or its back to the main menu
+#[derive(Default)]
+pub struct MusicPlayer {
+    pub map: String,
+    pub track: Option<MusicTrack>,
+    pub base_path: PathBuf,
+    pub sink: Option<Handle<AudioSink>>,
+    pub in_intro: bool,
+
+    // Real seconds the intro or loop started on, the game clock stops and starts too much for music
+    pub started: f64,
+}
+
+impl MusicPlayer {
+    fn stop(&mut self, audio_sinks: &Assets<AudioSink>) {
+        // Dropping the handle lets a sink carry on by itself so it has to be paused first
+        if let Some(sink) = self.sink.take().and_then(|sink| audio_sinks.get(sink)) {
+            sink.pause();
+        }
+    }
+
+    fn play_part(
+        &mut self,
+        audio: &Audio,
+        audio_sinks: &Assets<AudioSink>,
+        asset_server: &AssetServer,
+        settings: &GameSettings,
+        intro: bool,
+        now: f64,
+    ) {
+        self.stop(audio_sinks);
+
+        let track = match &self.track {
+            Some(track) => track,
+            None => return,
+        };
+
+        let (path, playback) = match intro {
+            true => (&track.intro, PlaybackSettings::ONCE),
+            false if track.loop_end > 0.0 => (&track.path, PlaybackSettings::ONCE),
+            false => (&track.path, PlaybackSettings::LOOP),
+        };
+
+        let source = asset_server.load(self.base_path.join(path));
+        let playback = playback.with_volume(music_volume(settings, track));
+        self.sink = Some(audio_sinks.get_handle(audio.play_with_settings(source, playback)));
+        self.in_intro = intro;
+        self.started = now;
+    }
+}
+
+pub fn with_master_volume(settings: &GameSettings, volume: f32) -> f32 {
+    settings.audio.master_volume * volume
+}
+
+pub fn music_volume(settings: &GameSettings, track: &MusicTrack) -> f32 {
+    with_master_volume(settings, settings.audio.music_volume * track.volume)
+}
+
+pub fn character_sound_volume(settings: &GameSettings, sounds: &CharacterSounds) -> f32 {
+    with_master_volume(settings, settings.audio.sfx_volume * sounds.volume)
+}
+
+pub fn play_sound(audio: &Audio, asset_server: &AssetServer, path: impl AsRef<Path>, volume: f32) {
+    let source = asset_server.load(path.as_ref());
+    audio.play_with_settings(source, PlaybackSettings::ONCE.with_volume(volume));
+}
+
+// Menu sounds are relative to the game folder, empty ones are silent
+pub fn play_ui_sound(
+    audio: &Option<Res<Audio>>,
+    asset_server: &AssetServer,
+    settings: &GameSettings,
+    path: &str,
+) {
+    if let (Some(audio), false) = (audio, path.is_empty()) {
+        play_sound(
+            audio,
+            asset_server,
+            path,
+            with_master_volume(settings, settings.audio.sfx_volume),
+        );
+    }
+}
+
+pub fn start_map_music(
+    game: Res<Game>,
+    time: Res<Time>,
+    settings: Res<GameSettings>,
+    audio: Option<Res<Audio>>,
+    audio_sinks: Option<Res<Assets<AudioSink>>>,
+    asset_server: Res<AssetServer>,
+    mut music: ResMut<MusicPlayer>,
+) {
+    let (audio, audio_sinks) = match (audio, audio_sinks) {
+        (Some(audio), Some(audio_sinks)) => (audio, audio_sinks),
+        _ => return,
+    };
+
+    let map = &game.selected_map;
+    if music.map == map.info.display_name && music.sink.is_some() {
+        return;
+    }
+
+    music.stop(&audio_sinks);
+    music.map = map.info.display_name.clone();
+    music.base_path = map.base_path.clone();
+    music.track = map
+        .music
+        .iter()
+        .filter(|track| !track.path.is_empty())
+        .collect::<Vec<_>>()
+        .choose(&mut rand::thread_rng())
+        .map(|track| (*track).clone());
+
+    let intro = match &music.track {
+        Some(track) => !track.intro.is_empty(),
+        None => return,
+    };
+    music.play_part(
+        &audio,
+        &audio_sinks,
+        &asset_server,
+        &settings,
+        intro,
+        time.seconds_since_startup(),
+    );
+}
+
+pub fn stop_music(audio_sinks: Option<Res<Assets<AudioSink>>>, mut music: ResMut<MusicPlayer>) {
+    if let Some(audio_sinks) = audio_sinks {
+        music.stop(&audio_sinks);
+    }
+    music.map = String::default();
+    music.track = None;
+}
+
+// Moves from the intro onto the loop, restarts the loop at loop_end and keeps the volume in line with the settings
+pub fn music_update(
+    time: Res<Time>,
+    settings: Res<GameSettings>,
+    audio: Option<Res<Audio>>,
+    audio_sinks: Option<Res<Assets<AudioSink>>>,
+    asset_server: Res<AssetServer>,
+    mut music: ResMut<MusicPlayer>,
+) {
+    let (audio, audio_sinks) = match (audio, audio_sinks) {
+        (Some(audio), Some(audio_sinks)) => (audio, audio_sinks),
+        _ => return,
+    };
+
+    let track = match &music.track {
+        Some(track) => track.clone(),
+        None => return,
+    };
+
+    let now = time.seconds_since_startup();
+    let elapsed = now - music.started;
+    let part_over = match music.in_intro {
+        true => elapsed >= track.intro_length,
+        false => track.loop_end > 0.0 && elapsed >= track.loop_end,
+    };
+
+    if part_over {
+        music.play_part(&audio, &audio_sinks, &asset_server, &settings, false, now);
+    }
+
+    // The sink only shows up once the file has loaded and started playing
+    if let Some(sink) = music.sink.as_ref().and_then(|sink| audio_sinks.get(sink)) {
+        sink.set_volume(music_volume(&settings, &track));
+    }
+}
+
+// Jumps and attacks are picked up from the descriptor changing, so rollbacks resimulating them dont play them twice
+pub fn character_sound_triggers(
+    player_query: Query<(Entity, &PlayerIdentifier, &AAPlayerDescriptor)>,
+    mut last_seen: Local<HashMap<Entity, (u32, Option<(ComboId, u64)>)>>,
+    mut sound_events: EventWriter<CharacterSoundEvent>,
+) {
+    let mut seen = HashMap::new();
+
+    for (entity, player_id, descriptor) in player_query.iter() {
+        let current = (descriptor.jumps_made, descriptor.last_attack);
+
+        if let Some((jumps_made, last_attack)) = last_seen.get(&entity) {
+            if current.0 > *jumps_made {
+                sound_events.send(CharacterSoundEvent {
+                    player_id: player_id.player_id,
+                    sound: CharacterSound::Jump,
+                });
+            }
+
+            if let Some((combo_id, _)) = current.1.filter(|attack| Some(*attack) != *last_attack) {
+                sound_events.send(CharacterSoundEvent {
+                    player_id: player_id.player_id,
+                    sound: CharacterSound::Attack(combo_id),
+                });
+            }
+        }
+
+        seen.insert(entity, current);
+    }
+
+    *last_seen = seen;
+}
+
+// Runs every frame rather than with the game so the last death of a round still gets heard
+pub fn play_character_sounds(
+    settings: Res<GameSettings>,
+    audio: Option<Res<Audio>>,
+    asset_server: Res<AssetServer>,
+    char_map: Option<Res<CharComponentMap>>,
+    mut sound_events: EventReader<CharacterSoundEvent>,
+) {
+    let (audio, char_map) = match (audio, char_map) {
+        (Some(audio), Some(char_map)) => (audio, char_map),
+        _ => return,
+    };
+
+    for sound_event in sound_events.iter() {
+        let char_entities = match char_map.get(&sound_event.player_id) {
+            Some(char_entities) => char_entities,
+            None => continue,
+        };
+        let sounds = &char_entities.sound;
+
+        let path = match sound_event.sound {
+            CharacterSound::Jump => sounds.path(&sounds.jump),
+            CharacterSound::Attack(combo_id) => {
+                let combo_sound = char_entities
+                    .combos
+                    .combos
+                    .get(combo_id)
+                    .and_then(|combo| sounds.path(&combo.sound));
+                combo_sound.or_else(|| sounds.path(&sounds.attack))
+            }
+            CharacterSound::Hit => sounds.path(&sounds.hit),
+            CharacterSound::Death => sounds.path(&sounds.death),
+        };
+
+        if let Some(path) = path {
+            play_sound(
+                &audio,
+                &asset_server,
+                path,
+                character_sound_volume(&settings, sounds),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bevy::asset::AssetPlugin;
+    use bevy::audio::AudioSource;
+
+    fn audio_settings(master: f32, music: f32, sfx: f32) -> GameSettings {
+        let mut settings = GameSettings::default();
+        settings.audio.master_volume = master;
+        settings.audio.music_volume = music;
+        settings.audio.sfx_volume = sfx;
+        settings
+    }
+
+    #[test]
+    fn volumes_scale_with_master() {
+        let settings = audio_settings(0.5, 0.8, 0.4);
+        assert_eq!(with_master_volume(&settings, 1.0), 0.5);
+
+        let track = MusicTrack {
+            volume: 0.5,
+            ..Default::default()
+        };
+        assert!((music_volume(&settings, &track) - 0.2).abs() < 1e-6);
+
+        let sounds = CharacterSounds {
+            volume: 0.5,
+            ..Default::default()
+        };
+        assert!((character_sound_volume(&settings, &sounds) - 0.1).abs() < 1e-6);
+
+        // Turning the master down to 0 mutes everything else with it
+        let muted = audio_settings(0.0, 1.0, 1.0);
+        assert_eq!(character_sound_volume(&muted, &sounds), 0.0);
+        assert_eq!(music_volume(&muted, &track), 0.0);
+    }
+
+    #[test]
+    fn intro_moves_onto_loop() {
+        // Stands in for the audio plugin, there's no output device to play anything on in tests
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin)
+            .add_asset::<AudioSource>()
+            .add_asset::<AudioSink>()
+            .init_resource::<Audio>()
+            .insert_resource(GameSettings::default())
+            .add_system(music_update);
+
+        let track = MusicTrack {
+            path: "loop.ogg".to_string(),
+            intro: "intro.ogg".to_string(),
+            intro_length: 4.0,
+            ..Default::default()
+        };
+        app.insert_resource(MusicPlayer {
+            track: Some(track),
+            in_intro: true,
+            // The app has only been running for a moment, so the intro still has about a second left
+            started: -3.0,
+            ..Default::default()
+        });
+
+        app.update();
+        assert!(app.world.resource::<MusicPlayer>().in_intro);
+        assert!(app.world.resource::<MusicPlayer>().sink.is_none());
+
+        app.world.resource_mut::<MusicPlayer>().started = -4.0;
+        app.update();
+
+        let music = app.world.resource::<MusicPlayer>();
+        assert!(!music.in_intro);
+        assert!(music.sink.is_some());
+        assert!(music.started >= 0.0);
+    }
+}
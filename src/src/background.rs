@@ -6,19 +6,140 @@ use bevy_svg::prelude::{Origin, Svg, Svg2dBundle};
 use num;
 use winit::dpi::PhysicalSize;
 
-use crate::assets::AssetType;
+use serde::{Deserialize, Serialize};
+
+use crate::assets::{get_asset, AssetMap, AssetType};
+use crate::camera::MatchCamera;
 use crate::draw::*;
+use crate::game::GameClock;
 use crate::{MapComponent, UpdatedTransformComponent};
 
 #[derive(Component)]
 pub struct Background {
     pub processed: bool,
+
+    // What svgs get stretched to once they've loaded
+    pub size: Vec2,
+}
+
+// How a background layer moves, the main background and every [[background.layer]] have one
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct ParallaxMotion {
+    // How much it moves when the camera does compared to the map. 1 moves with the map, 0 stays put on the screen,
+    // in between looks further away and over 1 looks closer
+    pub parallax: [f32; 2],
+
+    // Drift per second in the map's base dimensions, for things like clouds
+    pub scroll_speed: [f32; 2],
+
+    // The z it's drawn at, the same as a sprite's origin z
+    pub depth: f32,
+}
+
+impl Default for ParallaxMotion {
+    fn default() -> Self {
+        Self {
+            parallax: [1.0, 1.0],
+            scroll_speed: [0.0, 0.0],
+            depth: 0.0,
+        }
+    }
+}
+
+// [[background.layer]] in a map's main.toml, drawn on top of or under the main background depending on its depth
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ParallaxLayer {
+    pub asset: String,
+
+    // One tile in the map's base dimensions, left out it's the size of the window
+    pub size: Option<[f32; 2]>,
+
+    // Where the middle of the first tile starts off, in the map's base dimensions from the centre
+    pub offset: [f32; 2],
+
+    // Tiles forever in that direction so the edge never shows
+    pub repeat_x: bool,
+    pub repeat_y: bool,
+
+    #[serde(flatten)]
+    pub motion: ParallaxMotion,
+}
+
+impl Default for ParallaxLayer {
+    fn default() -> Self {
+        Self {
+            asset: String::default(),
+            size: None,
+            offset: [0.0, 0.0],
+            repeat_x: true,
+            repeat_y: false,
+            motion: ParallaxMotion::default(),
+        }
+    }
+}
+
+// Every background tile has one, in the world's units rather than the map's base dimensions
+#[derive(Component, Debug, Clone)]
+pub struct ParallaxTile {
+    pub parallax: Vec2,
+    pub scroll_speed: Vec2,
+
+    // Where the index 0 tile sits with the camera in the middle and nothing scrolled yet
+    pub origin: Vec2,
+    pub tile_size: Vec2,
+
+    // Which tile this is counting out from the one nearest the camera, or from origin when it doesnt repeat
+    pub index: IVec2,
+    pub repeat: BVec2,
+
+    // Clock seconds the map was loaded on, scrolling counts from here so every load starts off the same
+    pub spawned: f64,
+}
+
+impl ParallaxTile {
+    pub fn new(
+        motion: &ParallaxMotion,
+        rescale: f32,
+        origin: Vec2,
+        tile_size: Vec2,
+        index: IVec2,
+        repeat: BVec2,
+        spawned: f64,
+    ) -> Self {
+        Self {
+            parallax: Vec2::from(motion.parallax),
+            scroll_speed: Vec2::from(motion.scroll_speed) / rescale,
+            origin,
+            tile_size,
+            index,
+            repeat,
+            spawned,
+        }
+    }
+
+    pub fn position(&self, camera: Vec2, seconds: f32) -> Vec2 {
+        let pattern_origin =
+            self.origin + camera * (Vec2::ONE - self.parallax) + self.scroll_speed * seconds;
+
+        // Repeating tiles jump a whole tile at a time to stay around the camera, so it looks like one endless layer
+        let nearest = ((camera - pattern_origin) / self.tile_size).round();
+        let start = Vec2::select(
+            self.repeat,
+            pattern_origin + nearest * self.tile_size,
+            pattern_origin,
+        );
+
+        start + self.index.as_vec2() * self.tile_size
+    }
 }
 
 pub struct BackgroundResolution {
     pub resolution: Vec2,
 }
 
+// zoom_out is the furthest the camera can zoom out, there has to be enough tiles to cover it
 pub fn create_bgs(
     commands: &mut Commands,
     mut resolution: Vec2,
@@ -27,6 +148,10 @@ pub fn create_bgs(
     texture_over: Vec<AssetType>,
     texture_under: Vec<AssetType>,
     svg_assets: Res<Assets<Svg>>,
+    motion: &ParallaxMotion,
+    rescale: f32,
+    zoom_out: f32,
+    spawned: f64,
 ) {
     let background_sprite = Sprite {
         custom_size: Some(resolution),
@@ -35,10 +160,11 @@ pub fn create_bgs(
 
     commands.insert_resource(BackgroundResolution { resolution });
 
-    let size = winit_info.screen_dim;
+    let size = winit_info.screen_dim * zoom_out;
 
-    let ratio_x = ((size.x as f32) / resolution.x).ceil();
-    let ratio_y = ((size.y as f32) / resolution.y).ceil();
+    // One more each way so moving it around never shows an edge
+    let ratio_x = ((size.x as f32) / resolution.x).ceil() + 1.0;
+    let ratio_y = ((size.y as f32) / resolution.y).ceil() + 1.0;
 
     spawn_bg(
         commands,
@@ -50,6 +176,9 @@ pub fn create_bgs(
         ratio_y as u32,
         resolution,
         svg_assets,
+        motion,
+        rescale,
+        spawned,
     );
 }
 
@@ -63,7 +192,13 @@ pub fn spawn_bg(
     amount_spawned_y: u32,
     resolution: Vec2,
     svg_assets: Res<Assets<Svg>>,
+    motion: &ParallaxMotion,
+    rescale: f32,
+    spawned: f64,
 ) {
+    // Columns overlap by a pixel so there's no gap between them
+    let tile_size = Vec2::new(resolution.x - 1.0, resolution.y);
+
     for iteration_x in 0..amount_spawned_x {
         let mut transforms = calculate_transforms_x(iteration_x, resolution, texture.clone());
         let mut total_transforms = vec![];
@@ -82,30 +217,134 @@ pub fn spawn_bg(
         total_transforms.extend(transforms);
 
         for (transform, asset) in total_transforms {
-            match asset {
-                AssetType::Image(image_asset) => commands
-                    .spawn_bundle(SpriteBundle {
-                        transform: Transform::from_translation(transform),
-                        sprite: sprite.clone(),
-                        texture: image_asset,
-                        ..Default::default()
-                    })
-                    .insert(MapComponent)
-                    .insert(Background { processed: true }),
-                AssetType::Svg(svg_asset) => commands
-                    .spawn_bundle(Svg2dBundle {
-                        transform: Transform::from_translation(transform),
-                        svg: svg_asset,
-                        origin: Origin::Center,
-                        ..Default::default()
-                    })
-                    .insert(MapComponent)
-                    .insert(Background { processed: false }),
-            };
+            // The rows above and below are all different so only the columns repeat
+            let index = (transform.truncate() / tile_size).round().as_ivec2();
+            let parallax_tile = ParallaxTile::new(
+                motion,
+                rescale,
+                Vec2::ZERO,
+                tile_size,
+                index,
+                BVec2::new(true, false),
+                spawned,
+            );
+
+            spawn_bg_tile(
+                commands,
+                sprite.clone(),
+                asset,
+                transform.truncate().extend(motion.depth),
+                resolution,
+                parallax_tile,
+            );
         }
     }
 }
 
+pub fn spawn_bg_tile(
+    commands: &mut Commands,
+    sprite: Sprite,
+    asset: AssetType,
+    translation: Vec3,
+    size: Vec2,
+    parallax_tile: ParallaxTile,
+) {
+    match asset {
+        AssetType::Image(image_asset) => commands
+            .spawn_bundle(SpriteBundle {
+                transform: Transform::from_translation(translation),
+                sprite,
+                texture: image_asset,
+                ..Default::default()
+            })
+            .insert(MapComponent)
+            .insert(parallax_tile)
+            .insert(Background {
+                processed: true,
+                size,
+            }),
+        AssetType::Svg(svg_asset) => commands
+            .spawn_bundle(Svg2dBundle {
+                transform: Transform::from_translation(translation),
+                svg: svg_asset,
+                origin: Origin::Center,
+                ..Default::default()
+            })
+            .insert(MapComponent)
+            .insert(parallax_tile)
+            .insert(Background {
+                processed: false,
+                size,
+            }),
+    };
+}
+
+// Enough tiles of each layer to cover the view however far the camera zooms out
+pub fn spawn_parallax_layers(
+    commands: &mut Commands,
+    layers: &[ParallaxLayer],
+    asset_map: &AssetMap,
+    base_dimensions: Vec2,
+    rescale: f32,
+    view_size: Vec2,
+    spawned: f64,
+) {
+    for layer in layers {
+        let tile_size = layer.size.map(Vec2::from).unwrap_or(base_dimensions) / rescale;
+        let origin = Vec2::from(layer.offset) / rescale;
+        let repeat = BVec2::new(layer.repeat_x, layer.repeat_y);
+
+        let tiles_each_way = (view_size / tile_size / 2.0).ceil().as_ivec2() + IVec2::ONE;
+        let tiles_each_way = IVec2::select(repeat, tiles_each_way, IVec2::ZERO);
+
+        let asset = get_asset(&layer.asset, asset_map);
+        let sprite = Sprite {
+            custom_size: Some(tile_size),
+            ..Default::default()
+        };
+
+        for x in -tiles_each_way.x..=tiles_each_way.x {
+            for y in -tiles_each_way.y..=tiles_each_way.y {
+                let index = IVec2::new(x, y);
+                let parallax_tile = ParallaxTile::new(
+                    &layer.motion,
+                    rescale,
+                    origin,
+                    tile_size,
+                    index,
+                    repeat,
+                    spawned,
+                );
+                let translation = parallax_tile
+                    .position(Vec2::ZERO, 0.0)
+                    .extend(layer.motion.depth);
+
+                spawn_bg_tile(
+                    commands,
+                    sprite.clone(),
+                    asset.clone(),
+                    translation,
+                    tile_size,
+                    parallax_tile,
+                );
+            }
+        }
+    }
+}
+
+pub fn parallax_scroll(
+    clock: Res<GameClock>,
+    match_camera: Res<MatchCamera>,
+    mut tile_query: Query<(&ParallaxTile, &mut Transform)>,
+) {
+    for (parallax_tile, mut transform) in tile_query.iter_mut() {
+        let seconds = (clock.seconds() - parallax_tile.spawned) as f32;
+        let position = parallax_tile.position(match_camera.centre, seconds);
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
+    }
+}
+
 pub fn calculate_transforms_x(
     iter_x: u32,
     resolution: Vec2,
@@ -155,7 +394,6 @@ pub fn calculate_transforms_y(
 
 pub fn modify_svg_background_transform(
     mut query_background: Query<(&mut Background, &mut Transform, &Handle<Svg>)>,
-    resolution: Res<BackgroundResolution>,
     svg_assets: Res<Assets<Svg>>,
 ) {
     for (mut background, mut transform, svg_handle) in query_background.iter_mut() {
@@ -164,11 +402,12 @@ pub fn modify_svg_background_transform(
             if svg_asset.is_some() {
                 let asset = svg_asset.unwrap();
 
-                let size_y = resolution.resolution.y / asset.size.y;
-                let size_x = resolution.resolution.x / asset.size.x;
+                let size_y = background.size.y / asset.size.y;
+                let size_x = background.size.x / asset.size.x;
 
-                transform.translation.z = 0.0;
+                // z is left alone since it's the layer's depth
                 transform.scale = Vec3::new(size_x, size_y, 1.);
+                background.processed = true;
             }
         }
     }
@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::assets::AugmentedFonts;
+use crate::audio::{CharacterSound, CharacterSoundEvent};
+use crate::char::{ConvertToRgb, PlayerHealth, PlayerIdentifier, PlayerStocks};
+use crate::draw::get_resolution;
+use crate::game::*;
+use crate::{AppStates, MainCamera};
+
+// [camera] in a map's main.toml
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct MapCamera {
+    // Left, bottom, right and top edges the camera cant see past, in the map's base dimensions out from the centre.
+    // Left out it's the window the map was made for, so the camera can zoom in but never out past it
+    pub bounds: Option<[f32; 4]>,
+}
+
+impl MapCamera {
+    // The bottom left and top right corners in the world, which is measured in window pixels
+    pub fn world_bounds(&self, base_dimensions: Vec2, resolution: Vec2) -> (Vec2, Vec2) {
+        match self.bounds {
+            None => (-resolution / 2.0, resolution / 2.0),
+            Some([left, bottom, right, top]) => {
+                let ratio = base_dimensions.y / resolution.y;
+                (
+                    Vec2::new(left, bottom) / ratio,
+                    Vec2::new(right, top) / ratio,
+                )
+            }
+        }
+    }
+}
+
+// Where the camera has got to, the shake goes on top of this so it doesnt get smoothed away
+pub struct MatchCamera {
+    pub centre: Vec2,
+    pub zoom: f32,
+    pub shake: f32,
+}
+
+impl Default for MatchCamera {
+    fn default() -> Self {
+        Self {
+            centre: Vec2::ZERO,
+            zoom: 1.0,
+            shake: 0.0,
+        }
+    }
+}
+
+// Shown at the edge of the view while a player is off screen
+#[derive(Component)]
+pub struct OffscreenIndicator {
+    pub player_id: u64,
+}
+
+// The centre and zoom that fits everyone in, None when there's nobody left to fit
+fn frame_players(
+    positions: impl Iterator<Item = Vec2>,
+    camera_settings: &CameraSettings,
+    bounds: (Vec2, Vec2),
+    resolution: Vec2,
+) -> Option<(Vec2, f32)> {
+    let (min, max) = positions.fold(None, |extents: Option<(Vec2, Vec2)>, position| {
+        Some(match extents {
+            None => (position, position),
+            Some((min, max)) => (min.min(position), max.max(position)),
+        })
+    })?;
+
+    let padding = Vec2::splat(camera_settings.padding * resolution.y);
+    let (min, max) = (min - padding, max + padding);
+
+    let zoom = ((max - min) / resolution)
+        .max_element()
+        .max(camera_settings.min_zoom)
+        .min(camera_settings.max_zoom)
+        .min(((bounds.1 - bounds.0) / resolution).min_element());
+
+    let centre = clamp_to_bounds((min + max) / 2.0, resolution / 2.0 * zoom, bounds);
+    Some((centre, zoom))
+}
+
+fn clamp_to_bounds(centre: Vec2, half_view: Vec2, (bounds_min, bounds_max): (Vec2, Vec2)) -> Vec2 {
+    let low = bounds_min + half_view;
+    let high = bounds_max - half_view;
+
+    // A view bigger than the bounds just sits in the middle of them
+    let clamp_axis = |value: f32, low: f32, high: f32| match low <= high {
+        true => value.clamp(low, high),
+        false => (low + high) / 2.0,
+    };
+
+    Vec2::new(
+        clamp_axis(centre.x, low.x, high.x),
+        clamp_axis(centre.y, low.y, high.y),
+    )
+}
+
+pub fn update_match_camera(
+    time: Res<Time>,
+    state: Res<State<AppStates>>,
+    // Only there once the first match has been set up
+    game: Option<Res<Game>>,
+    settings: Res<GameSettings>,
+    windows: Res<Windows>,
+    window_descriptor: Res<WindowDescriptor>,
+    mut match_camera: ResMut<MatchCamera>,
+    mut sound_events: EventReader<CharacterSoundEvent>,
+    player_query: Query<&Transform, (With<PlayerIdentifier>, Without<MainCamera>)>,
+    mut camera_query: Query<(&mut Transform, &mut OrthographicProjection), With<MainCamera>>,
+) {
+    let (mut camera_transform, mut projection) = match camera_query.get_single_mut() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+
+    let camera_settings = &settings.camera;
+    let resolution = get_resolution(windows, &window_descriptor);
+    let delta = time.delta_seconds();
+
+    // Hits and deaths already go out as events for their sounds
+    for sound_event in sound_events.iter() {
+        match_camera.shake += match sound_event.sound {
+            CharacterSound::Hit => camera_settings.hit_shake,
+            CharacterSound::Death => camera_settings.death_shake,
+            _ => 0.0,
+        };
+    }
+    match_camera.shake =
+        (match_camera.shake.min(1.0) - camera_settings.shake_decay * delta).max(0.0);
+
+    // The countdown and victory text are in the middle of the window so the camera eases back out for them,
+    // menus get it put straight back
+    let (target, smooth) = match state.current() {
+        AppStates::LoadGame if camera_settings.follow_players => {
+            let target = game.as_ref().and_then(|game| {
+                let map = &game.selected_map;
+                let bounds = map
+                    .camera
+                    .world_bounds(Vec2::from(map.info.base_dimensions), resolution);
+                let positions = player_query
+                    .iter()
+                    .map(|transform| transform.translation.truncate());
+
+                frame_players(positions, camera_settings, bounds, resolution)
+            });
+            (target, true)
+        }
+        AppStates::LoadGame | AppStates::PreGame | AppStates::Victory => (None, true),
+        _ => {
+            match_camera.shake = 0.0;
+            (None, false)
+        }
+    };
+
+    let (centre, zoom) = target.unwrap_or((Vec2::ZERO, 1.0));
+    let t = match smooth {
+        true => 1.0 - (-camera_settings.smoothing * delta).exp(),
+        false => 1.0,
+    };
+    match_camera.centre = match_camera.centre.lerp(centre, t);
+    match_camera.zoom += (zoom - match_camera.zoom) * t;
+
+    let mut rng = rand::thread_rng();
+    let shake_distance = match_camera.shake.powi(2) * camera_settings.shake_distance * resolution.y;
+    let offset = Vec2::new(rng.gen_range(-1.0..=1.0), rng.gen_range(-1.0..=1.0)) * shake_distance;
+
+    camera_transform.translation.x = match_camera.centre.x + offset.x;
+    camera_transform.translation.y = match_camera.centre.y + offset.y;
+
+    // Changing the projection makes bevy work the camera out again, so only when it actually has to
+    if projection.scale != match_camera.zoom {
+        projection.scale = match_camera.zoom;
+    }
+}
+
+pub fn offscreen_indicators(
+    mut commands: Commands,
+    state: Res<State<AppStates>>,
+    game: Option<Res<Game>>,
+    settings: Res<GameSettings>,
+    fonts: Res<AugmentedFonts>,
+    windows: Res<Windows>,
+    window_descriptor: Res<WindowDescriptor>,
+    match_camera: Res<MatchCamera>,
+    player_query: Query<
+        (
+            &PlayerIdentifier,
+            &Transform,
+            &PlayerHealth,
+            Option<&PlayerStocks>,
+        ),
+        Without<OffscreenIndicator>,
+    >,
+    mut indicator_query: Query<
+        (
+            Entity,
+            &OffscreenIndicator,
+            &mut Transform,
+            &mut Text,
+            &mut Visibility,
+        ),
+        Without<PlayerIdentifier>,
+    >,
+) {
+    let game = match game {
+        Some(game) => game,
+        None => return,
+    };
+    let in_game = *state.current() == AppStates::LoadGame;
+    let resolution = get_resolution(windows, &window_descriptor);
+
+    // Goes off where the camera is without the shake, otherwise the indicators would shake too
+    let half_view = resolution / 2.0 * match_camera.zoom;
+    let view_min = match_camera.centre - half_view;
+    let view_max = match_camera.centre + half_view;
+    let margin = Vec2::splat(settings.camera.indicator_margin * resolution.y * match_camera.zoom);
+
+    let mut offscreen = HashMap::new();
+    if in_game {
+        for (player_id, transform, health, stocks) in player_query.iter() {
+            let position = transform.translation.truncate();
+            if position.cmpge(view_min).all() && position.cmple(view_max).all() {
+                continue;
+            }
+
+            // Percentage mode has nothing to drain so it shows the percentage
+            let status = match stocks {
+                Some(stocks) => format!("{:.0}%", stocks.percentage),
+                None => format!("{:.0}", health.current_health.max(0.0)),
+            };
+
+            offscreen.insert(
+                player_id.player_id,
+                (
+                    position.clamp(view_min + margin, view_max - margin),
+                    format!("P{}\n{}", player_id.player_id, status),
+                ),
+            );
+        }
+    }
+
+    for (entity, indicator, mut transform, mut text, mut visibility) in indicator_query.iter_mut() {
+        match offscreen.remove(&indicator.player_id) {
+            Some((position, value)) => {
+                transform.translation = position.extend(998.0);
+                transform.scale = Vec3::splat(match_camera.zoom);
+                text.sections[0].value = value;
+                visibility.is_visible = true;
+            }
+            None if in_game => visibility.is_visible = false,
+            // The round is over so they'll be made again next time someone goes off screen
+            None => commands.entity(entity).despawn_recursive(),
+        }
+    }
+
+    // Anyone left over has gone off screen for the first time this round
+    let text_style = TextStyle {
+        font: fonts.bold_font.clone(),
+        font_size: settings.camera.indicator_size * resolution.y,
+        color: Color::from(
+            game.selected_map
+                .char_element_colours
+                .player_id_text
+                .convert_to_rgb(),
+        ),
+    };
+    let text_alignment = TextAlignment {
+        vertical: VerticalAlign::Center,
+        horizontal: HorizontalAlign::Center,
+    };
+
+    for (player_id, (position, value)) in offscreen {
+        commands
+            .spawn_bundle(Text2dBundle {
+                text: Text::with_section(value, text_style.clone(), text_alignment.clone()),
+                transform: Transform {
+                    translation: position.extend(998.0),
+                    scale: Vec3::splat(match_camera.zoom),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(OffscreenIndicator { player_id });
+    }
+}
@@ -4,8 +4,11 @@ use std::ops::{Add, Index};
 use std::path::PathBuf;
 
 use crate::action_traits::ScaleToRatio;
+use crate::animation::{AnimatedPart, CharacterAnimation, PlayerAnimation};
+use crate::audio::CharacterSounds;
 use crate::assets::*;
 use crate::collider::*;
+use crate::combo::{Combo, ComboId, ComboTrie};
 use crate::game::*;
 use crate::projectile::{
     create_projectile_map, Attack, AttackActions, AttackMap, ExecuteOptionAttack, InstanceMap,
@@ -13,6 +16,7 @@ use crate::projectile::{
     UnusedAction,
 };
 use crate::rigidbody::*;
+use crate::shield::{create_shield_indicator, PlayerShield, Shield};
 use crate::universal::*;
 use crate::{
     AppStates, AssetServer, CharacterInputIdentifierMap, CharacterInputSettings, CountDownTextNode,
@@ -53,6 +57,10 @@ use serde::{Deserialize, Serialize};
 pub struct CharComponent {
     pub asset: String,
 
+    // What animation frames call it, the core is always "core"
+    #[serde(default)]
+    pub id: String,
+
     #[serde(default)]
     pub character_assets: Vec<String>,
 
@@ -104,6 +112,18 @@ pub struct PlayerHealth {
     pub subdivisions: usize,
 }
 
+// Only given to players in percentage mode, damage goes onto the percentage instead of their health
+#[derive(Debug, Clone, Copy, Component, Default)]
+pub struct PlayerStocks {
+    pub percentage: f32,
+    pub stocks: u32,
+}
+
+#[derive(Debug, Clone, Copy, Component, Default)]
+pub struct PlayerPercentageHudId {
+    pub player_id: u64,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Component, Default)]
 pub struct AvailableAttacks {
     pub one_one: Option<Attack>,
@@ -112,7 +132,7 @@ pub struct AvailableAttacks {
     pub two_two: Option<Attack>,
 }
 
-#[derive(Debug, Clone, Component, Copy)]
+#[derive(Debug, Clone, Component, Copy, PartialEq, Eq)]
 pub enum AttackKey {
     One,
     Two,
@@ -122,6 +142,9 @@ pub enum AttackKey {
 pub struct AttackBuffer {
     pub buffer: Vec<AttackKey>,
     pub final_timestamp: f64,
+
+    // Where the keys so far have got to in the character's ComboTrie, 0 when nothing is buffered
+    pub node: usize,
 }
 
 impl Default for AttackKey {
@@ -130,19 +153,19 @@ impl Default for AttackKey {
     }
 }
 
-pub type CooldownMap = HashMap<u64, HashMap<AttackType, AttackCooldown>>;
+pub type CooldownMap = HashMap<u64, HashMap<ComboId, AttackCooldown>>;
 
 pub struct AttackInstanceDirectory {
     pub attack_instances: HashMap<u64, InstanceMap>,
     pub previous_used_id: u64,
     pub unexecuted_actions: Vec<UnusedAction>,
-    pub cooldown: HashMap<u64, HashMap<AttackType, AttackCooldown>>,
+    pub cooldown: HashMap<u64, HashMap<ComboId, AttackCooldown>>,
 }
 
 pub fn check_cooldowns(
     mut attack_instance_directory: ResMut<AttackInstanceDirectory>,
     game: Res<Game>,
-    time: Res<Time>,
+    clock: Res<GameClock>,
 ) {
     for player in game.selected_characters.keys() {
         let cooldowns = attack_instance_directory
@@ -150,7 +173,7 @@ pub fn check_cooldowns(
             .entry(*player)
             .or_insert(HashMap::new());
         cooldowns.retain(|attack_type, cooldown| {
-            if cooldown.cooldown_duration - (time.seconds_since_startup() - cooldown.cooldown_start)
+            if cooldown.cooldown_duration - (clock.gameplay_seconds() - cooldown.cooldown_start)
                 <= 0.0
             {
                 false
@@ -168,7 +191,7 @@ pub fn execute_attack<T: ExecuteOptionAttack>(
     transform: &mut Transform,
     commands: &mut Commands,
     attack_directory: &mut ResMut<AttackInstanceDirectory>,
-    time: &Res<Time>,
+    clock: &Res<GameClock>,
 ) {
     attack.execute_attack(
         char_entities,
@@ -176,47 +199,29 @@ pub fn execute_attack<T: ExecuteOptionAttack>(
         transform,
         commands,
         attack_directory,
-        time,
+        clock,
     )
 }
 
+#[derive(Debug, Clone)]
 pub struct AttackCooldown {
     pub cooldown_start: f64,
     pub cooldown_duration: f64,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
-pub enum AttackType {
-    OneOne,
-    TwoOne,
-    OneTwo,
-    TwoTwo,
-}
-
 impl CharEntities {
     pub fn apply_attack(
         &mut self,
-        buffer: [AttackKey; 2],
+        combo_id: ComboId,
         mut player_descriptor: &mut AAPlayerDescriptor,
         transform: &mut Transform,
         commands: &mut Commands,
         attack_directory: &mut ResMut<AttackInstanceDirectory>,
-        time: &Res<Time>,
+        clock: &Res<GameClock>,
     ) {
-        let (attack, attack_type) = match buffer {
-            [AttackKey::One, AttackKey::One] => {
-                (&self.attacks.one_one, AttackType::OneOne)
-            }
-            [AttackKey::One,AttackKey::Two] => {
-                (&self.attacks.one_two, AttackType::OneTwo)
-            }
-            [ AttackKey::Two, AttackKey::One] => {
-                (&self.attacks.two_one, AttackType::TwoOne)
-            }
-            [AttackKey::Two,  AttackKey::Two] => {
-                (&self.attacks.two_two, AttackType::TwoTwo)
-            }
-            _ => return,
+        let attack = match self.combos.combos.get(combo_id) {
+            None => return,
+            Some(combo) => combo.attack(),
         };
 
         let cooldown_player_id = attack_directory
@@ -224,99 +229,115 @@ impl CharEntities {
             .entry(self.player_id)
             .or_insert(HashMap::new());
 
-        match cooldown_player_id.get(&attack_type) {
-            None => {
-                match attack.clone() {
-                    None => {}
-                    Some(attack) => {
-                        //Start the cooldown for the attack we just executed (uh oh)
-                        cooldown_player_id.insert(
-                            attack_type,
-                            AttackCooldown {
-                                cooldown_start: time.seconds_since_startup(),
-                                cooldown_duration: attack.cooldown,
-                            },
-                        );
-                    }
-                }
-
-                execute_attack(
-                    attack.clone(),
-                    self,
-                    player_descriptor,
-                    transform,
-                    commands,
-                    attack_directory,
-                    time,
-                );
-            }
-            _ => return,
+        if cooldown_player_id.contains_key(&combo_id) {
+            return;
         }
+
+        //Start the cooldown for the attack we just executed (uh oh)
+        cooldown_player_id.insert(
+            combo_id,
+            AttackCooldown {
+                cooldown_start: clock.gameplay_seconds(),
+                cooldown_duration: attack.cooldown,
+            },
+        );
+        player_descriptor.last_attack = Some((combo_id, clock.gameplay_frame));
+
+        execute_attack(
+            Some(attack),
+            self,
+            player_descriptor,
+            transform,
+            commands,
+            attack_directory,
+            clock,
+        );
     }
 }
 
 impl AttackBuffer {
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.final_timestamp = 0.0;
+        self.node = 0;
+    }
+
+    // Follows the pressed key through the combo trie, returns the combo to fire if there is one
     pub fn add_to_buffer(
         &mut self,
         input_type: InputKeyboardType,
         attack_key: InputPurpose,
-        time: &Res<Time>,
-    ) {
-        if input_type == InputKeyboardType::JustPressed {
-            match attack_key {
-                InputPurpose::Atk1 => {
-                    self.buffer.push(AttackKey::One);
-                }
-                InputPurpose::Atk2 => {
-                    self.buffer.push(AttackKey::Two);
-                }
-                InputPurpose::Reset => {
-                    self.buffer.clear();
-                    self.final_timestamp = 0.0;
-                    return;
+        facing: DirectionFacingFlags,
+        combos: &ComboTrie,
+        clock: &Res<GameClock>,
+    ) -> Option<ComboId> {
+        if input_type != InputKeyboardType::JustPressed {
+            return None;
+        }
+
+        let key = match attack_key {
+            InputPurpose::Atk1 => AttackKey::One,
+            InputPurpose::Atk2 => AttackKey::Two,
+            InputPurpose::Reset => {
+                self.reset();
+                return None;
+            }
+            _ => return None,
+        };
+
+        let elapsed = clock.gameplay_seconds() - self.final_timestamp;
+        let mut fired = None;
+
+        let next_node = match combos.follow(self.node, key, facing, elapsed) {
+            Some(next_node) => next_node,
+            None => {
+                // Nothing longer goes this way, so whatever was buffered fires and the key starts a new combo
+                fired = combos.nodes[self.node].combo;
+                self.reset();
+
+                match combos.follow(0, key, facing, elapsed) {
+                    None => return fired,
+                    Some(next_node) => next_node,
                 }
-                _ => return,
-            };
+            }
+        };
+
+        self.buffer.push(key);
+        self.final_timestamp = clock.gameplay_seconds();
+        self.node = next_node;
 
-            self.final_timestamp = time.seconds_since_startup();
+        // Nothing can come after this so there's no point waiting, unless something already fired this press
+        // in which case clear_expired fires it next frame
+        if combos.nodes[self.node].edges.is_empty() && fired.is_none() {
+            let combo = combos.nodes[self.node].combo;
+            self.reset();
+            return combo;
         }
+
+        fired
     }
 
-    pub fn execute_buffer(
-        &mut self,
-        mut char_entities: &mut CharEntities,
-        mut player_descriptor: &mut AAPlayerDescriptor,
-        transform: &mut Transform,
-        commands: &mut Commands,
-        attack_directory: &mut ResMut<AttackInstanceDirectory>,
-        time: &Res<Time>,
-        settings: &Res<GameSettings>,
-    ) {
-        if (self.final_timestamp + settings.gameplay_settings.attack_buffer_reset_time)
-            < time.seconds_since_startup()
-        {
-            self.buffer.clear();
-            self.final_timestamp = 0.0;
+    // Once no longer combo can be finished in time, the one the keys got to fires
+    pub fn clear_expired(&mut self, combos: &ComboTrie, clock: &Res<GameClock>) -> Option<ComboId> {
+        if self.node == 0 {
+            return None;
+        }
+
+        let node = match combos.nodes.get(self.node) {
+            None => {
+                self.reset();
+                return None;
+            }
+            Some(node) => node,
         };
 
-        if self.buffer.len() >= 2 {
-            let attack_buffer = [
-                *self.buffer.get(0).unwrap_or(&AttackKey::default()),
-                *self.buffer.get(1).unwrap_or(&AttackKey::default()),
-            ];
-
-            self.buffer.clear();
-            self.final_timestamp = 0.0;
-
-            char_entities.apply_attack(
-                attack_buffer,
-                player_descriptor,
-                transform,
-                commands,
-                attack_directory,
-                time,
-            );
+        if self.final_timestamp + node.longest_window() < clock.gameplay_seconds() {
+            let combo = node.combo;
+            self.reset();
+            return combo;
         }
+
+        None
     }
 }
 
@@ -346,6 +367,32 @@ pub struct AAPlayerDescriptor {
 
     #[serde(skip)]
     pub lock_jumps_at_max: bool,
+
+    // The gameplay frame the player can move and attack again on after getting hit
+    #[serde(skip)]
+    pub hitstun_until: u64,
+
+    // Keys let go of during hitstun, they get applied once it's over so nothing is left held down
+    #[serde(skip)]
+    pub deferred_releases: Vec<InputPurpose>,
+
+    // Keys whose press went through and havent been let go of yet, a release only does anything for these
+    #[serde(skip)]
+    pub held_keys: Vec<InputPurpose>,
+
+    // The combo last fired and the gameplay frame it was on, for its animation
+    #[serde(skip)]
+    pub last_attack: Option<(ComboId, u64)>,
+
+    // Goes up every time a jump goes through, for the jump sound
+    #[serde(skip)]
+    pub jumps_made: u32,
+}
+
+impl AAPlayerDescriptor {
+    pub fn in_hitstun(&self, clock: &GameClock) -> bool {
+        clock.gameplay_frame < self.hitstun_until
+    }
 }
 
 impl Default for AAPlayerDescriptor {
@@ -356,6 +403,11 @@ impl Default for AAPlayerDescriptor {
             char_collision_dominance: false,
             direction_facing: DirectionFacingFlags::NONE,
             lock_jumps_at_max: false,
+            hitstun_until: 0,
+            deferred_releases: vec![],
+            held_keys: vec![],
+            last_attack: None,
+            jumps_made: 0,
         }
     }
 }
@@ -499,6 +551,7 @@ impl MovementAction {
                     if !player_descriptor.char_collision_dominance {
                         player_descriptor.available_jumps -= self.jumps_removed;
                     }
+                    player_descriptor.jumps_made = player_descriptor.jumps_made.wrapping_add(1);
                     true
                 } else {
                     false
@@ -616,7 +669,7 @@ pub struct CharEntities {
     pub core: Entity,
     pub static_component: Option<Vec<Entity>>,
     pub actions: AvailableMovementActions,
-    pub attacks: AvailableAttacks,
+    pub combos: ComboTrie,
     pub projectiles: ProjectileMap,
     pub rescale_ratio: Vec3,
     pub attack_actions: AttackMap,
@@ -625,6 +678,8 @@ pub struct CharEntities {
     pub asset_map: AssetMap,
     pub player_id: u64,
     pub movement_map: MovementActionMap,
+    pub animation: CharacterAnimation,
+    pub sound: CharacterSounds,
 }
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Character {
@@ -667,6 +722,10 @@ pub struct Character {
     #[serde(default)]
     pub attack: AvailableAttacks,
 
+    // Combos of any length, the four attacks above get added to these
+    #[serde(default)]
+    pub combo: Vec<Combo>,
+
     #[serde(default)]
     pub projectile: Vec<Projectile>,
 
@@ -678,6 +737,15 @@ pub struct Character {
 
     #[serde(default)]
     pub movement_attack_action: Vec<MovementAttackAction>,
+
+    #[serde(default)]
+    pub shield: Shield,
+
+    #[serde(default)]
+    pub animation: CharacterAnimation,
+
+    #[serde(default)]
+    pub sound: CharacterSounds,
 }
 
 pub struct CharIdentifier(pub u64);
@@ -796,6 +864,7 @@ pub fn load_selected_characters(
     character_input_settings_map: Res<CharacterInputIdentifierMap>,
     mut state: ResMut<State<AppStates>>,
     mut controls_map: ResMut<CharacterInputMap>,
+    mut gamepad_map: ResMut<GamepadInputMap>,
     mut coreponents: ResMut<CharComponentMap>,
     game_settings: Res<GameSettings>,
     mut meshes: ResMut<Assets<Mesh>>,
@@ -811,8 +880,11 @@ pub fn load_selected_characters(
         let map_character_ratio = map_dim / Vec2::from(character.info.base_dimensions);
         let character_screen_ratio = map_character_ratio * map_screen_ratio;
         let asset_map = asset_dir.char_assets.get(&index).unwrap();
-        let character_controls = *character_input_settings_map.map.get(&index).unwrap();
-        controls_map.add_character_inputs(character_controls, *index);
+        // Players on the other side of a netplay match dont have any controls here
+        if let Some(character_controls) = character_input_settings_map.map.get(&index) {
+            controls_map.add_character_inputs(*character_controls, *index);
+            gamepad_map.add_character_inputs(*character_controls, *index);
+        }
 
         let spawn_pos = match spawn_positions.get((*index - 1) as usize) {
             Some(pos) => Vec2::from(*pos),
@@ -835,6 +907,7 @@ pub fn load_selected_characters(
             &mut materials,
             &fonts,
             &game.selected_map,
+            &game.ruleset,
         );
 
         coreponents.insert(*index, char_entity);
@@ -856,6 +929,8 @@ pub struct CharComponentPlayerIdentifier {
 pub enum CharComponentIdentifiers {
     IDMoveDirLookIdentifier(MoveDirLookIdentifier),
     IDCharComponentPlayerIdentifier(CharComponentPlayerIdentifier),
+    // The part id animations know it by
+    IDAnimatedPart(String),
 }
 
 impl CharComponent {
@@ -869,9 +944,15 @@ impl CharComponent {
         offset_transform: SyncTransformOffset,
         extra_identifiers: Vec<CharComponentIdentifiers>,
     ) -> Entity {
-        let possible_bundle = match self.character_assets.get((player_id - 1) as usize) {
-            Some(asset_id) => self.retrieve_bundle_with_asset(assets, *ratio, asset_id.clone()),
-            None => self.retrieve_bundle(assets, *ratio),
+        let asset_id = match self.character_assets.get((player_id - 1) as usize) {
+            Some(asset_id) => asset_id.clone(),
+            None => self.asset.clone(),
+        };
+        let possible_bundle = self.retrieve_bundle_with_asset(assets, *ratio, asset_id.clone());
+
+        let spawn_transform = match &possible_bundle {
+            PossibleBundle::Sprite(spritebundle) => spritebundle.transform,
+            PossibleBundle::Svg(svgbundle) => svgbundle.svg_bundle.transform,
         };
 
         let mut spawned_bundle = match possible_bundle {
@@ -889,6 +970,14 @@ impl CharComponent {
                 CharComponentIdentifiers::IDCharComponentPlayerIdentifier(identifier) => {
                     spawned_bundle.insert(identifier);
                 }
+                CharComponentIdentifiers::IDAnimatedPart(part_id) => {
+                    spawned_bundle.insert(AnimatedPart::new(
+                        player_id,
+                        part_id,
+                        asset_id.clone(),
+                        &spawn_transform,
+                    ));
+                }
             }
         }
         spawned_bundle.id()
@@ -924,6 +1013,7 @@ pub fn spawn_core(
     mut colliders: &mut ColliderMap,
     spawn_transform: Transform,
     mut extra_sync: Vec<(Entity, SyncColliderFlags)>,
+    ruleset: &RulesetSettings,
 ) -> Entity {
     let core = char.core.spawn_self(
         &assets,
@@ -932,7 +1022,9 @@ pub fn spawn_core(
         player_id,
         &mut commands,
         SyncTransformOffset::default(),
-        vec![],
+        vec![CharComponentIdentifiers::IDAnimatedPart(String::from(
+            "core",
+        ))],
     );
     let player_identifier = PhysicsSpawnExtras::PlayerIdentifier(PlayerIdentifier { player_id });
     let player_descriptor = PhysicsSpawnExtras::AAPlayerDescriptor(char.player_descriptor.clone());
@@ -947,9 +1039,24 @@ pub fn spawn_core(
         PhysicsSpawnExtras::AttackBuffer(AttackBuffer {
             buffer: vec![],
             final_timestamp: 0.0,
+            node: 0,
         }),
+        PhysicsSpawnExtras::PlayerAnimation(PlayerAnimation::default()),
     ];
 
+    if ruleset.ruleset == Ruleset::Percentage {
+        rigid_extras.push(PhysicsSpawnExtras::PlayerStocks(PlayerStocks {
+            percentage: 0.0,
+            stocks: ruleset.stocks.max(1),
+        }));
+    }
+
+    if char.shield.health > 0.0 {
+        rigid_extras.push(PhysicsSpawnExtras::PlayerShield(PlayerShield::new(
+            char.shield,
+        )));
+    }
+
     extra_sync.push((
         core,
         SyncColliderFlags {
@@ -1005,6 +1112,12 @@ pub fn load_char_components(
                     },
                 ));
 
+                if !char_component.id.is_empty() {
+                    extra_identifiers.push(CharComponentIdentifiers::IDAnimatedPart(
+                        char_component.id.clone(),
+                    ));
+                }
+
                 phys_object.extra_synced_items.push((
                     char_component.spawn_self(
                         &assets,
@@ -1065,6 +1178,40 @@ pub fn create_healthbar(
         .id()
 }
 
+pub fn create_percentage_hud(
+    char: &Character,
+    mut commands: &mut Commands,
+    ratio: Vec3,
+    player_id: u64,
+    font: &Res<AugmentedFonts>,
+    map: &Map,
+) -> Entity {
+    let text_style = TextStyle {
+        font: font.regular_font.clone(),
+        font_size: char.text_identifier.size / ratio.y,
+        color: Color::from(map.char_element_colours.healthbar_max.convert_to_rgb()),
+    };
+
+    let text_alignment = TextAlignment {
+        vertical: VerticalAlign::Center,
+        horizontal: HorizontalAlign::Center,
+    };
+
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::with_section(format!(""), text_style, text_alignment),
+            transform: Transform::from_translation(Vec3::new(0.0, 0.0, 997.0)),
+            ..Default::default()
+        })
+        .insert(SyncTransformOffset {
+            transform: Transform::from_translation(
+                Vec3::new(0., char.health.healthbar_distance, 0.) / ratio.y,
+            ),
+        })
+        .insert(PlayerPercentageHudId { player_id })
+        .id()
+}
+
 pub trait ConvertToRgb {
     fn convert_to_rgb(self) -> Self;
 }
@@ -1185,21 +1332,28 @@ pub fn load_character(
     mut materials: &mut ResMut<Assets<ColorMaterial>>,
     font: &Res<AugmentedFonts>,
     map: &Map,
+    ruleset: &RulesetSettings,
 ) -> CharEntities {
     let rescale_ratio = Vec3::new(ratio.y, ratio.y, 1.0);
 
     println!("getting colliders for char");
     let mut colliders = char.collider.get_hitbox_bundles(ratio.y);
 
-    let healthbar = create_healthbar(
-        &char,
-        &mut commands,
-        rescale_ratio,
-        &mut meshes,
-        &mut materials,
-        &map,
-        player_id,
-    );
+    // Percentage mode has nothing to drain so the healthbar is swapped for the percentage and stocks
+    let healthbar = match ruleset.ruleset {
+        Ruleset::Health => create_healthbar(
+            &char,
+            &mut commands,
+            rescale_ratio,
+            &mut meshes,
+            &mut materials,
+            &map,
+            player_id,
+        ),
+        Ruleset::Percentage => {
+            create_percentage_hud(&char, &mut commands, rescale_ratio, player_id, &font, &map)
+        }
+    };
 
     let attack_text =
         create_player_atk_text(&char, &mut commands, rescale_ratio, player_id, &font, &map);
@@ -1207,6 +1361,24 @@ pub fn load_character(
     let player_id_text =
         create_player_id_text(&char, &mut commands, rescale_ratio, player_id, &font, &map);
 
+    let mut extra_sync = vec![
+        (healthbar, SyncColliderFlags { rotation: false }),
+        (player_id_text, SyncColliderFlags { rotation: false }),
+        (attack_text, SyncColliderFlags { rotation: false }),
+    ];
+
+    if char.shield.health > 0.0 {
+        let shield_indicator = create_shield_indicator(
+            &char,
+            &mut commands,
+            rescale_ratio,
+            &mut meshes,
+            &mut materials,
+            player_id,
+        );
+        extra_sync.push((shield_indicator, SyncColliderFlags { rotation: false }));
+    }
+
     println!("finished getting colliders for char");
     let mut phys_map = create_physics_map(&char.physics_object, &rescale_ratio);
     let mut projectile_map = create_projectile_map(&char.projectile);
@@ -1230,18 +1402,22 @@ pub fn load_character(
         &phys_map,
         &mut colliders,
         spawn_transform,
-        vec![
-            (healthbar, SyncColliderFlags { rotation: false }),
-            (player_id_text, SyncColliderFlags { rotation: false }),
-            (attack_text, SyncColliderFlags { rotation: false }),
-        ],
+        extra_sync,
+        ruleset,
     );
 
     CharEntities {
         core: core_phys,
         static_component: None,
         actions: char.char_movement_action.scale_to_ratio(&ratio),
-        attacks: char.attack.clone(),
+        combos: ComboTrie::build(
+            char.attack
+                .combos()
+                .into_iter()
+                .chain(char.combo.iter().cloned())
+                .collect(),
+            game_settings.gameplay_settings.attack_buffer_reset_time,
+        ),
         projectiles: projectile_map,
         attack_actions: action_map,
         rescale_ratio: ratio,
@@ -1250,6 +1426,11 @@ pub fn load_character(
         asset_map: assets,
         player_id,
         movement_map,
+        animation: char.animation.scale_to_ratio(&ratio),
+        sound: CharacterSounds {
+            base_path: char.base_path.clone(),
+            ..char.sound.clone()
+        },
     }
 }
 
@@ -1298,6 +1479,7 @@ pub fn sync_objects_colliders(
     sync_offest_transform: Query<&mut SyncTransformOffset>,
     asset_info: Res<AssetInfoMap>,
     svg_handle: Query<&Handle<Svg>>,
+    animated_parts: Query<&AnimatedPart>,
 ) {
     for (collider_position, mut entity_sync) in colliders.iter_mut() {
         let mut transform = collider_position.translation.xy();
@@ -1321,6 +1503,14 @@ pub fn sync_objects_colliders(
                         _ => {}
                     }
 
+                    if let Ok(animated_part) = animated_parts.get(*synced_entity) {
+                        let synced_rotation = match sync_flags.rotation {
+                            true => Some(collider_position.rotation),
+                            false => None,
+                        };
+                        animated_part.apply_pose(&mut entity_transform, synced_rotation);
+                    }
+
                     match svg_handle.get(*synced_entity) {
                         Ok(svg_handle) => match asset_info.get(&svg_handle.id) {
                             None => {}
@@ -1446,3 +1636,34 @@ pub fn healthbar_update(
         }
     }
 }
+
+pub fn percentage_hud_update(
+    game: Res<Game>,
+    stocks_query: Query<&PlayerStocks>,
+    char_map: Res<CharComponentMap>,
+    mut hud_query: Query<(&PlayerPercentageHudId, &mut Text)>,
+) {
+    for (hud, mut text) in hud_query.iter_mut() {
+        let player_stocks = match char_map
+            .get(&hud.player_id)
+            .map(|character_entity| stocks_query.get(character_entity.core))
+        {
+            Some(Ok(player_stocks)) => player_stocks,
+            _ => continue,
+        };
+
+        // Shifts colour the same way the healthbar does, 150% counts as being on 0 health
+        let colour_addition = game.selected_map.char_element_colours.health_colour_distr
+            * (player_stocks.percentage / 150.0).min(1.0);
+        let colour =
+            Vec3::from(game.selected_map.char_element_colours.healthbar_max) + colour_addition;
+
+        for section in text.sections.iter_mut() {
+            section.value = format!(
+                "{:.0}% x{}",
+                player_stocks.percentage, player_stocks.stocks
+            );
+            section.style.color = Color::from((colour / 255.0).extend(1.0));
+        }
+    }
+}
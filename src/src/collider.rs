@@ -53,6 +53,24 @@ pub struct AACollider {
     pub death_colliders: CollidersVec,
 }
 
+impl AACollider {
+    // Every id given to a collider, colliders without one can't be referenced
+    pub fn ids(&self) -> Vec<String> {
+        let cuboid = self.cuboid.iter().map(|collider| &collider.id);
+        let circle = self.circle.iter().map(|collider| &collider.id);
+        let convex = self.convex.iter().map(|collider| &collider.id);
+        let round_cuboid = self.round_cuboid.iter().map(|collider| &collider.id);
+
+        cuboid
+            .chain(circle)
+            .chain(convex)
+            .chain(round_cuboid)
+            .flatten()
+            .cloned()
+            .collect()
+    }
+}
+
 impl Default for AACollider {
     fn default() -> Self {
         Self {
@@ -337,6 +355,10 @@ impl AACollider {
 }
 
 impl CollidersVec {
+    pub fn ids(&self) -> &[String] {
+        &self.colliders
+    }
+
     pub fn set_special_type(&self, bundles: &mut ColliderMap, collider_type: AAColliderType) {
         for id in &self.colliders {
             match bundles.get_mut(&Some(id.clone())) {
@@ -0,0 +1,338 @@
+use bevy::log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::char::{AttackKey, AvailableAttacks, DirectionFacingFlags};
+use crate::projectile::Attack;
+
+// Which of the cooldowns and combos of a character an attack is, the index into ComboTrie::combos
+pub type ComboId = usize;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ComboDirection {
+    Any,
+    None,
+    Up,
+    Down,
+    Left,
+    Right,
+    UpLeft,
+    UpRight,
+    DownLeft,
+    DownRight,
+}
+
+impl Default for ComboDirection {
+    fn default() -> Self {
+        ComboDirection::Any
+    }
+}
+
+impl ComboDirection {
+    pub fn matches(&self, facing: DirectionFacingFlags) -> bool {
+        let required = match self {
+            ComboDirection::Any => return true,
+            ComboDirection::None => return facing == DirectionFacingFlags::NONE,
+            ComboDirection::Up => DirectionFacingFlags::UP,
+            ComboDirection::Down => DirectionFacingFlags::DOWN,
+            ComboDirection::Left => DirectionFacingFlags::LEFT,
+            ComboDirection::Right => DirectionFacingFlags::RIGHT,
+            ComboDirection::UpLeft => DirectionFacingFlags::UP | DirectionFacingFlags::LEFT,
+            ComboDirection::UpRight => DirectionFacingFlags::UP | DirectionFacingFlags::RIGHT,
+            ComboDirection::DownLeft => DirectionFacingFlags::DOWN | DirectionFacingFlags::LEFT,
+            ComboDirection::DownRight => DirectionFacingFlags::DOWN | DirectionFacingFlags::RIGHT,
+        };
+
+        facing.contains(required)
+    }
+}
+
+// A [[combo]] in main.toml, e.g. keys = [1, 1, 2]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Combo {
+    pub keys: Vec<u8>,
+
+    // Seconds each key has to come within of the one before it, missing ones use attack_buffer_reset_time.
+    // The first key has nothing before it so its window is never used
+    pub windows: Vec<f64>,
+
+    // The direction that has to be held for each key, missing ones are any direction
+    pub directions: Vec<ComboDirection>,
+
+    pub actions: Vec<String>,
+    pub cooldown: f64,
+    pub uninterruptible: bool,
+
+    // The animation clip played while it's going off
+    pub animation: String,
+
+    // Played instead of the character's attack sound
+    pub sound: String,
+}
+
+impl Combo {
+    pub fn attack_keys(&self) -> Option<Vec<AttackKey>> {
+        self.keys
+            .iter()
+            .map(|key| match key {
+                1 => Some(AttackKey::One),
+                2 => Some(AttackKey::Two),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn attack(&self) -> Attack {
+        Attack {
+            actions: self.actions.clone(),
+            cooldown: self.cooldown,
+            uninterruptible: self.uninterruptible,
+            animation: self.animation.clone(),
+            sound: self.sound.clone(),
+        }
+    }
+}
+
+impl AvailableAttacks {
+    // The old fixed attacks are just two key combos
+    pub fn combos(&self) -> Vec<Combo> {
+        [
+            (&self.one_one, [1, 1]),
+            (&self.one_two, [1, 2]),
+            (&self.two_one, [2, 1]),
+            (&self.two_two, [2, 2]),
+        ]
+        .into_iter()
+        .filter_map(|(attack, keys)| {
+            attack.as_ref().map(|attack| Combo {
+                keys: keys.to_vec(),
+                windows: vec![],
+                directions: vec![],
+                actions: attack.actions.clone(),
+                cooldown: attack.cooldown,
+                uninterruptible: attack.uninterruptible,
+                animation: attack.animation.clone(),
+                sound: attack.sound.clone(),
+            })
+        })
+        .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ComboEdge {
+    pub key: AttackKey,
+    pub direction: ComboDirection,
+    pub window: f64,
+    pub node: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ComboNode {
+    // The combo that fires if the keys stop here
+    pub combo: Option<ComboId>,
+    pub edges: Vec<ComboEdge>,
+}
+
+impl ComboNode {
+    // How long to wait for the next key before giving up on anything longer
+    pub fn longest_window(&self) -> f64 {
+        self.edges
+            .iter()
+            .map(|edge| edge.window)
+            .fold(0.0, f64::max)
+    }
+}
+
+// Node 0 is where every combo starts, each key pressed follows one edge further in
+#[derive(Debug, Clone, Default)]
+pub struct ComboTrie {
+    pub nodes: Vec<ComboNode>,
+    pub combos: Vec<Combo>,
+}
+
+impl ComboTrie {
+    pub fn build(combos: Vec<Combo>, default_window: f64) -> Self {
+        let mut trie = ComboTrie {
+            nodes: vec![ComboNode::default()],
+            combos: vec![],
+        };
+
+        for combo in combos {
+            let keys = match combo.attack_keys() {
+                Some(keys) if !keys.is_empty() => keys,
+                _ => {
+                    warn!(
+                        "Combo {:?} isnt made of only 1s and 2s so it cant be used",
+                        combo.keys
+                    );
+                    continue;
+                }
+            };
+
+            let mut node = 0;
+            for (step, key) in keys.into_iter().enumerate() {
+                let direction = combo.directions.get(step).copied().unwrap_or_default();
+                let window = combo.windows.get(step).copied().unwrap_or(default_window);
+
+                let existing = trie.nodes[node]
+                    .edges
+                    .iter_mut()
+                    .find(|edge| edge.key == key && edge.direction == direction);
+
+                node = match existing {
+                    // Combos sharing a step get the most forgiving window of the lot
+                    Some(edge) => {
+                        edge.window = edge.window.max(window);
+                        edge.node
+                    }
+                    None => {
+                        let next_node = trie.nodes.len();
+                        trie.nodes.push(ComboNode::default());
+                        trie.nodes[node].edges.push(ComboEdge {
+                            key,
+                            direction,
+                            window,
+                            node: next_node,
+                        });
+                        next_node
+                    }
+                };
+            }
+
+            if trie.nodes[node].combo.is_some() {
+                warn!(
+                    "There is more than one combo for {:?}, only the first is used",
+                    combo.keys
+                );
+                continue;
+            }
+
+            trie.nodes[node].combo = Some(trie.combos.len());
+            trie.combos.push(combo);
+        }
+
+        // Steps that need a direction get checked before the ones that dont care
+        for node in trie.nodes.iter_mut() {
+            node.edges
+                .sort_by_key(|edge| edge.direction == ComboDirection::Any);
+        }
+
+        trie
+    }
+
+    // The node reached by pressing key at node, if it came soon enough after the last one
+    pub fn follow(
+        &self,
+        node: usize,
+        key: AttackKey,
+        facing: DirectionFacingFlags,
+        elapsed: f64,
+    ) -> Option<usize> {
+        self.nodes[node]
+            .edges
+            .iter()
+            .find(|edge| {
+                edge.key == key
+                    && edge.direction.matches(facing)
+                    && (node == 0 || elapsed <= edge.window)
+            })
+            .map(|edge| edge.node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bevy::ecs::system::SystemState;
+    use bevy::prelude::*;
+
+    use crate::char::AttackBuffer;
+    use crate::game::{GameClock, InputKeyboardType, InputPurpose};
+
+    fn combo(keys: &[u8], directions: &[ComboDirection]) -> Combo {
+        Combo {
+            keys: keys.to_vec(),
+            directions: directions.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    // The buffer only takes the clock as a resource, so it gets one out of a world at the given gameplay frame
+    fn at_frame<T>(gameplay_frame: u64, run: impl FnOnce(&Res<GameClock>) -> T) -> T {
+        let mut world = World::new();
+        let mut clock = GameClock::new(60.0);
+        clock.gameplay_frame = gameplay_frame;
+        world.insert_resource(clock);
+
+        let mut clock_state = SystemState::<Res<GameClock>>::new(&mut world);
+        let clock = clock_state.get(&world);
+        run(&clock)
+    }
+
+    fn press(
+        buffer: &mut AttackBuffer,
+        trie: &ComboTrie,
+        purpose: InputPurpose,
+        facing: DirectionFacingFlags,
+        gameplay_frame: u64,
+    ) -> Option<ComboId> {
+        at_frame(gameplay_frame, |clock| {
+            buffer.add_to_buffer(InputKeyboardType::JustPressed, purpose, facing, trie, clock)
+        })
+    }
+
+    #[test]
+    fn longer_combo_wins_over_its_prefix() {
+        let trie = ComboTrie::build(vec![combo(&[1, 1], &[]), combo(&[1, 1, 2], &[])], 0.5);
+        let mut buffer = AttackBuffer::default();
+        let facing = DirectionFacingFlags::NONE;
+
+        // The prefix cant fire yet, something longer might still be coming
+        assert_eq!(press(&mut buffer, &trie, InputPurpose::Atk1, facing, 0), None);
+        assert_eq!(press(&mut buffer, &trie, InputPurpose::Atk1, facing, 10), None);
+        assert_eq!(press(&mut buffer, &trie, InputPurpose::Atk2, facing, 20), Some(1));
+        assert_eq!(buffer.node, 0);
+    }
+
+    #[test]
+    fn expired_window_fires_the_prefix() {
+        let trie = ComboTrie::build(vec![combo(&[1, 1], &[]), combo(&[1, 1, 2], &[])], 0.5);
+        let mut buffer = AttackBuffer::default();
+        let facing = DirectionFacingFlags::NONE;
+
+        press(&mut buffer, &trie, InputPurpose::Atk1, facing, 0);
+        press(&mut buffer, &trie, InputPurpose::Atk1, facing, 10);
+
+        // Still inside the half second window for the 2
+        assert_eq!(at_frame(30, |clock| buffer.clear_expired(&trie, clock)), None);
+        assert_eq!(at_frame(41, |clock| buffer.clear_expired(&trie, clock)), Some(0));
+        assert_eq!(buffer.node, 0);
+        assert!(buffer.buffer.is_empty());
+    }
+
+    #[test]
+    fn directed_steps_come_before_any_direction() {
+        // The any direction one is listed first but still has to lose to the one that asks for up
+        let trie = ComboTrie::build(
+            vec![
+                combo(&[1], &[ComboDirection::Any]),
+                combo(&[1], &[ComboDirection::Up]),
+            ],
+            0.5,
+        );
+
+        let edges = &trie.nodes[0].edges;
+        assert_eq!(edges.len(), 2);
+        assert_eq!(edges[0].direction, ComboDirection::Up);
+        assert_eq!(edges[1].direction, ComboDirection::Any);
+
+        let up_node = trie.follow(0, AttackKey::One, DirectionFacingFlags::UP, 0.0);
+        assert_eq!(up_node.and_then(|node| trie.nodes[node].combo), Some(1));
+
+        let left_node = trie.follow(0, AttackKey::One, DirectionFacingFlags::LEFT, 0.0);
+        assert_eq!(left_node.and_then(|node| trie.nodes[node].combo), Some(0));
+    }
+}
@@ -0,0 +1,596 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::assets::AugmentedFonts;
+use crate::char::ConvertToRgb;
+use crate::game::*;
+use crate::headless::Headless;
+use crate::pack_io::resolve_asset_path;
+use crate::AppStates;
+
+// The layout of directory.json, every pack is a [url, name] pair and they all share one version
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ContentDirectory {
+    #[serde(default)]
+    pub maps: Vec<(String, String)>,
+
+    #[serde(default)]
+    pub chars: Vec<(String, String)>,
+
+    pub version: u64,
+}
+
+// Which directory version every downloaded pack came from, keyed by where it got installed
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct InstalledContent {
+    pub packs: HashMap<String, u64>,
+}
+
+impl InstalledContent {
+    pub fn load(path: &str) -> Self {
+        match std::fs::read_to_string(path) {
+            Err(_) => InstalledContent::default(),
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|error| {
+                warn!(
+                    "{} is malformed, treating everything as not downloaded: {}",
+                    path, error
+                );
+                InstalledContent::default()
+            }),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+// The only folders a pack can be installed into
+pub const CONTENT_FOLDERS: [&str; 2] = ["maps", "char"];
+
+#[derive(Debug, Clone)]
+pub struct ContentPack {
+    pub url: String,
+    pub name: String,
+
+    // Either maps or char, the same folders load_maps and load_characters read from
+    pub folder: String,
+}
+
+impl ContentPack {
+    // Names come from the directory, anything like ../ or an absolute path would install outside the folder
+    pub fn install_path(&self) -> anyhow::Result<PathBuf> {
+        if !CONTENT_FOLDERS.contains(&self.folder.as_str()) {
+            anyhow::bail!("{} isnt a folder packs can be installed into", self.folder);
+        }
+
+        let mut components = Path::new(&self.name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => Ok(resolve_asset_path(
+                &PathBuf::from(&self.folder).join(&self.name),
+            )),
+            _ => anyhow::bail!("{} isnt a valid pack name", self.name),
+        }
+    }
+
+    pub fn key(&self) -> String {
+        format!("{}/{}", self.folder, self.name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContentStage {
+    FetchingDirectory,
+    Downloading {
+        name: String,
+        index: usize,
+        total: usize,
+    },
+    Extracting {
+        name: String,
+    },
+    Finished,
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct ContentProgress {
+    pub stage: ContentStage,
+    pub downloaded_bytes: u64,
+    pub total_bytes: Option<u64>,
+
+    // Packs that failed don't stop the rest, they just get reported at the end
+    pub failed_packs: Vec<String>,
+}
+
+impl ContentProgress {
+    pub fn describe(&self) -> String {
+        let bytes = match self.total_bytes {
+            Some(total) if total > 0 => {
+                format!("{}%", self.downloaded_bytes * 100 / total)
+            }
+            _ => format!("{} KB", self.downloaded_bytes / 1024),
+        };
+
+        match &self.stage {
+            ContentStage::FetchingDirectory => format!("CHECKING FOR CONTENT {}", bytes),
+            ContentStage::Downloading { name, index, total } => {
+                format!("DOWNLOADING {} ({}/{}) {}", name, index, total, bytes)
+            }
+            ContentStage::Extracting { name } => format!("INSTALLING {}", name),
+            ContentStage::Finished => "DONE".to_string(),
+            ContentStage::Failed(error) => format!("FAILED: {}", error),
+        }
+    }
+}
+
+pub struct ContentDownloader {
+    pub progress: Arc<Mutex<ContentProgress>>,
+}
+
+#[derive(Component)]
+pub struct ContentProgressText;
+
+fn set_stage(progress: &Arc<Mutex<ContentProgress>>, stage: ContentStage) {
+    let mut progress = progress.lock().unwrap();
+    progress.stage = stage;
+    progress.downloaded_bytes = 0;
+    progress.total_bytes = None;
+}
+
+fn is_url(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+// Pack urls can be relative to wherever the directory came from, handy for a local stand-in
+pub fn resolve_source(directory_source: &str, source: &str) -> anyhow::Result<String> {
+    if is_url(source) || Path::new(source.trim_start_matches("file://")).is_absolute() {
+        return Ok(source.to_string());
+    }
+
+    if is_url(directory_source) {
+        Ok(reqwest::Url::parse(directory_source)?
+            .join(source)?
+            .to_string())
+    } else {
+        let directory_path = PathBuf::from(directory_source.trim_start_matches("file://"));
+        let parent = directory_path.parent().unwrap_or(Path::new(""));
+        Ok(parent.join(source).to_string_lossy().to_string())
+    }
+}
+
+// Reads from a url or a local file, either way the progress gets updated as it goes
+pub async fn fetch(
+    source: &str,
+    progress: &Arc<Mutex<ContentProgress>>,
+) -> anyhow::Result<Vec<u8>> {
+    if !is_url(source) {
+        let bytes = std::fs::read(source.trim_start_matches("file://"))?;
+        let mut progress = progress.lock().unwrap();
+        progress.downloaded_bytes = bytes.len() as u64;
+        progress.total_bytes = Some(bytes.len() as u64);
+        return Ok(bytes);
+    }
+
+    let mut response = reqwest::get(source).await?.error_for_status()?;
+    progress.lock().unwrap().total_bytes = response.content_length();
+
+    let mut bytes = vec![];
+    while let Some(chunk) = response.chunk().await? {
+        bytes.extend_from_slice(&chunk);
+        progress.lock().unwrap().downloaded_bytes = bytes.len() as u64;
+    }
+
+    Ok(bytes)
+}
+
+// Anything missing from disk or downloaded from an older directory version
+pub fn packs_to_download(
+    directory: &ContentDirectory,
+    installed: &InstalledContent,
+) -> Vec<ContentPack> {
+    let maps = directory.maps.iter().map(|pack| (pack, "maps"));
+    let chars = directory.chars.iter().map(|pack| (pack, "char"));
+
+    maps.chain(chars)
+        .map(|((url, name), folder)| ContentPack {
+            url: url.clone(),
+            name: name.clone(),
+            folder: folder.to_string(),
+        })
+        .filter(|pack| {
+            let install_path = match pack.install_path() {
+                Ok(install_path) => install_path,
+                Err(error) => {
+                    warn!("Skipping content pack from {}: {}", pack.url, error);
+                    return false;
+                }
+            };
+
+            // A zip of the pack dropped in by hand counts as installed too
+            let zip_path =
+                resolve_asset_path(&PathBuf::from(&pack.folder).join(format!("{}.zip", pack.name)));
+            if !install_path.exists() && !zip_path.exists() {
+                return true;
+            }
+
+            // Packs put there by hand never get touched
+            match installed.packs.get(&pack.key()) {
+                None => false,
+                Some(version) => *version < directory.version,
+            }
+        })
+        .collect()
+}
+
+// The zips hold a single folder named after the pack, it replaces whatever was installed before
+pub fn extract_pack(pack: &ContentPack, bytes: Vec<u8>) -> anyhow::Result<()> {
+    extract_pack_to(&pack.install_path()?, bytes)
+}
+
+// Extracts next to install_path first, so a broken zip never leaves a half installed pack behind
+pub fn extract_pack_to(install_path: &Path, bytes: Vec<u8>) -> anyhow::Result<()> {
+    let name = match install_path.file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => anyhow::bail!("{} isnt a valid install path", install_path.display()),
+    };
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+
+    let partial_path = install_path.with_file_name(format!(".{}.partial", name));
+    if partial_path.exists() {
+        std::fs::remove_dir_all(&partial_path)?;
+    }
+    archive.extract(&partial_path)?;
+
+    let extracted_path = match partial_path.join(&name) {
+        nested if nested.is_dir() => nested,
+        _ => partial_path.clone(),
+    };
+
+    if install_path.exists() {
+        std::fs::remove_dir_all(install_path)?;
+    }
+    std::fs::rename(&extracted_path, install_path)?;
+
+    if partial_path.exists() {
+        std::fs::remove_dir_all(&partial_path)?;
+    }
+    Ok(())
+}
+
+pub async fn download_content(
+    settings: ContentSettings,
+    progress: Arc<Mutex<ContentProgress>>,
+) -> anyhow::Result<()> {
+    let directory: ContentDirectory =
+        serde_json::from_slice(&fetch(&settings.directory, &progress).await?)?;
+    let mut installed = InstalledContent::load(&settings.installed_path);
+
+    let packs = packs_to_download(&directory, &installed);
+    println!("{} content packs to download", packs.len());
+
+    for (index, pack) in packs.iter().enumerate() {
+        set_stage(
+            &progress,
+            ContentStage::Downloading {
+                name: pack.name.clone(),
+                index: index + 1,
+                total: packs.len(),
+            },
+        );
+
+        let downloaded = match resolve_source(&settings.directory, &pack.url) {
+            Ok(source) => fetch(&source, &progress).await,
+            Err(error) => Err(error),
+        };
+
+        set_stage(
+            &progress,
+            ContentStage::Extracting {
+                name: pack.name.clone(),
+            },
+        );
+        match downloaded.and_then(|bytes| extract_pack(pack, bytes)) {
+            Ok(_) => {
+                installed.packs.insert(pack.key(), directory.version);
+                installed.save(&settings.installed_path)?;
+            }
+            Err(error) => {
+                warn!("Failed to install {}: {}", pack.name, error);
+                progress
+                    .lock()
+                    .unwrap()
+                    .failed_packs
+                    .push(pack.name.clone());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn start_content_download(
+    mut commands: Commands,
+    settings: Res<GameSettings>,
+    fonts: Res<AugmentedFonts>,
+    headless: Option<Res<Headless>>,
+    mut state: ResMut<State<AppStates>>,
+) {
+    // Headless runs shouldnt reach out to the network or change what's installed under them
+    if !settings.content.enabled || headless.is_some() {
+        state.set(AppStates::LoadComps);
+        return;
+    }
+
+    let progress = Arc::new(Mutex::new(ContentProgress {
+        stage: ContentStage::FetchingDirectory,
+        downloaded_bytes: 0,
+        total_bytes: None,
+        failed_packs: vec![],
+    }));
+
+    let thread_progress = progress.clone();
+    let content_settings = settings.content.clone();
+
+    // Downloads happen on their own thread so the window stays responsive
+    std::thread::spawn(move || {
+        let runtime = match tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(error) => {
+                set_stage(&thread_progress, ContentStage::Failed(error.to_string()));
+                return;
+            }
+        };
+
+        let stage =
+            match runtime.block_on(download_content(content_settings, thread_progress.clone())) {
+                Ok(_) => ContentStage::Finished,
+                Err(error) => ContentStage::Failed(error.to_string()),
+            };
+        set_stage(&thread_progress, stage);
+    });
+
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::with_section(
+                String::new(),
+                TextStyle {
+                    font: fonts.bold_font.clone(),
+                    font_size: settings.window.height * settings.player_id.help_text_size,
+                    color: Color::from(settings.player_id.help_text_colour.convert_to_rgb()),
+                },
+                TextAlignment {
+                    vertical: VerticalAlign::Center,
+                    horizontal: HorizontalAlign::Center,
+                },
+            ),
+            transform: Transform::from_translation(Vec3::new(0., 0., 30.)),
+            ..Default::default()
+        })
+        .insert(ContentProgressText);
+
+    commands.insert_resource(ContentDownloader { progress });
+}
+
+pub fn content_download_update(
+    downloader: Option<Res<ContentDownloader>>,
+    mut text_query: Query<(Entity, &mut Text), With<ContentProgressText>>,
+    mut state: ResMut<State<AppStates>>,
+    mut commands: Commands,
+) {
+    let downloader = match downloader {
+        None => return,
+        Some(downloader) => downloader,
+    };
+
+    let progress = downloader.progress.lock().unwrap().clone();
+    for (_, mut text) in text_query.iter_mut() {
+        text.sections[0].value = progress.describe();
+    }
+
+    match &progress.stage {
+        ContentStage::Finished => {
+            if !progress.failed_packs.is_empty() {
+                warn!(
+                    "Some content failed to install: {:?}",
+                    progress.failed_packs
+                );
+            }
+        }
+        // Whatever is already installed is still playable, so carry on without the download
+        ContentStage::Failed(error) => warn!("Failed to download content: {}", error),
+        _ => return,
+    }
+
+    for (entity, _) in text_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<ContentDownloader>();
+    state.set(AppStates::LoadComps);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn pack(folder: &str, name: &str) -> ContentPack {
+        ContentPack {
+            url: format!("{}.zip", name),
+            name: name.to_string(),
+            folder: folder.to_string(),
+        }
+    }
+
+    // Somewhere to install into that isnt the real asset folders
+    fn scratch_dir(test: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("aa_content_{}_{}", test, std::process::id()));
+        if path.exists() {
+            std::fs::remove_dir_all(&path).unwrap();
+        }
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn zip_of(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(vec![]));
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        for (path, content) in files {
+            writer.start_file(*path, options).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn install_path_stays_in_content_folders() {
+        for name in [
+            "../escape",
+            "..",
+            "/etc/escape",
+            "dunes/../../escape",
+            "nested/pack",
+            "",
+        ] {
+            assert!(
+                pack("maps", name).install_path().is_err(),
+                "{} was allowed",
+                name
+            );
+        }
+        assert!(pack("saves", "dunes").install_path().is_err());
+
+        let install_path = pack("char", "euclid").install_path().unwrap();
+        assert!(install_path.ends_with("char/euclid"));
+    }
+
+    #[test]
+    fn sources_resolve_against_the_directory() {
+        assert_eq!(
+            resolve_source(
+                "https://example.com/content/directory.json",
+                "packs/dunes.zip"
+            )
+            .unwrap(),
+            "https://example.com/content/packs/dunes.zip"
+        );
+        assert_eq!(
+            resolve_source(
+                "https://example.com/content/directory.json",
+                "http://mirror.example.com/dunes.zip"
+            )
+            .unwrap(),
+            "http://mirror.example.com/dunes.zip"
+        );
+        assert_eq!(
+            resolve_source("/srv/content/directory.json", "dunes.zip").unwrap(),
+            "/srv/content/dunes.zip"
+        );
+        assert_eq!(
+            resolve_source("file:///srv/content/directory.json", "dunes.zip").unwrap(),
+            "/srv/content/dunes.zip"
+        );
+        assert_eq!(
+            resolve_source("/srv/content/directory.json", "/elsewhere/dunes.zip").unwrap(),
+            "/elsewhere/dunes.zip"
+        );
+    }
+
+    #[test]
+    fn extracting_replaces_the_old_install() {
+        let maps = scratch_dir("replace");
+        let install_path = maps.join("dunes");
+        std::fs::create_dir_all(&install_path).unwrap();
+        std::fs::write(install_path.join("old.toml"), "").unwrap();
+
+        // Left behind by an install that got cut off part way
+        let partial_path = maps.join(".dunes.partial");
+        std::fs::create_dir_all(&partial_path).unwrap();
+        std::fs::write(partial_path.join("stale.toml"), "").unwrap();
+
+        extract_pack_to(&install_path, zip_of(&[("dunes/map.toml", "[info]")])).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(install_path.join("map.toml")).unwrap(),
+            "[info]"
+        );
+        assert!(!install_path.join("old.toml").exists());
+        assert!(!install_path.join("stale.toml").exists());
+        assert!(!partial_path.exists());
+        std::fs::remove_dir_all(&maps).unwrap();
+    }
+
+    #[test]
+    fn zips_without_a_pack_folder_still_install() {
+        let maps = scratch_dir("flat");
+        let install_path = maps.join("dunes");
+
+        extract_pack_to(&install_path, zip_of(&[("map.toml", "[info]")])).unwrap();
+
+        assert!(install_path.join("map.toml").is_file());
+        assert!(!maps.join(".dunes.partial").exists());
+        std::fs::remove_dir_all(&maps).unwrap();
+    }
+
+    #[test]
+    fn broken_zips_leave_the_install_alone() {
+        let maps = scratch_dir("broken");
+        let install_path = maps.join("dunes");
+        std::fs::create_dir_all(&install_path).unwrap();
+        std::fs::write(install_path.join("map.toml"), "").unwrap();
+
+        assert!(extract_pack_to(&install_path, b"not a zip".to_vec()).is_err());
+
+        assert!(install_path.join("map.toml").is_file());
+        std::fs::remove_dir_all(&maps).unwrap();
+    }
+
+    #[test]
+    fn packs_fetch_from_next_to_a_local_directory() {
+        let content = scratch_dir("fetch");
+        std::fs::write(
+            content.join("dunes.zip"),
+            zip_of(&[("dunes/map.toml", "[info]")]),
+        )
+        .unwrap();
+        let directory = content.join("directory.json");
+
+        let progress = Arc::new(Mutex::new(ContentProgress {
+            stage: ContentStage::FetchingDirectory,
+            downloaded_bytes: 0,
+            total_bytes: None,
+            failed_packs: vec![],
+        }));
+        let source = resolve_source(
+            &format!("file://{}", directory.display()),
+            &pack("maps", "dunes").url,
+        )
+        .unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let bytes = runtime.block_on(fetch(&source, &progress)).unwrap();
+        assert_eq!(
+            progress.lock().unwrap().downloaded_bytes,
+            bytes.len() as u64
+        );
+
+        let install_path = content.join("maps").join("dunes");
+        std::fs::create_dir_all(content.join("maps")).unwrap();
+        extract_pack_to(&install_path, bytes).unwrap();
+
+        assert!(install_path.join("map.toml").is_file());
+        std::fs::remove_dir_all(&content).unwrap();
+    }
+}
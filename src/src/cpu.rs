@@ -0,0 +1,376 @@
+use std::collections::{HashMap, VecDeque};
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::char::{
+    AAPlayerDescriptor, AttackInstanceDirectory, AttackKey, CharComponentMap, PlayerHealth,
+    PlayerIdentifier,
+};
+use crate::collider::{DeathColliderIdentifier, SolidColliderIdentifier};
+use crate::combo::ComboDirection;
+use crate::game::*;
+use crate::maps::MapComponent;
+use crate::replay::ReplayPlayer;
+use crate::AppStates;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum CpuDifficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl CpuDifficulty {
+    pub fn next(&self) -> Self {
+        match self {
+            CpuDifficulty::Easy => CpuDifficulty::Normal,
+            CpuDifficulty::Normal => CpuDifficulty::Hard,
+            CpuDifficulty::Hard => CpuDifficulty::Easy,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CpuDifficulty::Easy => "EASY",
+            CpuDifficulty::Normal => "NORMAL",
+            CpuDifficulty::Hard => "HARD",
+        }
+    }
+
+    pub fn behaviour<'a>(&self, settings: &'a CpuSettings) -> &'a CpuBehaviour {
+        match self {
+            CpuDifficulty::Easy => &settings.easy,
+            CpuDifficulty::Normal => &settings.normal,
+            CpuDifficulty::Hard => &settings.hard,
+        }
+    }
+}
+
+// Everything a cpu decides is just the same key presses a player would make
+pub struct CpuBrain {
+    pub difficulty: CpuDifficulty,
+    pub held: Vec<InputPurpose>,
+    pub pending: VecDeque<(InputPurpose, InputKeyboardType)>,
+    pub next_decision: u64,
+    pub rng: Option<StdRng>,
+}
+
+impl CpuBrain {
+    pub fn new(difficulty: CpuDifficulty) -> Self {
+        Self {
+            difficulty,
+            held: vec![],
+            pending: VecDeque::new(),
+            next_decision: 0,
+            rng: None,
+        }
+    }
+
+    // Presses whatever isnt held yet and lets go of everything else
+    fn hold(
+        &mut self,
+        player_id: u64,
+        wanted: Vec<InputPurpose>,
+        input_queue: &mut PlayerInputQueue,
+    ) {
+        for purpose in self.held.iter() {
+            if !wanted.contains(purpose) {
+                input_queue.inputs.push(PlayerInput {
+                    player_id,
+                    purpose: *purpose,
+                    input_type: InputKeyboardType::JustReleased,
+                });
+            }
+        }
+
+        for purpose in wanted.iter() {
+            if !self.held.contains(purpose) {
+                input_queue.inputs.push(PlayerInput {
+                    player_id,
+                    purpose: *purpose,
+                    input_type: InputKeyboardType::JustPressed,
+                });
+            }
+        }
+
+        self.held = wanted;
+    }
+}
+
+pub struct CpuPlayers {
+    pub players: HashMap<u64, CpuBrain>,
+}
+
+#[derive(Component)]
+pub struct CpuJoinedText {
+    pub player_id: u64,
+}
+
+type MapColliderQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Option<&'static SolidColliderIdentifier>,
+        Option<&'static DeathColliderIdentifier>,
+    ),
+    With<MapComponent>,
+>;
+
+// Looks straight down from a point, true if there is nothing to land on or only something that kills
+fn is_dangerous(
+    rapier_context: &RapierContext,
+    origin: Vec2,
+    distance: f32,
+    map_colliders: &MapColliderQuery,
+) -> bool {
+    let only_map = |entity: Entity| map_colliders.get(entity).is_ok();
+
+    match rapier_context.cast_ray(
+        origin,
+        Vec2::new(0.0, -1.0),
+        distance,
+        true,
+        InteractionGroups::all(),
+        Some(&only_map),
+    ) {
+        None => true,
+        Some((entity, _)) => match map_colliders.get(entity) {
+            Ok((_, Some(_))) => true,
+            _ => false,
+        },
+    }
+}
+
+// What's left of a player's health, characters with a bigger maximum dont look any healthier for it
+fn health_fraction(health: &PlayerHealth) -> f32 {
+    match health.maximum_health > 0.0 {
+        true => (health.current_health / health.maximum_health).clamp(0.0, 1.0),
+        false => 1.0,
+    }
+}
+
+pub fn cpu_input_system(
+    cpu_players: Option<ResMut<CpuPlayers>>,
+    replay_player: Option<Res<ReplayPlayer>>,
+    game: Option<Res<Game>>,
+    state: Res<State<AppStates>>,
+    gameplay_toggle: Res<AAGamePlayToggle>,
+    clock: Res<GameClock>,
+    settings: Res<GameSettings>,
+    attack_directory: Res<AttackInstanceDirectory>,
+    char_map: Res<CharComponentMap>,
+    rapier_context: Res<RapierContext>,
+    players: Query<(&PlayerIdentifier, &Transform, &AAPlayerDescriptor, &PlayerHealth)>,
+    map_colliders: MapColliderQuery,
+    mut input_queue: ResMut<PlayerInputQueue>,
+) {
+    let (mut cpu_players, game) = match (cpu_players, game) {
+        (Some(cpu_players), Some(game)) => (cpu_players, game),
+        _ => return,
+    };
+
+    // Replays already have everything the cpus pressed recorded
+    if replay_player.is_some() || state.current() != &AppStates::LoadGame {
+        return;
+    }
+
+    // Pending keys are one a frame, so updates without a gameplay frame in them cant use any up
+    if clock.frames_due == 0 {
+        return;
+    }
+
+    for (player_id, brain) in cpu_players.players.iter_mut() {
+        // Inputs get thrown away while movement is paused, so start again from nothing afterwards
+        if !gameplay_toggle.process_movement {
+            brain.held.clear();
+            brain.pending.clear();
+            brain.next_decision = 0;
+            continue;
+        }
+
+        let (own_transform, descriptor, own_health) = match players
+            .iter()
+            .find(|(identifier, ..)| identifier.player_id == *player_id)
+        {
+            None => continue,
+            Some((_, transform, descriptor, health)) => {
+                (transform, descriptor, health_fraction(health))
+            }
+        };
+
+        // Attacks are queued up key by key, one press or release a frame
+        if let Some((purpose, input_type)) = brain.pending.pop_front() {
+            input_queue.inputs.push(PlayerInput {
+                player_id: *player_id,
+                purpose,
+                input_type,
+            });
+        }
+
+        if clock.gameplay_frame < brain.next_decision {
+            continue;
+        }
+
+        let behaviour = brain.difficulty.behaviour(&settings.cpu).clone();
+        brain.next_decision = clock.gameplay_frame + behaviour.reaction_frames;
+
+        // Hurt players count as closer, so it goes after whoever is easiest to finish off
+        let position = own_transform.translation.truncate();
+        let target = players
+            .iter()
+            .filter(|(identifier, ..)| identifier.player_id != *player_id)
+            .map(|(_, transform, _, health)| {
+                (transform.translation.truncate(), health_fraction(health))
+            })
+            .min_by(|(a, a_health), (b, b_health)| {
+                (a.distance(position) * (1.0 + a_health))
+                    .partial_cmp(&(b.distance(position) * (1.0 + b_health)))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+
+        let (target, target_health) = match target {
+            None => {
+                brain.hold(*player_id, vec![], &mut input_queue);
+                continue;
+            }
+            Some(target) => target,
+        };
+
+        // Trading hits with someone healthier would only lose, so it keeps its distance instead
+        let retreating = own_health < behaviour.retreat_health && target_health > own_health;
+
+        let height = settings.window.height;
+        let offset = target - position;
+        let attack_range = behaviour.attack_range * height;
+        let lookahead = settings.cpu.edge_lookahead * height;
+        let look_distance = settings.cpu.edge_look_distance * height;
+
+        let mut wanted = vec![];
+        let mut jump = false;
+
+        let danger_below = behaviour.avoid_edges
+            && is_dangerous(&rapier_context, position, look_distance, &map_colliders);
+
+        if danger_below {
+            // Head for whichever side has ground under it and try to jump back onto it
+            let left_safe = !is_dangerous(
+                &rapier_context,
+                position - Vec2::new(lookahead, 0.0),
+                look_distance,
+                &map_colliders,
+            );
+            wanted.push(if left_safe {
+                InputPurpose::Left
+            } else {
+                InputPurpose::Right
+            });
+            jump = true;
+        } else if retreating {
+            let (purpose, direction) = if offset.x < 0.0 {
+                (InputPurpose::Right, 1.0)
+            } else {
+                (InputPurpose::Left, -1.0)
+            };
+
+            // Backed up against an edge it stands its ground rather than falling off
+            let edge_behind = behaviour.avoid_edges
+                && is_dangerous(
+                    &rapier_context,
+                    position + Vec2::new(lookahead * direction, 0.0),
+                    look_distance,
+                    &map_colliders,
+                );
+            if !edge_behind {
+                wanted.push(purpose);
+            }
+        } else if offset.x.abs() > attack_range * 0.5 {
+            let (purpose, direction) = if offset.x < 0.0 {
+                (InputPurpose::Left, -1.0)
+            } else {
+                (InputPurpose::Right, 1.0)
+            };
+
+            let edge_ahead = behaviour.avoid_edges
+                && is_dangerous(
+                    &rapier_context,
+                    position + Vec2::new(lookahead * direction, 0.0),
+                    look_distance,
+                    &map_colliders,
+                );
+
+            // Only follow over an edge if the target is up high enough to jump across to
+            if !edge_ahead || offset.y > settings.cpu.jump_height * height {
+                wanted.push(purpose);
+                jump = edge_ahead;
+            }
+        }
+
+        if offset.y > settings.cpu.jump_height * height && !retreating {
+            jump = true;
+        }
+
+        // Letting go of up for a decision is what allows the next jump to happen
+        if jump && descriptor.available_jumps > 0 && !brain.held.contains(&InputPurpose::Up) {
+            wanted.push(InputPurpose::Up);
+        }
+
+        brain.hold(*player_id, wanted, &mut input_queue);
+
+        let rng = brain
+            .rng
+            .get_or_insert_with(|| StdRng::seed_from_u64(game.seed ^ *player_id));
+
+        if offset.length() > attack_range
+            || !brain.pending.is_empty()
+            || rng.gen::<f64>() > behaviour.attack_chance
+        {
+            continue;
+        }
+
+        let combos = match char_map.get(player_id) {
+            None => continue,
+            Some(char_entities) => &char_entities.combos.combos,
+        };
+
+        // Combos that need a direction held are left to the players
+        let cooldowns = attack_directory.cooldown.get(player_id);
+        let ready: Vec<Vec<AttackKey>> = combos
+            .iter()
+            .enumerate()
+            .filter(|(combo_id, combo)| {
+                let cooling_down = match cooldowns {
+                    None => false,
+                    Some(cooldowns) => cooldowns.contains_key(combo_id),
+                };
+                !cooling_down
+                    && combo
+                        .directions
+                        .iter()
+                        .all(|direction| *direction == ComboDirection::Any)
+            })
+            .filter_map(|(_, combo)| combo.attack_keys())
+            .collect();
+
+        if ready.is_empty() {
+            continue;
+        }
+
+        // Each key is its own press so the attack buffer sees them one at a time
+        for key in ready[rng.gen_range(0..ready.len())].iter() {
+            let purpose = match key {
+                AttackKey::One => InputPurpose::Atk1,
+                AttackKey::Two => InputPurpose::Atk2,
+            };
+            brain
+                .pending
+                .push_back((purpose, InputKeyboardType::JustPressed));
+            brain
+                .pending
+                .push_back((purpose, InputKeyboardType::JustReleased));
+        }
+    }
+}
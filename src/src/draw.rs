@@ -20,20 +20,24 @@ use crate::background::create_bgs;
 use crate::char::VictoryEvent;
 use crate::collider::AACollider;
 use crate::collider::*;
+use crate::headless::Headless;
 use crate::maps::{Map, SpriteAsset};
+use crate::replay::ReplayPlayer;
 use crate::universal::*;
 use crate::{
-    AppStates, AssetDirectory, AugmentedFonts, CharComponentMap, CharacterInputMap, ConvertToRgb,
-    Game, GameSettings, MapComponent, PlayerIdentifier, ProjectileIdentifier, ToPrimitive,
+    AppStates, AssetDirectory, AttackInstanceDirectory, AugmentedFonts, CharComponentMap,
+    CharacterInputMap, ConvertToRgb, Game, GameClock, GameSettings, GamepadInputMap, MapComponent, PlayerIdentifier, ProjectileIdentifier, ToPrimitive,
 };
 
-pub fn get_resolution(windows: Res<Windows>) -> Vec2 {
-    let primary_window = windows.get_primary().unwrap();
-
-    Vec2::new(
-        primary_window.width() as f32,
-        primary_window.height() as f32,
-    )
+pub fn get_resolution(windows: Res<Windows>, window_descriptor: &Res<WindowDescriptor>) -> Vec2 {
+    match windows.get_primary() {
+        Some(primary_window) => Vec2::new(
+            primary_window.width() as f32,
+            primary_window.height() as f32,
+        ),
+        // Headless so there's no window, go off of what the window would have been
+        None => Vec2::new(window_descriptor.width, window_descriptor.height),
+    }
 }
 
 pub struct GameRounds {
@@ -51,12 +55,12 @@ pub struct GameCountdownTextId {
 
 pub fn hide_update_game_text(
     mut countdown_text_query: Query<(&mut Visibility, &GameCountdownTextId)>,
-    time: Res<Time>,
+    clock: Res<GameClock>,
     settings: Res<GameSettings>,
 ) {
     for (mut visibility, id) in countdown_text_query.iter_mut() {
         let seconds_left = (settings.gameplay_settings.countdown_disappear
-            - (time.seconds_since_startup() - id.fin_time))
+            - (clock.seconds() - id.fin_time))
             .ceil();
 
         if seconds_left <= 0.0 {
@@ -71,36 +75,47 @@ pub struct VictoryText {
     pub total_seconds: f64,
 }
 
+// Returns the state to go to if the map is changing
 pub fn change_map(
     mut game_resource: &mut ResMut<Game>,
     mut rounds: &mut ResMut<GameRounds>,
     settings: &Res<GameSettings>,
-    map_query: &Query<(Entity), (With<MapComponent>)>,
+    map_query: &Query<(Entity), (With<MapComponent>, Without<Parent>)>,
     commands: &mut Commands,
-) -> bool {
+    can_vote: bool,
+) -> Option<AppStates> {
     let total_vic_len = rounds.total_victories.len() as u64;
     if (total_vic_len - rounds.previous_map_change)
-        >= settings.gameplay_settings.rounds_to_map_change
+        < settings.gameplay_settings.rounds_to_map_change
     {
-        rounds.previous_map_change = total_vic_len;
-        println!(
-            "length of available maps: {:#?}",
-            game_resource.available_maps.len()
-        );
-        game_resource.selected_map = game_resource
-            .available_maps
-            .choose(&mut rand::thread_rng())
-            .unwrap()
-            .clone();
-        println!("{:#?}", game_resource.selected_map.info.display_name);
-        for (map_entity) in map_query.iter() {
-            commands.entity(map_entity).despawn_recursive()
-        }
-        commands.remove_resource::<AssetDirectory>();
-        true
-    } else {
-        false
+        return None;
     }
+
+    rounds.previous_map_change = total_vic_len;
+    println!(
+        "length of available maps: {:#?}",
+        game_resource.available_maps.len()
+    );
+
+    // Anything parented, like a platform's colliders, goes along with what its attached to
+    for (map_entity) in map_query.iter() {
+        commands.entity(map_entity).despawn_recursive()
+    }
+    commands.remove_resource::<AssetDirectory>();
+
+    let game = &mut **game_resource;
+    match game
+        .map_rotation
+        .next_map(&game.available_maps, &game.selected_map, &mut game.rng)
+    {
+        Some(next_map) => game.selected_map = next_map,
+        None if can_vote => return Some(AppStates::MapSelect),
+        // Nobody can vote without the menus, so just go random
+        None => game.selected_map = game.available_maps.choose(&mut game.rng).unwrap().clone(),
+    }
+    println!("{:#?}", game.selected_map.info.display_name);
+
+    Some(AppStates::LoadAssets)
 }
 
 pub fn victory_screen(
@@ -108,7 +123,7 @@ pub fn victory_screen(
     game_rounds: Res<GameRounds>,
     font: Res<AugmentedFonts>,
     mut commands: Commands,
-    time: Res<Time>,
+    clock: Res<GameClock>,
     settings: Res<GameSettings>,
 ) {
     let text = match game_rounds.previous_victory {
@@ -159,14 +174,14 @@ pub fn victory_screen(
             });
         })
         .insert(VictoryText {
-            created_timestamp: time.seconds_since_startup(),
+            created_timestamp: clock.seconds(),
             total_seconds: settings.gameplay_settings.victory_disappear,
         });
 }
 
 pub fn spawn_game_victory(
     text_query: Query<(Entity), (With<CountDownTextNode>)>,
-    map_query: Query<(Entity), (With<MapComponent>)>,
+    map_query: Query<(Entity), (With<MapComponent>, Without<Parent>)>,
     player_query: Query<(Entity, &ColliderSyncEntity), (With<PlayerIdentifier>)>,
     victory_query: Query<
         (Entity, &VictoryText),
@@ -176,13 +191,16 @@ pub fn spawn_game_victory(
     mut commands: Commands,
     mut game_resource: ResMut<Game>,
     mut rounds: ResMut<GameRounds>,
-    time: Res<Time>,
+    mut attack_directory: ResMut<AttackInstanceDirectory>,
+    clock: Res<GameClock>,
     mut state: ResMut<State<AppStates>>,
     settings: Res<GameSettings>,
+    headless: Option<Res<Headless>>,
+    replay_player: Option<Res<ReplayPlayer>>,
 ) {
     for (victory_entity, text) in victory_query.iter() {
         let seconds_left = (settings.gameplay_settings.victory_disappear
-            - (time.seconds_since_startup() - text.created_timestamp))
+            - (clock.seconds() - text.created_timestamp))
             .ceil();
 
         if seconds_left > 0.0 {
@@ -194,18 +212,19 @@ pub fn spawn_game_victory(
 
     //Next round
 
-    if change_map(
+    let can_vote = headless.is_none() && replay_player.is_none() && !settings.netplay.enabled;
+    match change_map(
         &mut game_resource,
         &mut rounds,
         &settings,
         &map_query,
         &mut commands,
+        can_vote,
     ) {
         // reload assets so we get the correct assets for our new map
-        state.set(AppStates::LoadAssets);
-    } else {
-        state.set(AppStates::LoadChar);
-    }
+        Some(next_state) => state.set(next_state),
+        None => state.set(AppStates::LoadChar),
+    };
 
     for (entity, sync) in player_query.iter() {
         sync.despawn_self(&mut commands);
@@ -221,23 +240,29 @@ pub fn spawn_game_victory(
         commands.entity(entity).despawn_recursive();
     }
 
+    // Attacks still going would carry on with the next round's characters
+    attack_directory.unexecuted_actions.clear();
+    attack_directory.attack_instances.clear();
+
     // Despawn all unwanted resources
     commands.remove_resource::<CharComponentMap>();
     commands.remove_resource::<CharacterInputMap>();
+    commands.remove_resource::<GamepadInputMap>();
     // Insert new versions of the resources
     commands.insert_resource(CharComponentMap::new());
     commands.insert_resource(CharacterInputMap::new());
+    commands.insert_resource(GamepadInputMap::default());
 }
 
 pub fn update_game_countdown_to_start(
     mut countdown_text_query: Query<(&mut Text, &mut GameCountdownTextId)>,
-    time: Res<Time>,
+    clock: Res<GameClock>,
     settings: Res<GameSettings>,
     mut state: ResMut<State<AppStates>>,
 ) {
     for (mut countdown_text, mut id) in countdown_text_query.iter_mut() {
         let seconds_left =
-            (id.total_seconds - (time.seconds_since_startup() - id.created_timestamp)).ceil();
+            (id.total_seconds - (clock.seconds() - id.created_timestamp)).ceil();
         let mut seconds_text = format!("{}", seconds_left.to_u64().unwrap_or(0));
 
         if seconds_left <= 0.0 {
@@ -249,7 +274,7 @@ pub fn update_game_countdown_to_start(
         }
 
         if seconds_left <= 0.0 {
-            id.fin_time = time.seconds_since_startup();
+            id.fin_time = clock.seconds();
             state.set(AppStates::LoadGame);
         }
     }
@@ -262,7 +287,7 @@ pub fn spawn_game_countdown_to_start(
     game: Res<Game>,
     font: Res<AugmentedFonts>,
     mut commands: Commands,
-    time: Res<Time>,
+    clock: Res<GameClock>,
     settings: Res<GameSettings>,
 ) {
     commands
@@ -313,7 +338,7 @@ pub fn spawn_game_countdown_to_start(
                     ..Default::default()
                 })
                 .insert(GameCountdownTextId {
-                    created_timestamp: time.seconds_since_startup(),
+                    created_timestamp: clock.seconds(),
                     total_seconds: settings.gameplay_settings.countdown_to_start_time,
                     fin_time: 0.0,
                 });
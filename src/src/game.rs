@@ -2,16 +2,24 @@ use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
 
 use crate::assets::AssetType;
-use crate::char::{AttackBuffer, CharEntities, Character};
+use crate::audio::{CharacterSound, CharacterSoundEvent};
+use crate::char::{AttackBuffer, CharEntities, Character, DirectionFacingFlags, PlayerStocks};
 use crate::collider::{
     DeathColliderIdentifier, JumpResetColliderIdentifier, SolidColliderIdentifier,
 };
-use crate::projectile::ProjectileIdentifier;
+use crate::cpu::CpuDifficulty;
+use crate::headless::Headless;
+use crate::maps::MapRotation;
+use crate::netplay::NetplaySession;
+use crate::projectile::{take_damage, ProjectileIdentifier};
+use crate::projectile_behaviour::ProjectileBehaviour;
 use crate::rigidbody::PhysicsSpawnExtras;
+use crate::shield::{ParryEffect, PlayerShield, ShieldBlock};
 use crate::{
-    AAPlayerDescriptor, AttackInstanceDirectory, CharComponentMap, ColliderSyncEntity,
+    AAPlayerDescriptor, AppStates, AttackInstanceDirectory, CharComponentMap, ColliderSyncEntity,
     DirectionFacingFlags, Map, PlayerHealth, PlayerIdentifier, QueryEntityError,
 };
+use bevy::ecs::schedule::ShouldRun;
 use bevy::prelude::*;
 use bevy_inspector_egui::Inspectable;
 use bevy_rapier2d::prelude::*;
@@ -19,6 +27,7 @@ use bevy_rapier2d::rapier::geometry::CollisionEventFlags;
 use bevy_svg::prelude::Svg;
 use igd::aio::Gateway;
 use igd::{AddPortError, PortMappingProtocol, SearchOptions};
+use rand::rngs::StdRng;
 use serde::{Deserialize, Serialize};
 use std::io::Write;
 use tokio::net::{TcpListener, UdpSocket};
@@ -28,6 +37,14 @@ pub struct Game {
     pub available_characters: Vec<Character>,
     pub selected_map: Map,
     pub available_maps: Vec<Map>,
+
+    // Seed for every random choice made during the match so both sides of a netplay match pick the same maps
+    pub seed: u64,
+    pub rng: StdRng,
+    pub map_rotation: MapRotation,
+
+    // Taken from the settings when the match starts, netplay and replays bring their own
+    pub ruleset: RulesetSettings,
 }
 
 pub enum CollisionEventType {
@@ -110,6 +127,7 @@ pub fn single_update_player_death(
         &mut AAPlayerDescriptor,
         &mut PlayerHealth,
     )>,
+    stocks_query: &Query<&mut PlayerStocks>,
     collision_type: CollisionEventType,
 ) {
     // Check if its a jump reset collider else return skipping having to query for the player identifier
@@ -126,6 +144,11 @@ pub fn single_update_player_death(
                     health.current_health = 0.0;
                 }
                 CollisionEventType::Stopped => {
+                    // Respawning is what moves them out of it, that shouldnt cost another stock
+                    if stocks_query.contains(player_body) {
+                        return;
+                    }
+
                     let (_player_id, _player_desc, mut health) =
                         player_query.get_mut(player_body).unwrap();
                     health.current_health = 0.0;
@@ -256,6 +279,11 @@ pub fn projectile_hit_collision_event(
     )>,
     parent_query: &Query<&Parent>,
     solid_collider_query: &Query<&SolidColliderIdentifier>,
+    knockback_query: &mut Query<(&mut Velocity, &Transform, Option<&mut AttackBuffer>)>,
+    stocks_query: &mut Query<&mut PlayerStocks>,
+    shield_query: &mut Query<&mut PlayerShield>,
+    clock: &Res<GameClock>,
+    sound_events: &mut EventWriter<CharacterSoundEvent>,
 ) {
     let projectile_entity = match parent_query.get(projectile_entity) {
         Ok(parent) => parent.0,
@@ -273,6 +301,11 @@ pub fn projectile_hit_collision_event(
                 return;
             };
 
+            // Every hit it had has been used up, so it passes straight through
+            if projectile_id.pierce_left() == 0 {
+                return;
+            }
+
             //check if its a solid collider since we dont want collision to happen with jump reset colliders:
             if !solid_collider_query.contains(char_entity) {
                 return;
@@ -290,24 +323,241 @@ pub fn projectile_hit_collision_event(
                 }
             };
 
-            projectile_id.apply_projectile(&mut health, &mut player_id, &mut player_desc);
+            let victim = parent.0;
+            let mut stocks = stocks_query.get_mut(victim).ok();
+
+            // Every collider of the victim touches a melee hitbox, so only the first one that comes in counts
+            if let Some(melee) = projectile_id.melee {
+                if !melee.can_hit(projectile_id.last_hits.get(&victim), clock) {
+                    return;
+                }
+            }
+            projectile_id.last_hits.insert(victim, clock.gameplay_frame);
+
+            let shield_block = match shield_query.get_mut(victim) {
+                Ok(mut player_shield) => {
+                    player_shield.block(projectile_id.damage, &mut player_desc, clock)
+                }
+                Err(_) => ShieldBlock::Unblocked,
+            };
+
+            match shield_block {
+                ShieldBlock::Unblocked => {}
+                // Blocked hits still use up pierce but dont knock back or stun
+                ShieldBlock::Blocked(damage) => {
+                    take_damage(damage, &mut health, stocks.as_mut());
+                    projectile_id.use_pierce();
+                    return;
+                }
+                // Melee hitboxes are stuck to the attacker so they cant be sent back, they just get negated
+                ShieldBlock::Parried(ParryEffect::Reflect) if projectile_id.melee.is_none() => {
+                    // Whatever it does from here on is down to the reflector, splits and kills included
+                    projectile_id.parent = victim;
+                    projectile_id.owner = player_id.player_id;
+                    if let Ok((mut velocity, _, _)) = knockback_query.get_mut(projectile_entity) {
+                        velocity.linvel = -velocity.linvel;
+                    }
+                    return;
+                }
+                ShieldBlock::Parried(_) => {
+                    // Gone the next time projectile_lifetimes runs, without splitting or exploding on the way out
+                    projectile_id.lifetime = 0.0;
+                    projectile_id.behaviour = ProjectileBehaviour::default();
+                    return;
+                }
+            }
+
+            projectile_id.hit = true;
+            if !projectile_id.victims.contains(&victim) {
+                projectile_id.victims.push(victim);
+            }
+            if projectile_id.behaviour.ends_on_hit() {
+                projectile_id.lifetime = 0.0;
+            }
+
+            projectile_id.apply_projectile(
+                &mut health,
+                &mut player_id,
+                &mut player_desc,
+                stocks.as_mut(),
+                clock,
+            );
+
+            let (projectile_velocity, projectile_position) =
+                match knockback_query.get(projectile_entity) {
+                    Ok((velocity, transform, _)) => {
+                        (velocity.linvel, transform.translation.truncate())
+                    }
+                    Err(_) => (Vec2::ZERO, Vec2::ZERO),
+                };
+            // In percentage mode the percentage is what knockback grows with
+            let damage_taken = match stocks {
+                Some(stocks) => stocks.percentage,
+                None => health.maximum_health - health.current_health,
+            };
+            let in_hitstun = player_desc.in_hitstun(clock);
+
+            // The attacker might have died since firing it
+            let attacker = player_query
+                .get(projectile_id.parent)
+                .ok()
+                .map(|(attacker_id, attacker_desc, _)| {
+                    (attacker_id.player_id, attacker_desc.direction_facing)
+                });
+            let attacker_facing = attacker.map(|(_, facing)| facing);
+
+            if let Some((attacker_id, _)) = attacker {
+                sound_events.send(CharacterSoundEvent {
+                    player_id: attacker_id,
+                    sound: CharacterSound::Hit,
+                });
+            }
+
+            if let Ok((mut velocity, transform, attack_buffer)) = knockback_query.get_mut(victim) {
+                let away_from_hit = transform.translation.truncate() - projectile_position;
+                if let Some(launch_velocity) = projectile_id.knockback.launch_velocity(
+                    damage_taken,
+                    projectile_velocity,
+                    attacker_facing,
+                    away_from_hit,
+                ) {
+                    velocity.linvel = launch_velocity;
+                }
+
+                // Whatever combo they were in the middle of gets interrupted
+                if let (true, Some(mut attack_buffer)) = (in_hitstun, attack_buffer) {
+                    attack_buffer.reset();
+                }
+            }
         }
         Err(_projectile_err) => {}
     }
 }
 
 pub fn health_despawn_check(
-    projectile_query: Query<(Entity, &PlayerHealth, &ColliderSyncEntity)>,
+    projectile_query: Query<(
+        Entity,
+        &PlayerIdentifier,
+        &PlayerHealth,
+        &ColliderSyncEntity,
+        Option<&PlayerStocks>,
+    )>,
+    mut sound_events: EventWriter<CharacterSoundEvent>,
     mut commands: Commands,
 ) {
-    for (entity, player_health, collider_entity) in projectile_query.iter() {
-        if player_health.current_health <= 0.0 {
+    for (entity, player_id, player_health, collider_entity, stocks) in projectile_query.iter() {
+        // Players with stocks to spare get respawned by stock_loss_check instead
+        let last_stock = match stocks {
+            Some(stocks) => stocks.stocks <= 1,
+            None => true,
+        };
+
+        if player_health.current_health <= 0.0 && last_stock {
+            sound_events.send(CharacterSoundEvent {
+                player_id: player_id.player_id,
+                sound: CharacterSound::Death,
+            });
             collider_entity.despawn_self(&mut commands);
             commands.entity(entity).despawn_recursive();
         }
     }
 }
 
+// Half the size of the map in the world, set when the map gets loaded
+pub struct MapBounds {
+    pub half_extents: Vec2,
+}
+
+// Going this far past the edge of the map counts the same as touching a death collider
+pub fn out_of_bounds_check(
+    game: Res<Game>,
+    map_bounds: Option<Res<MapBounds>>,
+    mut player_query: Query<(&Transform, &mut PlayerHealth), With<PlayerStocks>>,
+) {
+    let map_bounds = match map_bounds {
+        None => return,
+        Some(map_bounds) => map_bounds,
+    };
+    let bounds = map_bounds.half_extents * (1.0 + game.ruleset.bounds_margin);
+
+    for (transform, mut health) in player_query.iter_mut() {
+        let position = transform.translation.truncate();
+        if position.x.abs() > bounds.x || position.y.abs() > bounds.y {
+            health.current_health = 0.0;
+        }
+    }
+}
+
+// Percentage mode only, a player on 0 health with stocks left goes back to their spawn with a clean slate
+pub fn stock_loss_check(
+    game: Res<Game>,
+    char_map: Res<CharComponentMap>,
+    mut player_query: Query<(
+        &PlayerIdentifier,
+        &mut PlayerHealth,
+        &mut PlayerStocks,
+        &mut Transform,
+        &mut Velocity,
+        &mut AAPlayerDescriptor,
+        &mut AttackBuffer,
+        Option<&mut PlayerShield>,
+    )>,
+    mut sound_events: EventWriter<CharacterSoundEvent>,
+) {
+    for (
+        player_id,
+        mut health,
+        mut stocks,
+        mut transform,
+        mut velocity,
+        mut descriptor,
+        mut attack_buffer,
+        player_shield,
+    ) in player_query.iter_mut()
+    {
+        if health.current_health > 0.0 || stocks.stocks <= 1 {
+            continue;
+        }
+
+        sound_events.send(CharacterSoundEvent {
+            player_id: player_id.player_id,
+            sound: CharacterSound::Death,
+        });
+
+        stocks.stocks -= 1;
+        stocks.percentage = 0.0;
+        health.current_health = health.maximum_health;
+
+        // Scaled the same way load_selected_characters places them at the start
+        let spawn_pos = match game
+            .selected_map
+            .spawn_positions
+            .positions
+            .get((player_id.player_id - 1) as usize)
+        {
+            Some(pos) => Vec2::from(*pos),
+            None => Vec2::new(0.0, 0.0),
+        };
+        let ratio = match char_map.get(&player_id.player_id) {
+            Some(char_entities) => char_entities.rescale_ratio.y,
+            None => 1.0,
+        };
+
+        transform.translation = (spawn_pos / ratio).extend(transform.translation.z);
+        transform.rotation = Quat::IDENTITY;
+        *velocity = Velocity::default();
+
+        descriptor.available_jumps = descriptor.maximum_jumps;
+        descriptor.hitstun_until = 0;
+        descriptor.deferred_releases.clear();
+        attack_buffer.reset();
+
+        if let Some(mut player_shield) = player_shield {
+            *player_shield = PlayerShield::new(player_shield.shield);
+        }
+    }
+}
+
 pub fn collision_process(
     mut collision_events: EventReader<CollisionEvent>,
     jump_reset_query: Query<&JumpResetColliderIdentifier>,
@@ -321,6 +571,11 @@ pub fn collision_process(
     )>,
     mut projectile_query: Query<(&mut ProjectileIdentifier)>,
     parent_query: Query<&Parent>,
+    mut knockback_query: Query<(&mut Velocity, &Transform, Option<&mut AttackBuffer>)>,
+    mut stocks_query: Query<&mut PlayerStocks>,
+    mut shield_query: Query<&mut PlayerShield>,
+    clock: Res<GameClock>,
+    mut sound_events: EventWriter<CharacterSoundEvent>,
 ) {
     for collision_event in collision_events.iter() {
         match collision_event {
@@ -360,6 +615,7 @@ pub fn collision_process(
                             other_collider,
                             &death_query,
                             &mut player_query,
+                            &stocks_query,
                             CollisionEventType::Started,
                         );
 
@@ -370,6 +626,11 @@ pub fn collision_process(
                             &mut player_query,
                             &parent_query,
                             &solid_collider_query,
+                            &mut knockback_query,
+                            &mut stocks_query,
+                            &mut shield_query,
+                            &clock,
+                            &mut sound_events,
                         );
                     }
                     CollisionPlayerType::Two(found, (player_one, player_two)) => {
@@ -449,9 +710,148 @@ pub struct CharacterInputSettings {
     pub attack_one: KeyCode,
     pub attack_two: KeyCode,
     pub reset: KeyCode,
+
+    // Controller bindings for the same player, the keyboard keeps working alongside it
+    #[serde(default)]
+    pub gamepad: Option<GamepadInputSettings>,
 }
 pub type CharacterInputMap = HashMap<KeyCode, Vec<(u64, InputPurpose)>>;
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct GamepadInputSettings {
+    // Gamepads are numbered in the order they were connected, starting from 0
+    pub gamepad: usize,
+    pub up_button: Option<GamepadButtonType>,
+    pub down_button: Option<GamepadButtonType>,
+    pub left_button: Option<GamepadButtonType>,
+    pub right_button: Option<GamepadButtonType>,
+    pub attack_one: Option<GamepadButtonType>,
+    pub attack_two: Option<GamepadButtonType>,
+    pub reset: Option<GamepadButtonType>,
+    pub stick_x: Option<GamepadAxisType>,
+    pub stick_y: Option<GamepadAxisType>,
+
+    // How far a stick has to be pushed before it counts as a direction being pressed
+    pub stick_deadzone: f32,
+}
+
+impl GamepadInputSettings {
+    pub fn new(gamepad: usize) -> Self {
+        Self {
+            gamepad,
+            ..Default::default()
+        }
+    }
+
+    pub fn buttons(&self) -> Vec<(GamepadButton, InputPurpose)> {
+        let gamepad = Gamepad(self.gamepad);
+        [
+            (self.up_button, InputPurpose::Up),
+            (self.down_button, InputPurpose::Down),
+            (self.left_button, InputPurpose::Left),
+            (self.right_button, InputPurpose::Right),
+            (self.attack_one, InputPurpose::Atk1),
+            (self.attack_two, InputPurpose::Atk2),
+            (self.reset, InputPurpose::Reset),
+        ]
+        .into_iter()
+        .filter_map(|(button, purpose)| {
+            button.map(|button| (GamepadButton(gamepad, button), purpose))
+        })
+        .collect()
+    }
+
+    // Where the stick is pushed to, either axis can be left unbound
+    pub fn stick_deflection(&self, gamepad_axes: &Axis<GamepadAxis>) -> Vec2 {
+        let gamepad = Gamepad(self.gamepad);
+        let deflection = |axis: Option<GamepadAxisType>| {
+            axis.and_then(|axis| gamepad_axes.get(GamepadAxis(gamepad, axis)))
+                .unwrap_or(0.0)
+        };
+
+        Vec2::new(deflection(self.stick_x), deflection(self.stick_y))
+    }
+
+    // Used to join in the player identify menu, the same as pressing up on a keyboard.
+    // The stick only counts on the update it gets pushed up, stick_was_up keeps track of where it was before
+    pub fn up_pressed(
+        &self,
+        gamepad_buttons: &Input<GamepadButton>,
+        gamepad_axes: &Axis<GamepadAxis>,
+        stick_was_up: &mut bool,
+    ) -> bool {
+        let gamepad = Gamepad(self.gamepad);
+
+        let button_pressed = match self.up_button {
+            None => false,
+            Some(button) => gamepad_buttons.just_pressed(GamepadButton(gamepad, button)),
+        };
+
+        let stick_up = stick_facing(self.stick_deflection(gamepad_axes), self.stick_deadzone)
+            .contains(DirectionFacingFlags::UP);
+        let stick_pushed = stick_up && !*stick_was_up;
+        *stick_was_up = stick_up;
+
+        button_pressed || stick_pushed
+    }
+}
+
+// The directions a stick is pushed in, diagonals come from its angle so they dont need both axes pushed past the deadzone
+pub fn stick_facing(deflection: Vec2, deadzone: f32) -> DirectionFacingFlags {
+    let mut facing = DirectionFacingFlags::empty();
+    let length = deflection.length();
+    if length <= deadzone {
+        return facing;
+    }
+
+    // Within 22.5 degrees of an axis only counts as that one direction
+    let threshold = length * (std::f32::consts::PI / 8.0).sin();
+    facing.set(DirectionFacingFlags::UP, deflection.y > threshold);
+    facing.set(DirectionFacingFlags::DOWN, deflection.y < -threshold);
+    facing.set(DirectionFacingFlags::LEFT, deflection.x < -threshold);
+    facing.set(DirectionFacingFlags::RIGHT, deflection.x > threshold);
+    facing
+}
+
+pub const STICK_DIRECTIONS: [(DirectionFacingFlags, InputPurpose); 4] = [
+    (DirectionFacingFlags::UP, InputPurpose::Up),
+    (DirectionFacingFlags::DOWN, InputPurpose::Down),
+    (DirectionFacingFlags::LEFT, InputPurpose::Left),
+    (DirectionFacingFlags::RIGHT, InputPurpose::Right),
+];
+
+impl Default for GamepadInputSettings {
+    fn default() -> Self {
+        Self {
+            gamepad: 0,
+            up_button: Some(GamepadButtonType::South),
+            down_button: Some(GamepadButtonType::DPadDown),
+            left_button: Some(GamepadButtonType::DPadLeft),
+            right_button: Some(GamepadButtonType::DPadRight),
+            attack_one: Some(GamepadButtonType::West),
+            attack_two: Some(GamepadButtonType::North),
+            reset: Some(GamepadButtonType::Select),
+            stick_x: Some(GamepadAxisType::LeftStickX),
+            stick_y: Some(GamepadAxisType::LeftStickY),
+            stick_deadzone: 0.5,
+        }
+    }
+}
+
+// A player's stick, the directions it's pushed in are held down the same as their direction buttons would be
+pub struct GamepadStickBinding {
+    pub player_id: u64,
+    pub settings: GamepadInputSettings,
+    pub held: DirectionFacingFlags,
+}
+
+#[derive(Default)]
+pub struct GamepadInputMap {
+    pub buttons: HashMap<GamepadButton, Vec<(u64, InputPurpose)>>,
+    pub sticks: Vec<GamepadStickBinding>,
+}
+
 pub trait InputCharacter {
     fn add_character_inputs(&mut self, input: CharacterInputSettings, id: u64);
 }
@@ -474,6 +874,41 @@ impl IntoIterator for CharacterInputSettings {
     }
 }
 
+impl CharacterInputSettings {
+    pub fn set_key(&mut self, purpose: InputPurpose, key: KeyCode) {
+        match purpose {
+            InputPurpose::Up => self.up_button = key,
+            InputPurpose::Down => self.down_button = key,
+            InputPurpose::Left => self.left_button = key,
+            InputPurpose::Right => self.right_button = key,
+            InputPurpose::Atk1 => self.attack_one = key,
+            InputPurpose::Atk2 => self.attack_two = key,
+            InputPurpose::Reset => self.reset = key,
+        }
+    }
+}
+
+impl InputCharacter for GamepadInputMap {
+    fn add_character_inputs(&mut self, input: CharacterInputSettings, id: u64) {
+        let gamepad_settings = match input.gamepad {
+            None => return,
+            Some(gamepad_settings) => gamepad_settings,
+        };
+
+        for (button, action) in gamepad_settings.buttons() {
+            self.buttons.entry(button).or_insert(vec![]).push((id, action));
+        }
+
+        if gamepad_settings.stick_x.is_some() || gamepad_settings.stick_y.is_some() {
+            self.sticks.push(GamepadStickBinding {
+                player_id: id,
+                settings: gamepad_settings,
+                held: DirectionFacingFlags::empty(),
+            });
+        }
+    }
+}
+
 impl InputCharacter for CharacterInputMap {
     fn add_character_inputs(&mut self, mut input: CharacterInputSettings, id: u64) {
         for (button, action) in input {
@@ -487,7 +922,7 @@ impl InputCharacter for CharacterInputMap {
     }
 }
 
-#[derive(Inspectable, Debug, Copy, Clone)]
+#[derive(Inspectable, Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
 pub enum InputPurpose {
     Up,
     Down,
@@ -520,6 +955,22 @@ pub struct GameplaySettings {
     pub countdown_disappear: f64,
     pub victory_disappear: f64,
     pub rounds_to_map_change: u64,
+
+    // How many gameplay frames make up a second, physics is stepped once for each of them
+    #[serde(default = "default_tick_rate")]
+    pub tick_rate: f64,
+
+    // Most gameplay frames a single update can play to catch up after a slow frame, past this the game slows down instead
+    #[serde(default = "default_max_frames_per_update")]
+    pub max_frames_per_update: u64,
+}
+
+pub fn default_tick_rate() -> f64 {
+    60.0
+}
+
+pub fn default_max_frames_per_update() -> u64 {
+    4
 }
 
 impl Default for GameplaySettings {
@@ -530,6 +981,335 @@ impl Default for GameplaySettings {
             countdown_disappear: 0.5,
             victory_disappear: 1.0,
             rounds_to_map_change: 3,
+            tick_rate: default_tick_rate(),
+            max_frames_per_update: default_max_frames_per_update(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct NetplaySettings {
+    pub enabled: bool,
+    pub host: bool,
+    pub local_port: u16,
+    pub remote_address: String,
+
+    // How many frames local inputs are held back for, hides some of the latency before having to rollback
+    pub input_delay: u64,
+    pub max_rollback_frames: u64,
+}
+
+impl Default for NetplaySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: true,
+            local_port: 7000,
+            remote_address: "127.0.0.1:7001".to_string(),
+            input_delay: 2,
+            max_rollback_frames: 8,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ReplaySettings {
+    // Off unless asked for, nothing ever cleans the directory up so every match would leave a file behind
+    pub record: bool,
+    pub directory: String,
+
+    // Path of a replay to play back instead of going to the main menu, left empty to play normally
+    pub playback: String,
+}
+
+impl Default for ReplaySettings {
+    fn default() -> Self {
+        Self {
+            record: false,
+            directory: "replays".to_string(),
+            playback: String::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct HeadlessSettings {
+    // Display names of the characters to fight, the first two installed ones are used if this is empty
+    pub characters: Vec<String>,
+    pub rounds: u64,
+    pub frame_rate: f64,
+
+    // Every player is a cpu at this difficulty, unless agents are enabled to play them instead
+    pub cpu_difficulty: CpuDifficulty,
+
+    // Gameplay frames before the match is stopped without a winner, 0 lets it go on forever
+    pub max_frames: u64,
+}
+
+impl Default for HeadlessSettings {
+    fn default() -> Self {
+        Self {
+            characters: vec![],
+            rounds: 1,
+            frame_rate: 60.0,
+            cpu_difficulty: CpuDifficulty::Normal,
+            max_frames: 18000,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AudioSettings {
+    // Everything gets multiplied by this on top of its own volume
+    pub master_volume: f32,
+    pub music_volume: f32,
+    pub sfx_volume: f32,
+
+    // Played by the menus when moving between options and picking one, relative to the game folder.
+    // Empty ones are silent
+    pub ui_move: String,
+    pub ui_select: String,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            master_volume: 1.0,
+            music_volume: 0.6,
+            sfx_volume: 0.8,
+            ui_move: String::default(),
+            ui_select: String::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CameraSettings {
+    // Off keeps the camera still on the whole map, it still shakes
+    pub follow_players: bool,
+
+    // Space left around the players, as a fraction of the window height
+    pub padding: f32,
+
+    // How big the view can get compared to the window, under 1 is zoomed in.
+    // The map's camera bounds can stop it zooming out as far as max_zoom
+    pub min_zoom: f32,
+    pub max_zoom: f32,
+
+    // How quickly the camera catches up with the players, higher is snappier
+    pub smoothing: f32,
+
+    // Shake added by a hit and a death, a shake of 1 moves the camera by shake_distance of the window height
+    pub hit_shake: f32,
+    pub death_shake: f32,
+    pub shake_distance: f32,
+
+    // Shake that wears off every second
+    pub shake_decay: f32,
+
+    // Size of the text showing where off screen players are and how far it's kept from the edge,
+    // both as a fraction of the window height
+    pub indicator_size: f32,
+    pub indicator_margin: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            follow_players: true,
+            padding: 0.15,
+            min_zoom: 0.5,
+            max_zoom: 1.0,
+            smoothing: 4.0,
+            hit_shake: 0.3,
+            death_shake: 0.8,
+            shake_distance: 0.03,
+            shake_decay: 1.5,
+            indicator_size: 0.03,
+            indicator_margin: 0.05,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ContentSettings {
+    pub enabled: bool,
+
+    // Url or local path of the directory listing every map and character pack
+    pub directory: String,
+
+    // Keeps track of which directory version each downloaded pack came from
+    pub installed_path: String,
+}
+
+impl Default for ContentSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: "https://github.com/jean1398reborn/augmented-directory/raw/main/directory.json"
+                .to_string(),
+            installed_path: "installed_content.json".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AgentSettings {
+    pub enabled: bool,
+
+    // A tcp address, or unix: followed by a socket path
+    pub address: String,
+
+    // Gameplay only moves forward once every connected agent has answered the last observation
+    pub lock_step: bool,
+
+    // How many observations a connection can fall behind by before it starts skipping them
+    pub observation_backlog: usize,
+}
+
+impl Default for AgentSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: "127.0.0.1:7100".to_string(),
+            lock_step: false,
+            observation_backlog: 64,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CpuBehaviour {
+    // Frames between each decision, the cpu keeps doing what it was doing in between
+    pub reaction_frames: u64,
+
+    // Fraction of the window height the target has to be within before attacking
+    pub attack_range: f32,
+
+    // Chance of attacking on each decision made while in range
+    pub attack_chance: f64,
+    pub avoid_edges: bool,
+
+    // Below this fraction of its health the cpu backs away from anyone healthier than it
+    #[serde(default)]
+    pub retreat_health: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CpuSettings {
+    pub add_key: KeyCode,
+    pub difficulty_key: KeyCode,
+    pub default_difficulty: CpuDifficulty,
+
+    // How far ahead and down to look for ground, all fractions of the window height
+    pub edge_lookahead: f32,
+    pub edge_look_distance: f32,
+
+    // Targets higher than this above the cpu get jumped towards
+    pub jump_height: f32,
+
+    pub easy: CpuBehaviour,
+    pub normal: CpuBehaviour,
+    pub hard: CpuBehaviour,
+}
+
+impl Default for CpuSettings {
+    fn default() -> Self {
+        Self {
+            add_key: KeyCode::F1,
+            difficulty_key: KeyCode::F2,
+            default_difficulty: CpuDifficulty::Normal,
+            edge_lookahead: 0.05,
+            edge_look_distance: 1.0,
+            jump_height: 0.1,
+            easy: CpuBehaviour {
+                reaction_frames: 30,
+                attack_range: 0.15,
+                attack_chance: 0.2,
+                avoid_edges: false,
+                retreat_health: 0.0,
+            },
+            normal: CpuBehaviour {
+                reaction_frames: 15,
+                attack_range: 0.2,
+                attack_chance: 0.5,
+                avoid_edges: true,
+                retreat_health: 0.25,
+            },
+            hard: CpuBehaviour {
+                reaction_frames: 5,
+                attack_range: 0.25,
+                attack_chance: 0.9,
+                avoid_edges: true,
+                retreat_health: 0.35,
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum MapRotationMode {
+    Random,
+    // Goes through the playlist in order, every installed map is used if it's empty
+    Playlist,
+    // Every map gets played once before any of them repeat
+    Shuffle,
+    // The joined players pick the next map on the map select screen
+    Vote,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MapRotationSettings {
+    pub mode: MapRotationMode,
+
+    // Display names of the maps, in the order they get played
+    pub playlist: Vec<String>,
+}
+
+impl Default for MapRotationSettings {
+    fn default() -> Self {
+        Self {
+            mode: MapRotationMode::Random,
+            playlist: vec![],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Ruleset {
+    // Damage drains health and a player is out once it hits 0
+    Health,
+    // Damage raises a percentage that makes knockback stronger, stocks are only lost by leaving the map
+    Percentage,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct RulesetSettings {
+    pub ruleset: Ruleset,
+
+    // Lives each player starts with in percentage mode
+    pub stocks: u32,
+
+    // How far past the edges of the map a player can go before losing a stock, a fraction of the map's size
+    pub bounds_margin: f32,
+}
+
+impl Default for RulesetSettings {
+    fn default() -> Self {
+        Self {
+            ruleset: Ruleset::Health,
+            stocks: 3,
+            bounds_margin: 0.25,
         }
     }
 }
@@ -551,6 +1331,71 @@ pub struct GameSettings {
     pub main_menu: MainMenuConfig,
     pub player_id: PlayerIdentifyConfig,
     pub select_char: SelectCharacterConfig,
+
+    #[serde(default)]
+    pub netplay: NetplaySettings,
+
+    #[serde(default)]
+    pub replay: ReplaySettings,
+
+    #[serde(default)]
+    pub headless: HeadlessSettings,
+
+    #[serde(default)]
+    pub settings_menu: SettingsMenuConfig,
+
+    #[serde(default)]
+    pub map_rotation: MapRotationSettings,
+
+    #[serde(default)]
+    pub map_select: MapSelectConfig,
+
+    #[serde(default)]
+    pub content: ContentSettings,
+
+    #[serde(default)]
+    pub cpu: CpuSettings,
+
+    #[serde(default)]
+    pub agent: AgentSettings,
+
+    #[serde(default)]
+    pub ruleset: RulesetSettings,
+
+    #[serde(default)]
+    pub audio: AudioSettings,
+
+    #[serde(default)]
+    pub camera: CameraSettings,
+}
+
+impl GameSettings {
+    // Players are numbered from 1 like everywhere else
+    pub fn player_ctrls(&self, player_id: u64) -> &CharacterInputSettings {
+        match player_id {
+            1 => &self.p1_ctrls,
+            2 => &self.p2_ctrls,
+            3 => &self.p3_ctrls,
+            4 => &self.p4_ctrls,
+            5 => &self.p5_ctrls,
+            6 => &self.p6_ctrls,
+            7 => &self.p7_ctrls,
+            _ => &self.p8_ctrls,
+        }
+    }
+
+    pub fn player_ctrls_mut(&mut self, player_id: u64) -> &mut CharacterInputSettings {
+        match player_id {
+            1 => &mut self.p1_ctrls,
+            2 => &mut self.p2_ctrls,
+            3 => &mut self.p3_ctrls,
+            4 => &mut self.p4_ctrls,
+            5 => &mut self.p5_ctrls,
+            6 => &mut self.p6_ctrls,
+            7 => &mut self.p7_ctrls,
+            _ => &mut self.p8_ctrls,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -660,6 +1505,86 @@ impl Default for SelectCharacterConfig {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SettingsMenuConfig {
+    pub option_font_colour: [f32; 3],
+    pub option_font_size: f32,
+    pub option_window_down: f32,
+    pub option_individual_distance: f32,
+    pub highlight_colour: [f32; 3],
+    pub highlight_height: f32,
+    pub message_font_colour: [f32; 3],
+    pub message_window_down: f32,
+}
+
+impl Default for SettingsMenuConfig {
+    fn default() -> Self {
+        Self {
+            option_font_colour: [255.0, 255.0, 255.0],
+            option_font_size: 0.04,
+            option_window_down: 0.42,
+            option_individual_distance: -0.045,
+            highlight_colour: [212.0, 112.0, 24.0],
+            highlight_height: 0.045,
+            message_font_colour: [255.0, 80.0, 80.0],
+            message_window_down: -0.43,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MapSelectConfig {
+    pub title_text_size: f32,
+    pub title_text_colour: [f32; 3],
+    pub title_up_dist: f32,
+    pub title_highlight_height: f32,
+    pub title_highlight_colour: [f32; 3],
+    pub map_icon_size: [f32; 2],
+    pub icon_vertical_dist: f32,
+    pub map_name_text_size: f32,
+    pub map_name_colour: [f32; 3],
+    pub map_name_up_dist: f32,
+    pub map_info_text_size: f32,
+    pub map_info_colour: [f32; 3],
+    pub author_up_dist: f32,
+    pub description_up_dist: f32,
+    pub description_width: f32,
+    pub vote_text_size: f32,
+    pub vote_text_colour: [f32; 3],
+    pub voted_text_colour: [f32; 3],
+    pub vote_up_dist: f32,
+    pub vote_individual_distance: f32,
+}
+
+impl Default for MapSelectConfig {
+    fn default() -> Self {
+        Self {
+            title_text_size: 0.09,
+            title_text_colour: [255.0, 255.0, 255.0],
+            title_up_dist: 0.38,
+            title_highlight_height: 0.08,
+            title_highlight_colour: [1.0, 1.0, 1.0],
+            map_icon_size: [0.3, 0.3],
+            icon_vertical_dist: 0.12,
+            map_name_text_size: 0.07,
+            map_name_colour: [255.0, 255.0, 255.0],
+            map_name_up_dist: -0.08,
+            map_info_text_size: 0.035,
+            map_info_colour: [200.0, 200.0, 200.0],
+            author_up_dist: -0.14,
+            description_up_dist: -0.2,
+            description_width: 0.6,
+            vote_text_size: 0.035,
+            vote_text_colour: [255.0, 255.0, 255.0],
+            voted_text_colour: [212.0, 112.0, 24.0],
+            vote_up_dist: -0.28,
+            vote_individual_distance: -0.04,
+        }
+    }
+}
+
 impl Default for MainMenuConfig {
     fn default() -> Self {
         MainMenuConfig {
@@ -693,7 +1618,19 @@ impl Default for GameSettings {
             gameplay_settings: GameplaySettings::default(),
             main_menu: MainMenuConfig::default(),
             player_id: PlayerIdentifyConfig::default(),
-            select_char: SelectCharacterConfig::default()
+            select_char: SelectCharacterConfig::default(),
+            netplay: NetplaySettings::default(),
+            replay: ReplaySettings::default(),
+            headless: HeadlessSettings::default(),
+            settings_menu: SettingsMenuConfig::default(),
+            map_rotation: MapRotationSettings::default(),
+            map_select: MapSelectConfig::default(),
+            content: ContentSettings::default(),
+            cpu: CpuSettings::default(),
+            agent: AgentSettings::default(),
+            ruleset: RulesetSettings::default(),
+            audio: AudioSettings::default(),
+            camera: CameraSettings::default(),
         }
     }
 }
@@ -717,6 +1654,7 @@ pub fn p1_ctrl_default() -> CharacterInputSettings {
         attack_one: KeyCode::Key4,
         attack_two: KeyCode::R,
         reset: KeyCode::Key3,
+        gamepad: Some(GamepadInputSettings::new(0)),
     }
 }
 
@@ -729,6 +1667,7 @@ pub fn p2_ctrl_default() -> CharacterInputSettings {
         attack_one: KeyCode::Equals,
         attack_two: KeyCode::RBracket,
         reset: KeyCode::Minus,
+        gamepad: Some(GamepadInputSettings::new(1)),
     }
 }
 
@@ -741,6 +1680,7 @@ pub fn p3_ctrl_default() -> CharacterInputSettings {
         attack_one: KeyCode::Key8,
         attack_two: KeyCode::I,
         reset: KeyCode::Key7,
+        gamepad: Some(GamepadInputSettings::new(2)),
     }
 }
 
@@ -753,6 +1693,7 @@ pub fn p4_ctrl_default() -> CharacterInputSettings {
         attack_one: KeyCode::F,
         attack_two: KeyCode::V,
         reset: KeyCode::D,
+        gamepad: Some(GamepadInputSettings::new(3)),
     }
 }
 
@@ -765,6 +1706,7 @@ pub fn p5_ctrl_default() -> CharacterInputSettings {
         attack_one: KeyCode::Numpad2,
         attack_two: KeyCode::Numpad0,
         reset: KeyCode::Numpad1,
+        gamepad: Some(GamepadInputSettings::new(4)),
     }
 }
 
@@ -777,6 +1719,7 @@ pub fn p6_ctrl_default() -> CharacterInputSettings {
         attack_one: KeyCode::Apostrophe,
         attack_two: KeyCode::RShift,
         reset: KeyCode::Semicolon,
+        gamepad: Some(GamepadInputSettings::new(5)),
     }
 }
 
@@ -789,6 +1732,7 @@ pub fn p7_ctrl_default() -> CharacterInputSettings {
         attack_one: KeyCode::Numlock,
         attack_two: KeyCode::Numpad7,
         reset: KeyCode::PageUp,
+        gamepad: Some(GamepadInputSettings::new(6)),
     }
 }
 
@@ -801,6 +1745,7 @@ pub fn p8_ctrl_default() -> CharacterInputSettings {
         attack_one: KeyCode::NumpadAdd,
         attack_two: KeyCode::NumpadEnter,
         reset: KeyCode::Numpad6,
+        gamepad: Some(GamepadInputSettings::new(7)),
     }
 }
 
@@ -829,6 +1774,12 @@ pub fn recieve_settings() -> String {
     }
 }
 
+pub fn save_settings(settings: &GameSettings) -> anyhow::Result<()> {
+    let string_settings = toml::to_string_pretty(settings)?;
+    std::fs::write(SETTINGSPATH, string_settings)?;
+    Ok(())
+}
+
 #[derive(Deserialize, Serialize, Component, Debug, Copy, Clone, Inspectable)]
 #[serde(default)]
 pub struct VelocityForceCap {
@@ -849,10 +1800,25 @@ impl Default for VelocityForceCap {
     }
 }
 
-pub fn cap_velocity(mut query: Query<(&mut Velocity, &mut ExternalForce, &VelocityForceCap)>) {
-    for (mut velocity, mut external_force, cap) in query.iter_mut() {
+pub fn cap_velocity(
+    mut query: Query<(
+        &mut Velocity,
+        &mut ExternalForce,
+        &VelocityForceCap,
+        Option<&AAPlayerDescriptor>,
+    )>,
+    clock: Res<GameClock>,
+) {
+    for (mut velocity, mut external_force, cap, descriptor) in query.iter_mut() {
+        // Knockback would barely do anything if it got capped like normal movement
+        let in_hitstun = match descriptor {
+            None => false,
+            Some(descriptor) => descriptor.in_hitstun(&clock),
+        };
+
         velocity.linvel = match cap.max_velocity {
             None => velocity.linvel,
+            Some(_) if in_hitstun => velocity.linvel,
             Some(velocity_cap) => {
                 let velocity_cap_vec = Vec2::from(velocity_cap);
                 velocity
@@ -883,20 +1849,273 @@ pub fn cap_velocity(mut query: Query<(&mut Velocity, &mut ExternalForce, &Veloci
     }
 }
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
 pub enum InputKeyboardType {
     JustPressed,
     JustReleased,
     None,
 }
 
+// A single input for a player, it doesnt matter where it came from (keyboard, network etc.) they all end up as one of these
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct PlayerInput {
+    pub player_id: u64,
+    pub purpose: InputPurpose,
+    pub input_type: InputKeyboardType,
+}
+
+// Every input that should be applied to the characters this frame
+#[derive(Default)]
+pub struct PlayerInputQueue {
+    pub inputs: Vec<PlayerInput>,
+}
+
 pub struct AAGamePlayToggle {
     pub process_movement: bool,
 }
 
-pub fn movement_input_system(
+// Counts frames instead of reading the system clock, so timers come out the same at any fps and across pauses
+pub struct GameClock {
+    pub timestep: f64,
+
+    // Every frame since startup, keeps going through menus and countdowns
+    pub frame: u64,
+
+    // Only frames where gameplay is actually running
+    pub gameplay_frame: u64,
+    pub paused: bool,
+
+    // Real time that hasnt been played out as a frame yet, and how many frames this pass of the schedule plays,
+    // never more than one
+    pub accumulator: f64,
+    pub frames_due: u64,
+}
+
+impl GameClock {
+    pub fn new(tick_rate: f64) -> Self {
+        Self {
+            timestep: 1.0 / tick_rate,
+            frame: 0,
+            gameplay_frame: 0,
+            paused: true,
+            accumulator: 0.0,
+            frames_due: 0,
+        }
+    }
+
+    // Each pass through the schedule plays at most one frame, time left over carries on to the next pass
+    pub fn accumulate(&mut self, delta: f64) -> u64 {
+        self.accumulator += delta;
+        if self.accumulator < self.timestep {
+            return 0;
+        }
+
+        self.accumulator -= self.timestep;
+        1
+    }
+
+    pub fn frame_behind(&self) -> bool {
+        self.accumulator >= self.timestep
+    }
+
+    // Whole frames past the catch up limit are dropped rather than played later, so a long hitch doesnt turn into
+    // a burst of catch up
+    pub fn drop_behind_frames(&mut self) {
+        self.accumulator %= self.timestep;
+    }
+
+    pub fn advance(&mut self) {
+        self.frame += self.frames_due;
+        if !self.paused {
+            self.gameplay_frame += self.frames_due;
+        }
+    }
+
+    // How much gameplay time this update covers
+    pub fn frame_delta(&self) -> f64 {
+        self.frames_due as f64 * self.timestep
+    }
+
+    pub fn seconds(&self) -> f64 {
+        self.frame as f64 * self.timestep
+    }
+
+    pub fn gameplay_seconds(&self) -> f64 {
+        self.gameplay_frame as f64 * self.timestep
+    }
+}
+
+// Runs at the start of First so everything after it in the frame knows how many gameplay frames are due
+pub fn accumulate_game_clock(
+    time: Res<Time>,
+    settings: Res<GameSettings>,
+    headless: Option<Res<Headless>>,
+    mut clock: ResMut<GameClock>,
+    mut physics_config: ResMut<RapierConfiguration>,
+) {
+    // Headless matches and lock stepped agents play one frame every update however long it took
+    let one_per_update =
+        headless.is_some() || (settings.agent.enabled && settings.agent.lock_step);
+    let frames_due = match one_per_update {
+        true => 1,
+        false => clock.accumulate(time.delta_seconds_f64()),
+    };
+
+    // Physics only steps on passes that play a frame, the same as the rest of gameplay
+    clock.frames_due = frames_due;
+    physics_config.timestep_mode = TimestepMode::Fixed {
+        dt: clock.timestep as f32,
+        substeps: frames_due as usize,
+    };
+}
+
+// Runs the whole schedule again while gameplay is behind, so slower displays play every frame they owe instead of
+// the game slowing down. Every pass gets its own inputs, physics step and gameplay systems like a normal update
+pub fn catch_up_gameplay_frames(
+    settings: Res<GameSettings>,
+    mut clock: ResMut<GameClock>,
+    mut passes: Local<u64>,
+) -> ShouldRun {
+    // The first pass of an update always happens, even if it turns out there's no frame to play in it
+    if *passes == 0 {
+        *passes = 1;
+        return ShouldRun::YesAndCheckAgain;
+    }
+
+    if clock.frame_behind() && *passes < settings.gameplay_settings.max_frames_per_update.max(1) {
+        *passes += 1;
+        return ShouldRun::YesAndCheckAgain;
+    }
+
+    clock.drop_behind_frames();
+    *passes = 0;
+    ShouldRun::No
+}
+
+// Gameplay systems only run on passes with a frame to play, faster displays get some updates without one
+pub fn gameplay_frame_due(state: Res<State<AppStates>>, clock: Res<GameClock>) -> ShouldRun {
+    match state.current() == &AppStates::LoadGame && clock.frames_due > 0 {
+        true => ShouldRun::Yes,
+        false => ShouldRun::No,
+    }
+}
+
+// Collisions from a physics step that havent been handled yet. Events only last two updates, so on faster displays
+// they'd be gone before the next frame's gameplay systems get to run
+#[derive(Default)]
+pub struct HeldCollisionEvents {
+    pub events: Vec<CollisionEvent>,
+}
+
+pub fn hold_collision_events(
+    clock: Res<GameClock>,
+    netplay_session: Option<ResMut<NetplaySession>>,
+    mut collision_events: ResMut<Events<CollisionEvent>>,
+    mut held: ResMut<HeldCollisionEvents>,
+) {
+    if clock.frames_due == 0 {
+        held.events.extend(collision_events.drain());
+        return;
+    }
+
+    if held.events.is_empty() {
+        return;
+    }
+
+    for event in held.events.drain(..) {
+        collision_events.send(event);
+    }
+
+    // Netplay already took these for its snapshot when they first came in
+    if let Some(mut session) = netplay_session {
+        session.collision_reader = collision_events.get_reader_current();
+    }
+}
+
+pub fn advance_game_clock(mut clock: ResMut<GameClock>) {
+    clock.advance();
+}
+
+pub fn keyboard_input_system(
     keyboard_input: Res<Input<KeyCode>>,
     input_action_map: Res<CharacterInputMap>,
+    mut input_queue: ResMut<PlayerInputQueue>,
+) {
+    for (keycode, actions) in input_action_map.iter() {
+        let input_type = if keyboard_input.just_pressed(*keycode) {
+            println!("{:?} {:?}", keycode, actions);
+            InputKeyboardType::JustPressed
+        } else if keyboard_input.just_released(*keycode) {
+            InputKeyboardType::JustReleased
+        } else {
+            continue;
+        };
+
+        for (player_id, purpose) in actions {
+            input_queue.inputs.push(PlayerInput {
+                player_id: *player_id,
+                purpose: *purpose,
+                input_type,
+            });
+        }
+    }
+}
+
+pub fn gamepad_input_system(
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    mut gamepad_map: ResMut<GamepadInputMap>,
+    mut input_queue: ResMut<PlayerInputQueue>,
+) {
+    for (button, actions) in gamepad_map.buttons.iter() {
+        let input_type = if gamepad_buttons.just_pressed(*button) {
+            InputKeyboardType::JustPressed
+        } else if gamepad_buttons.just_released(*button) {
+            InputKeyboardType::JustReleased
+        } else {
+            continue;
+        };
+
+        for (player_id, purpose) in actions {
+            input_queue.inputs.push(PlayerInput {
+                player_id: *player_id,
+                purpose: *purpose,
+                input_type,
+            });
+        }
+    }
+
+    // Sticks act like the direction buttons, letting go of whatever it moved away from before pressing anything new
+    for stick in gamepad_map.sticks.iter_mut() {
+        let facing = stick_facing(
+            stick.settings.stick_deflection(&gamepad_axes),
+            stick.settings.stick_deadzone,
+        );
+
+        for (flag, purpose) in STICK_DIRECTIONS {
+            if stick.held.contains(flag) && !facing.contains(flag) {
+                input_queue.inputs.push(PlayerInput {
+                    player_id: stick.player_id,
+                    purpose,
+                    input_type: InputKeyboardType::JustReleased,
+                });
+            }
+        }
+        for (flag, purpose) in STICK_DIRECTIONS {
+            if facing.contains(flag) && !stick.held.contains(flag) {
+                input_queue.inputs.push(PlayerInput {
+                    player_id: stick.player_id,
+                    purpose,
+                    input_type: InputKeyboardType::JustPressed,
+                });
+            }
+        }
+        stick.held = facing;
+    }
+}
+
+pub fn movement_input_system(
+    mut input_queue: ResMut<PlayerInputQueue>,
     mut char_map: ResMut<CharComponentMap>,
     mut commands: Commands,
     mut char_query: Query<(
@@ -907,70 +2126,207 @@ pub fn movement_input_system(
         &mut AttackBuffer,
         &mut Transform,
     )>,
+    mut shield_query: Query<&mut PlayerShield>,
     mut attack_directory: ResMut<AttackInstanceDirectory>,
-    time: Res<Time>,
+    clock: Res<GameClock>,
     settings: Res<GameSettings>,
-    svg_test: Res<Assets<Svg>>,
     gameplay_toggle: Res<AAGamePlayToggle>,
 ) {
+    // Inputs wait in the queue for the next update that actually plays a frame
+    if clock.frames_due == 0 {
+        return;
+    }
+
+    // Always take the inputs out so they dont pile up while movement is paused
+    let inputs = std::mem::take(&mut input_queue.inputs);
+
     if !gameplay_toggle.process_movement {
         return;
     };
 
-    for (keycode, actions) in input_action_map.iter() {
-        let input_type = if keyboard_input.just_pressed(*keycode) {
-            println!("{:?} {:?}", keycode, actions);
-            InputKeyboardType::JustPressed
-        } else if keyboard_input.just_released(*keycode) {
-            InputKeyboardType::JustReleased
-        } else {
-            InputKeyboardType::None
+    // Buffers have to expire even for players that havent pressed anything this frame, a shorter combo can fire when they do
+    for char_components in char_map.values_mut() {
+        if let Ok((_velocity, _force, _damping, mut descriptor, mut attack_buffer, mut transform)) =
+            char_query.get_mut(char_components.core)
+        {
+            if let Some(combo_id) = attack_buffer.clear_expired(&char_components.combos, &clock) {
+                char_components.apply_attack(
+                    combo_id,
+                    &mut descriptor,
+                    &mut transform,
+                    &mut commands,
+                    &mut attack_directory,
+                    &clock,
+                );
+            }
+        }
+    }
+
+    // Keys let go of during hitstun finally get released now that it's over
+    for char_components in char_map.values_mut() {
+        let deferred_releases = match char_query.get_mut(char_components.core) {
+            Ok((_velocity, _force, _damping, mut descriptor, _attack_buffer, _transform)) => {
+                if descriptor.in_hitstun(&clock) || descriptor.deferred_releases.is_empty() {
+                    continue;
+                }
+                let deferred_releases = std::mem::take(&mut descriptor.deferred_releases);
+                descriptor
+                    .held_keys
+                    .retain(|purpose| !deferred_releases.contains(purpose));
+                deferred_releases
+            }
+            Err(_) => continue,
         };
 
-        for action in actions {
-            let player_id = action.0;
-            let mut char_components = match char_map.get_mut(&player_id) {
-                None => {
-                    panic!("Got input for non existant player id");
+        for purpose in deferred_releases {
+            if purpose == InputPurpose::Down {
+                if let Ok(mut player_shield) = shield_query.get_mut(char_components.core) {
+                    player_shield.lower();
                 }
-                Some(components) => components,
-            };
+            }
 
-            match char_query.get_mut(char_components.core) {
-                Ok((
-                    mut velocity,
-                    mut external_force,
-                    mut damping,
-                    mut player_descriptor,
-                    mut attack_buffer,
-                    mut transform,
-                )) => {
-                    // Attack Actions
-                    attack_buffer.add_to_buffer(input_type, action.1, &time);
-                    attack_buffer.execute_buffer(
-                        &mut char_components,
-                        &mut player_descriptor,
-                        &mut transform,
-                        &mut commands,
-                        &mut attack_directory,
-                        &time,
-                        &settings,
-                    );
+            if let Ok((velocity, external_force, damping, descriptor, _attack_buffer, _transform)) =
+                char_query.get_mut(char_components.core)
+            {
+                char_components.actions.apply_action(
+                    InputKeyboardType::JustReleased,
+                    purpose,
+                    velocity,
+                    external_force,
+                    damping,
+                    descriptor,
+                );
+            }
+        }
+    }
 
-                    // Movement Actions
-                    char_components.actions.apply_action(
-                        input_type,
-                        action.1,
-                        velocity,
-                        external_force,
-                        damping,
-                        player_descriptor,
-                    );
+    for input in inputs {
+        apply_player_input(
+            input,
+            &mut char_map,
+            &mut commands,
+            &mut char_query,
+            &mut shield_query,
+            &mut attack_directory,
+            &clock,
+            &settings,
+        );
+    }
+}
+
+pub fn apply_player_input(
+    input: PlayerInput,
+    char_map: &mut ResMut<CharComponentMap>,
+    commands: &mut Commands,
+    char_query: &mut Query<(
+        &mut Velocity,
+        &mut ExternalForce,
+        &mut Damping,
+        &mut AAPlayerDescriptor,
+        &mut AttackBuffer,
+        &mut Transform,
+    )>,
+    shield_query: &mut Query<&mut PlayerShield>,
+    attack_directory: &mut ResMut<AttackInstanceDirectory>,
+    clock: &Res<GameClock>,
+    settings: &Res<GameSettings>,
+) {
+    let mut char_components = match char_map.get_mut(&input.player_id) {
+        // Netplay packets and agents can name any player id, so ones that arent in the match get dropped
+        None => {
+            warn!("Got input for player {} who isnt in the match", input.player_id);
+            return;
+        }
+        Some(components) => components,
+    };
+
+    match char_query.get_mut(char_components.core) {
+        Ok((
+            mut velocity,
+            mut external_force,
+            mut damping,
+            mut player_descriptor,
+            mut attack_buffer,
+            mut transform,
+        )) => {
+            // A key pressed during hitstun never had its press go through, so letting go of it does nothing either
+            let held = player_descriptor.held_keys.contains(&input.purpose);
+            if input.input_type == InputKeyboardType::JustReleased && !held {
+                return;
+            }
+
+            // Nothing gets through during hitstun, letting go of a key waits until it's over
+            if player_descriptor.in_hitstun(clock) {
+                let deferred = &mut player_descriptor.deferred_releases;
+                match input.input_type {
+                    InputKeyboardType::JustReleased if !deferred.contains(&input.purpose) => {
+                        deferred.push(input.purpose)
+                    }
+                    // Pressed again before it was over, so it's still held down and there's nothing to let go of
+                    InputKeyboardType::JustPressed => {
+                        deferred.retain(|purpose| *purpose != input.purpose)
+                    }
+                    _ => {}
                 }
-                Err(error) => {}
-            };
+                return;
+            }
+
+            match input.input_type {
+                InputKeyboardType::JustPressed if !held => {
+                    player_descriptor.held_keys.push(input.purpose)
+                }
+                InputKeyboardType::JustReleased => player_descriptor
+                    .held_keys
+                    .retain(|purpose| *purpose != input.purpose),
+                _ => {}
+            }
+
+            // Down holds the shield up for characters that have one
+            let mut player_shield = shield_query.get_mut(char_components.core).ok();
+            if let (InputPurpose::Down, Some(player_shield)) = (input.purpose, &mut player_shield) {
+                match input.input_type {
+                    InputKeyboardType::JustPressed => player_shield.raise(clock),
+                    InputKeyboardType::JustReleased => player_shield.lower(),
+                    _ => {}
+                }
+            }
+
+            // Attack Actions
+            let combo_id = attack_buffer.add_to_buffer(
+                input.input_type,
+                input.purpose,
+                player_descriptor.direction_facing,
+                &char_components.combos,
+                clock,
+            );
+            if let Some(combo_id) = combo_id {
+                // Attacking drops the shield
+                if let Some(player_shield) = &mut player_shield {
+                    player_shield.lower();
+                }
+
+                char_components.apply_attack(
+                    combo_id,
+                    &mut player_descriptor,
+                    &mut transform,
+                    commands,
+                    attack_directory,
+                    clock,
+                );
+            }
+
+            // Movement Actions
+            char_components.actions.apply_action(
+                input.input_type,
+                input.purpose,
+                velocity,
+                external_force,
+                damping,
+                player_descriptor,
+            );
         }
-    }
+        Err(error) => {}
+    };
 }
 
 bitflags::bitflags! {
@@ -1079,3 +2435,143 @@ impl DirectionFacingFlags {
         full_dir_flags
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Real time that went by before the next update, the first pass through the schedule takes it
+    struct UpdateDelta(f64);
+
+    // Runs updates that each took delta seconds, returns how many gameplay frames got played
+    fn play_updates(delta: f64, updates: u64) -> u64 {
+        let mut world = World::new();
+        world.insert_resource(GameSettings::default());
+        world.insert_resource(GameClock::new(60.0));
+        world.insert_resource(UpdateDelta(0.0));
+
+        let mut schedule = Schedule::default();
+        schedule.set_run_criteria(IntoSystem::into_system(catch_up_gameplay_frames));
+        schedule.add_stage(
+            "frame",
+            // Stands in for accumulate_game_clock and advance_game_clock
+            SystemStage::single_threaded().with_system(
+                |mut clock: ResMut<GameClock>, mut delta: ResMut<UpdateDelta>| {
+                    let delta = std::mem::replace(&mut delta.0, 0.0);
+                    clock.frames_due = clock.accumulate(delta);
+                    clock.advance();
+                },
+            ),
+        );
+
+        for _ in 0..updates {
+            world.resource_mut::<UpdateDelta>().0 = delta;
+            schedule.run(&mut world);
+        }
+        world.resource::<GameClock>().frame
+    }
+
+    #[test]
+    fn slow_updates_catch_up() {
+        // 30 fps owes two frames every update at 60 ticks a second
+        assert_eq!(play_updates(1.0 / 30.0, 10), 20);
+        assert_eq!(play_updates(1.0 / 60.0, 10), 10);
+
+        // A fifth of a second is 12 frames, only max_frames_per_update of them get played and the rest are dropped
+        let cap = default_max_frames_per_update();
+        assert_eq!(play_updates(0.2, 3), 3 * cap);
+    }
+
+    #[test]
+    fn stick_angle_picks_directions() {
+        let facing = |x, y| stick_facing(Vec2::new(x, y), 0.5);
+        assert_eq!(facing(0.0, 0.4), DirectionFacingFlags::empty());
+        assert_eq!(facing(0.0, 0.9), DirectionFacingFlags::UP);
+        assert_eq!(facing(0.9, -0.2), DirectionFacingFlags::RIGHT);
+
+        // Neither axis is past the deadzone on its own here
+        assert_eq!(
+            facing(-0.45, 0.45),
+            DirectionFacingFlags::UP | DirectionFacingFlags::LEFT
+        );
+    }
+
+    fn stick_world(settings: GamepadInputSettings) -> World {
+        let mut world = World::new();
+        let mut gamepad_map = GamepadInputMap::default();
+        gamepad_map.add_character_inputs(
+            CharacterInputSettings {
+                up_button: KeyCode::W,
+                down_button: KeyCode::S,
+                left_button: KeyCode::A,
+                right_button: KeyCode::D,
+                attack_one: KeyCode::T,
+                attack_two: KeyCode::Y,
+                reset: KeyCode::R,
+                gamepad: Some(settings),
+            },
+            1,
+        );
+        world.insert_resource(gamepad_map);
+        world.insert_resource(Input::<GamepadButton>::default());
+        world.insert_resource(Axis::<GamepadAxis>::default());
+        world.insert_resource(PlayerInputQueue::default());
+        world
+    }
+
+    // Pushes the stick to where it's given, returns what got pressed and released for it
+    fn move_stick(world: &mut World, stage: &mut SystemStage, x: f32, y: f32) -> Vec<PlayerInput> {
+        let mut axes = world.resource_mut::<Axis<GamepadAxis>>();
+        axes.set(GamepadAxis(Gamepad(0), GamepadAxisType::LeftStickX), x);
+        axes.set(GamepadAxis(Gamepad(0), GamepadAxisType::LeftStickY), y);
+        stage.run(world);
+        std::mem::take(&mut world.resource_mut::<PlayerInputQueue>().inputs)
+    }
+
+    #[test]
+    fn stick_presses_and_releases_directions() {
+        let mut world = stick_world(GamepadInputSettings::default());
+        let mut stage = SystemStage::single_threaded().with_system(gamepad_input_system);
+        let input = |purpose, input_type| PlayerInput {
+            player_id: 1,
+            purpose,
+            input_type,
+        };
+
+        assert_eq!(
+            move_stick(&mut world, &mut stage, -0.7, 0.7),
+            vec![
+                input(InputPurpose::Up, InputKeyboardType::JustPressed),
+                input(InputPurpose::Left, InputKeyboardType::JustPressed),
+            ]
+        );
+        // Held where it is nothing new gets pressed
+        assert_eq!(move_stick(&mut world, &mut stage, -0.7, 0.7), vec![]);
+        assert_eq!(
+            move_stick(&mut world, &mut stage, -0.9, 0.0),
+            vec![input(InputPurpose::Up, InputKeyboardType::JustReleased)]
+        );
+        assert_eq!(
+            move_stick(&mut world, &mut stage, 0.0, 0.0),
+            vec![input(InputPurpose::Left, InputKeyboardType::JustReleased)]
+        );
+    }
+
+    #[test]
+    fn holding_the_stick_up_only_joins_once() {
+        let settings = GamepadInputSettings::default();
+        let buttons = Input::<GamepadButton>::default();
+        let mut axes = Axis::<GamepadAxis>::default();
+        let stick_y = GamepadAxis(Gamepad(0), GamepadAxisType::LeftStickY);
+        let mut stick_was_up = false;
+
+        axes.set(stick_y, 1.0);
+        assert!(settings.up_pressed(&buttons, &axes, &mut stick_was_up));
+        assert!(!settings.up_pressed(&buttons, &axes, &mut stick_was_up));
+
+        axes.set(stick_y, 0.0);
+        assert!(!settings.up_pressed(&buttons, &axes, &mut stick_was_up));
+        axes.set(stick_y, 1.0);
+        assert!(settings.up_pressed(&buttons, &axes, &mut stick_was_up));
+    }
+}
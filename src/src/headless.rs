@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use bevy::app::{AppExit, ScheduleRunnerPlugin, ScheduleRunnerSettings};
+use bevy::asset::AssetPlugin;
+use bevy::audio::AudioPlugin;
+use bevy::prelude::*;
+use bevy::render::settings::WgpuSettings;
+use bevy::winit::WinitPlugin;
+use bevy_svg::prelude::Svg;
+
+use crate::char::Character;
+use crate::cpu::{CpuBrain, CpuPlayers};
+use crate::draw::{GameRounds, WinitWindowsInfo};
+use crate::game::*;
+use crate::pack_io::PackAssetIoPlugin;
+use crate::{AppStates, CharacterInputIdentifierMap, SelectedCharacters};
+
+// Only exists when running with --headless, there's no window or gpu behind anything then
+pub struct Headless;
+
+pub fn headless_requested() -> bool {
+    std::env::args().any(|argument| argument == "--headless")
+}
+
+// Stands in for DefaultPlugins & SvgPlugin, everything that needs a window or gpu is left out
+pub fn add_headless_plugins(app: &mut App, settings: &GameSettings) {
+    app.insert_resource(WgpuSettings {
+        backends: None,
+        ..Default::default()
+    });
+    app.insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(
+        1.0 / settings.headless.frame_rate,
+    )));
+    app.add_plugins_with(DefaultPlugins, |group| {
+        group
+            .add_before::<AssetPlugin, _>(PackAssetIoPlugin)
+            .disable::<WinitPlugin>()
+            // Nothing to hear it on, the audio systems all do nothing when there's no Audio
+            .disable::<AudioPlugin>()
+    });
+    app.add_plugin(ScheduleRunnerPlugin);
+
+    // Svgs still get loaded as part of the map and characters, they just never get drawn
+    app.add_asset::<Svg>();
+
+    // There's no monitor to measure so pretend the screen is the size of the window
+    app.insert_resource(WinitWindowsInfo {
+        screen_dim: Vec2::new(settings.window.width, settings.window.height),
+    });
+    app.insert_resource(Headless);
+}
+
+pub fn headless_setup(
+    mut commands: Commands,
+    settings: Res<GameSettings>,
+    chars: Res<Vec<Character>>,
+    mut state: ResMut<State<AppStates>>,
+) {
+    let mut selected_characters = HashMap::new();
+
+    if settings.headless.characters.is_empty() {
+        for (index, character) in chars.iter().take(2).enumerate() {
+            selected_characters.insert(index as u64 + 1, character.clone());
+        }
+    } else {
+        for (index, name) in settings.headless.characters.iter().enumerate() {
+            match chars.iter().find(|char| &char.info.display_name == name) {
+                None => {
+                    error!("Headless match needs the character {} which isnt installed", name);
+                    std::process::exit(1);
+                }
+                Some(character) => {
+                    selected_characters.insert(index as u64 + 1, character.clone());
+                }
+            }
+        }
+    }
+
+    commands.insert_resource(CharacterInputIdentifierMap {
+        map: HashMap::new(),
+    });
+
+    // Nobody is at the keyboard, so without cpus or agents playing nothing would ever happen
+    let cpu_players = match settings.agent.enabled {
+        true => HashMap::new(),
+        false => selected_characters
+            .keys()
+            .map(|player_id| (*player_id, CpuBrain::new(settings.headless.cpu_difficulty)))
+            .collect(),
+    };
+    commands.insert_resource(CpuPlayers {
+        players: cpu_players,
+    });
+
+    commands.insert_resource(SelectedCharacters {
+        characters: selected_characters,
+    });
+
+    state.set(AppStates::PreLoad);
+}
+
+pub fn headless_round_limit(
+    game_rounds: Res<GameRounds>,
+    settings: Res<GameSettings>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let rounds_played = game_rounds.total_victories.len() as u64;
+    println!(
+        "Round {} finished: {:?}",
+        rounds_played, game_rounds.previous_victory
+    );
+
+    if rounds_played >= settings.headless.rounds {
+        exit.send(AppExit);
+    }
+}
+
+// Stops matches where nobody ever wins, like agents that stopped answering or cpus that wont engage
+pub fn headless_frame_limit(
+    clock: Res<GameClock>,
+    settings: Res<GameSettings>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let max_frames = settings.headless.max_frames;
+    if max_frames > 0 && clock.gameplay_frame >= max_frames {
+        println!("Stopping the match after {} frames without a winner", max_frames);
+        exit.send(AppExit);
+    }
+}
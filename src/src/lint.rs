@@ -0,0 +1,646 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::assets::Asset;
+use crate::char::Character;
+use crate::collider::AACollider;
+use crate::maps::Map;
+use crate::pack_io::{is_zip_pack, pack_file_exists, read_zip_pack, resolve_asset_path};
+
+// Run as `double_a_batteries lint [packs...]`, every pack in maps/ and char/ is checked if none are given
+pub fn lint_requested() -> Option<Vec<String>> {
+    let mut args = std::env::args().skip(1);
+    match args.next() {
+        Some(command) if command == "lint" => Some(args.collect()),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LintError {
+    pub file: PathBuf,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for LintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.file.display(), line, self.message),
+            None => write!(f, "{}: {}", self.file.display(), self.message),
+        }
+    }
+}
+
+pub struct PackLinter {
+    pub file: PathBuf,
+    pub base_path: PathBuf,
+    pub content: String,
+    pub errors: Vec<LintError>,
+}
+
+impl PackLinter {
+    pub fn open(pack_path: &Path) -> Result<Self, LintError> {
+        let failed = |message: String| LintError {
+            file: pack_path.to_path_buf(),
+            line: None,
+            message,
+        };
+
+        let (content, base_path) = if is_zip_pack(pack_path) {
+            read_zip_pack(pack_path, "main.toml").map_err(|error| failed(error.to_string()))?
+        } else {
+            let content = std::fs::read_to_string(pack_path.join("main.toml"))
+                .map_err(|error| failed(format!("couldnt read main.toml: {}", error)))?;
+            (content, pack_path.to_path_buf())
+        };
+
+        Ok(Self {
+            file: base_path.join("main.toml"),
+            base_path,
+            content,
+            errors: vec![],
+        })
+    }
+
+    // toml doesn't keep track of where values came from, so look for the first line that has the value on it
+    pub fn line_of(&self, key: &str, value: &str) -> Option<usize> {
+        let quoted = format!("\"{}\"", value);
+        let lines: Vec<&str> = self.content.lines().collect();
+
+        lines
+            .iter()
+            .position(|line| line.contains(key) && line.contains(&quoted))
+            .or_else(|| lines.iter().position(|line| line.contains(&quoted)))
+            .map(|index| index + 1)
+    }
+
+    // Combo keys are a list of numbers rather than a string, so they get looked for with the spaces taken out
+    pub fn line_of_keys(&self, keys: &[u8]) -> Option<usize> {
+        let compact = format!(
+            "[{}]",
+            keys.iter()
+                .map(|key| key.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+
+        self.content
+            .lines()
+            .position(|line| line.contains("keys") && line.replace(' ', "").contains(&compact))
+            .map(|index| index + 1)
+    }
+
+    // For values that arent strings, the first line that sets the key
+    pub fn line_of_key(&self, key: &str) -> Option<usize> {
+        self.content
+            .lines()
+            .position(|line| line.trim_start().starts_with(key))
+            .map(|index| index + 1)
+    }
+
+    pub fn error(&mut self, line: Option<usize>, message: String) {
+        self.errors.push(LintError {
+            file: self.file.clone(),
+            line,
+            message,
+        });
+    }
+
+    pub fn parse<T: serde::de::DeserializeOwned>(&mut self) -> Option<T> {
+        match toml::from_str(&self.content) {
+            Ok(parsed) => Some(parsed),
+            Err(error) => {
+                let line = error.line_col().map(|(line, _)| line + 1);
+                self.error(line, error.to_string());
+                None
+            }
+        }
+    }
+
+    // Checks that a value refers to one of the known ids, empty values are left alone as they mean nothing was set
+    pub fn expect_id(&mut self, key: &str, value: &str, known: &HashSet<String>, kind: &str) {
+        if !value.is_empty() && !known.contains(value) {
+            let line = self.line_of(key, value);
+            self.error(
+                line,
+                format!("{} = \"{}\" isnt the id of any {}", key, value, kind),
+            );
+        }
+    }
+
+    // Every asset has to be a file in the pack, returns the asset names for the other checks
+    pub fn check_assets(&mut self, asset: &Asset) -> HashSet<String> {
+        let paths = asset
+            .image
+            .iter()
+            .map(|image| (&image.name, &image.path))
+            .chain(asset.svg.iter().map(|svg| (&svg.name, &svg.path)));
+
+        let mut names = HashSet::new();
+        for (name, path) in paths {
+            if !pack_file_exists(&self.base_path.join(path)) {
+                let line = self.line_of("path", path);
+                self.error(
+                    line,
+                    format!("asset {} points to {} which doesnt exist", name, path),
+                );
+            }
+
+            if !names.insert(name.clone()) {
+                let line = self.line_of("name", name);
+                self.error(
+                    line,
+                    format!("there is more than one asset called {}", name),
+                );
+            }
+        }
+
+        names
+    }
+
+    pub fn check_icon(&mut self, icon: &str, asset: &Asset) {
+        if icon.is_empty() {
+            return;
+        }
+
+        let icon_path = asset.icon_path(icon, &self.base_path);
+        if !pack_file_exists(&icon_path) {
+            let line = self.line_of("icon", icon);
+            self.error(line, format!("icon {} doesnt exist", icon_path.display()));
+        }
+    }
+
+    // Sounds are files in the pack like assets, empty ones are silent
+    pub fn check_sound(&mut self, key: &str, path: &str) {
+        if !path.is_empty() && !pack_file_exists(&self.base_path.join(path)) {
+            let line = self.line_of(key, path);
+            self.error(line, format!("sound {} doesnt exist", path));
+        }
+    }
+
+    // Colliders are referenced by id from physics objects and the special collider lists
+    pub fn check_colliders(&mut self, collider: &AACollider) -> HashSet<String> {
+        let ids: HashSet<String> = collider.ids().into_iter().collect();
+
+        for (key, special_ids) in [
+            ("jump_reset_colliders", collider.jump_reset_colliders.ids()),
+            ("death_colliders", collider.death_colliders.ids()),
+        ] {
+            for id in special_ids {
+                self.expect_id(key, id, &ids, "collider");
+            }
+        }
+
+        ids
+    }
+}
+
+pub fn lint_character(linter: &mut PackLinter) {
+    let char: Character = match linter.parse() {
+        None => return,
+        Some(char) => char,
+    };
+
+    let assets = linter.check_assets(&char.asset);
+    linter.check_icon(&char.info.icon, &char.asset);
+    let collider_ids = linter.check_colliders(&char.collider);
+
+    for physics_object in &char.physics_object {
+        for collider_id in &physics_object.collider_ids {
+            linter.expect_id("collider_ids", collider_id, &collider_ids, "collider");
+        }
+    }
+
+    let physics_ids: HashSet<String> = char
+        .physics_object
+        .iter()
+        .map(|object| object.id.clone())
+        .collect();
+    for component in std::iter::once(&char.core).chain(char.char_component.iter()) {
+        linter.expect_id("asset", &component.asset, &assets, "asset");
+        for asset in &component.character_assets {
+            linter.expect_id("character_assets", asset, &assets, "asset");
+        }
+        linter.expect_id(
+            "physobj_id",
+            &component.physobj_id,
+            &physics_ids,
+            "physics_object",
+        );
+    }
+
+    for projectile in &char.projectile {
+        linter.expect_id("asset", &projectile.asset, &assets, "asset");
+        linter.expect_id(
+            "physobj_id",
+            &projectile.physobj_id,
+            &physics_ids,
+            "physics_object",
+        );
+    }
+
+    // Attacks run actions, spawn_projectile_action picks projectiles and movement_attack_action picks movement_action
+    let action_ids: HashSet<String> = char
+        .spawn_projectile_action
+        .iter()
+        .map(|action| action.id.clone())
+        .chain(
+            char.movement_attack_action
+                .iter()
+                .map(|action| action.id.clone()),
+        )
+        .collect();
+
+    let attacks = [
+        &char.attack.one_one,
+        &char.attack.one_two,
+        &char.attack.two_one,
+        &char.attack.two_two,
+    ];
+    let combo_actions = char.combo.iter().map(|combo| &combo.actions);
+    for actions in attacks
+        .into_iter()
+        .flatten()
+        .map(|attack| &attack.actions)
+        .chain(combo_actions)
+    {
+        for action in actions {
+            linter.expect_id(
+                "actions",
+                action,
+                &action_ids,
+                "spawn_projectile_action or movement_attack_action",
+            );
+        }
+    }
+
+    let clip_ids: HashSet<String> = char
+        .animation
+        .clip
+        .iter()
+        .map(|clip| clip.id.clone())
+        .collect();
+    for combo in char.attack.combos().iter().chain(char.combo.iter()) {
+        linter.expect_id("animation", &combo.animation, &clip_ids, "animation clip");
+    }
+
+    // Frames pick parts by id, the core is always there as "core"
+    let part_ids: HashSet<String> = std::iter::once(String::from("core"))
+        .chain(
+            char.char_component
+                .iter()
+                .map(|component| component.id.clone())
+                .filter(|id| !id.is_empty()),
+        )
+        .collect();
+    for clip in &char.animation.clip {
+        for frame in &clip.frame {
+            for (part_id, part_assets) in &frame.assets {
+                linter.expect_id("assets", part_id, &part_ids, "char_component");
+                for asset in part_assets {
+                    linter.expect_id("assets", asset, &assets, "asset");
+                }
+            }
+            for part_id in frame.poses.keys() {
+                linter.expect_id("poses", part_id, &part_ids, "char_component");
+            }
+        }
+    }
+
+    let mut combo_keys = HashSet::new();
+    for combo in char.attack.combos().iter().chain(char.combo.iter()) {
+        let keys = format!("{:?}", combo.keys);
+        let line = linter.line_of_keys(&combo.keys);
+
+        if combo.keys.is_empty() || combo.attack_keys().is_none() {
+            linter.error(line, format!("combo keys {} can only be 1s and 2s", keys));
+        }
+        if combo.windows.len() > combo.keys.len() || combo.directions.len() > combo.keys.len() {
+            linter.error(
+                line,
+                format!("combo {} has more windows or directions than keys", keys),
+            );
+        }
+
+        let directions = format!("{:?}", combo.directions);
+        if !combo_keys.insert((keys.clone(), directions)) {
+            linter.error(line, format!("there is more than one combo for {}", keys));
+        }
+    }
+
+    let projectile_ids: HashSet<String> = char
+        .projectile
+        .iter()
+        .map(|projectile| projectile.id.clone())
+        .collect();
+    for action in &char.spawn_projectile_action {
+        for (key, id) in action.directional_ids() {
+            linter.expect_id(key, id, &projectile_ids, "projectile");
+        }
+    }
+
+    for projectile in &char.projectile {
+        if let Some(split) = &projectile.split {
+            for id in &split.ids {
+                linter.expect_id("ids", id, &projectile_ids, "projectile");
+            }
+        }
+
+        // Sensors never touch the map so they have nothing to bounce off of
+        if projectile.bounce.is_some() && !projectile.dont_phase_through {
+            let line = linter.line_of("id", &projectile.id);
+            linter.error(
+                line,
+                format!(
+                    "projectile {} bounces but doesnt have dont_phase_through set",
+                    projectile.id
+                ),
+            );
+        }
+
+        // A solid hitbox held onto the core would shove players around instead of hitting them
+        if projectile.melee.is_some() && projectile.dont_phase_through {
+            let line = linter.line_of("id", &projectile.id);
+            linter.error(
+                line,
+                format!(
+                    "projectile {} is melee so it cant have dont_phase_through set",
+                    projectile.id
+                ),
+            );
+        }
+    }
+
+    for (key, path) in [
+        ("jump", &char.sound.jump),
+        ("attack", &char.sound.attack),
+        ("hit", &char.sound.hit),
+        ("death", &char.sound.death),
+    ] {
+        linter.check_sound(key, path);
+    }
+    for combo in char.attack.combos().iter().chain(char.combo.iter()) {
+        linter.check_sound("sound", &combo.sound);
+    }
+
+    let movement_ids: HashSet<String> = char
+        .movement_action
+        .iter()
+        .map(|action| action.id.clone())
+        .collect();
+    for action in &char.movement_attack_action {
+        for (key, id) in action.directional_ids() {
+            linter.expect_id(key, id, &movement_ids, "movement_action");
+        }
+    }
+}
+
+pub fn lint_map(linter: &mut PackLinter) {
+    let map: Map = match linter.parse() {
+        None => return,
+        Some(map) => map,
+    };
+
+    let assets = linter.check_assets(&map.asset);
+    linter.check_icon(&map.info.icon, &map.asset);
+    let collider_ids = linter.check_colliders(&map.collider);
+
+    for sprite in &map.sprite {
+        linter.expect_id("asset", &sprite.asset, &assets, "asset");
+    }
+
+    linter.expect_id("bg_main", &map.background.bg_main, &assets, "asset");
+    for bg in &map.background.bg_above {
+        linter.expect_id("bg_above", bg, &assets, "asset");
+    }
+    for bg in &map.background.bg_below {
+        linter.expect_id("bg_below", bg, &assets, "asset");
+    }
+    for layer in &map.background.layer {
+        linter.expect_id("asset", &layer.asset, &assets, "asset");
+    }
+
+    if let Some([left, bottom, right, top]) = map.camera.bounds {
+        if left >= right || bottom >= top {
+            let line = linter.line_of_key("bounds");
+            linter.error(
+                line,
+                "camera bounds have to be left, bottom, right, top with room between them"
+                    .to_string(),
+            );
+        }
+    }
+
+    let mut platform_colliders = HashSet::new();
+    for platform in &map.platform {
+        for id in &platform.colliders {
+            linter.expect_id("colliders", id, &collider_ids, "collider");
+
+            if !platform_colliders.insert(id.clone()) {
+                let line = linter.line_of("colliders", id);
+                linter.error(
+                    line,
+                    format!("collider {} is on more than one platform", id),
+                );
+            }
+        }
+
+        for sprite in &platform.sprite {
+            linter.expect_id("asset", &sprite.asset, &assets, "asset");
+        }
+
+        if platform.waypoint.len() < 2 {
+            let line = linter.line_of("id", &platform.id);
+            linter.error(
+                line,
+                format!(
+                    "platform {} needs at least 2 waypoints to move between",
+                    platform.id
+                ),
+            );
+        }
+    }
+
+    for track in &map.music {
+        linter.check_sound("path", &track.path);
+        linter.check_sound("intro", &track.intro);
+
+        if !track.intro.is_empty() && track.intro_length <= 0.0 {
+            let line = linter.line_of("intro", &track.intro);
+            linter.error(
+                line,
+                format!("music intro {} needs an intro_length", track.intro),
+            );
+        }
+    }
+}
+
+// Characters are the only packs with a core component, ones that dont parse go by the folder they're in
+pub fn is_character_pack(pack_path: &Path, content: &str) -> bool {
+    match content.parse::<toml::Value>() {
+        Ok(value) => value.get("core").is_some(),
+        Err(_) => pack_path
+            .parent()
+            .and_then(|folder| folder.file_name())
+            .map(|folder| folder == "char")
+            .unwrap_or(false),
+    }
+}
+
+pub fn lint_pack(pack_path: &Path) -> Vec<LintError> {
+    let mut linter = match PackLinter::open(pack_path) {
+        Ok(linter) => linter,
+        Err(error) => return vec![error],
+    };
+
+    if is_character_pack(pack_path, &linter.content) {
+        lint_character(&mut linter);
+    } else {
+        lint_map(&mut linter);
+    }
+
+    linter.errors
+}
+
+// Returns the exit code, 1 if anything was wrong with any of the packs
+pub fn run_lint(packs: Vec<String>) -> i32 {
+    // Packs named on the command line are from wherever it was run, the rest are where the game loads them from
+    let current_dir = std::env::current_dir().unwrap_or_default();
+    let mut pack_paths: Vec<PathBuf> = packs.iter().map(|pack| current_dir.join(pack)).collect();
+
+    if pack_paths.is_empty() {
+        for folder in ["maps", "char"] {
+            let read_dir = match std::fs::read_dir(resolve_asset_path(Path::new(folder))) {
+                Ok(read_dir) => read_dir,
+                Err(_) => continue,
+            };
+
+            for entry in read_dir.flatten() {
+                let path = entry.path();
+                let hidden = entry.file_name().to_string_lossy().starts_with('.');
+                if !hidden && (path.is_dir() || is_zip_pack(&path)) {
+                    pack_paths.push(path);
+                }
+            }
+        }
+    }
+    pack_paths.sort();
+
+    let mut total_errors = 0;
+    for pack_path in &pack_paths {
+        let errors = lint_pack(pack_path);
+        for error in &errors {
+            println!("{}", error);
+        }
+        total_errors += errors.len();
+    }
+
+    println!(
+        "{} problems found in {} packs",
+        total_errors,
+        pack_paths.len()
+    );
+    if total_errors > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID_MAP: &str = r#"[info]
+display_name = "Test"
+base_dimensions = [1920.0, 1080.0]
+
+[[asset.image]]
+name = "ground"
+path = "ground.png"
+
+[[sprite]]
+asset = "ground"
+"#;
+
+    // A pack folder under a scratch maps/ folder, so the real packs are never touched
+    fn map_pack(test: &str, main: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("aa_lint_{}_{}", test, std::process::id()));
+        if root.exists() {
+            std::fs::remove_dir_all(&root).unwrap();
+        }
+
+        let pack_path = root.join("maps").join("test");
+        std::fs::create_dir_all(&pack_path).unwrap();
+        std::fs::write(pack_path.join("main.toml"), main).unwrap();
+        std::fs::write(pack_path.join("ground.png"), "").unwrap();
+        pack_path
+    }
+
+    fn linter(content: &str) -> PackLinter {
+        PackLinter {
+            file: PathBuf::from("main.toml"),
+            base_path: PathBuf::new(),
+            content: content.to_string(),
+            errors: vec![],
+        }
+    }
+
+    #[test]
+    fn valid_pack_has_no_errors() {
+        let pack_path = map_pack("valid", VALID_MAP);
+        let errors = lint_pack(&pack_path);
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    #[test]
+    fn missing_asset_is_reported_on_its_line() {
+        let pack_path = map_pack(
+            "missing_asset",
+            &VALID_MAP.replace("asset = \"ground\"", "asset = \"rock\""),
+        );
+        let errors = lint_pack(&pack_path);
+
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert_eq!(errors[0].line, Some(10));
+        assert!(errors[0].message.contains("rock"));
+    }
+
+    #[test]
+    fn missing_key_fails_to_parse() {
+        let pack_path = map_pack(
+            "missing_key",
+            &VALID_MAP.replace("display_name = \"Test\"\n", ""),
+        );
+        let errors = lint_pack(&pack_path);
+
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].message.contains("display_name"));
+    }
+
+    #[test]
+    fn line_of_prefers_the_line_with_the_key() {
+        let linter = linter(
+            "[[asset.image]]\nname = \"core\"\npath = \"core.png\"\n\n[core]\nasset = \"core\"\n\n[[combo]]\nkeys = [1, 2]\nwindow = 3\n",
+        );
+
+        assert_eq!(linter.line_of("asset", "core"), Some(6));
+        assert_eq!(linter.line_of("physobj_id", "core"), Some(2));
+        assert_eq!(linter.line_of("asset", "missing"), None);
+        assert_eq!(linter.line_of_keys(&[1, 2]), Some(9));
+        assert_eq!(linter.line_of_keys(&[2, 1]), None);
+        assert_eq!(linter.line_of_key("window"), Some(10));
+    }
+
+    #[test]
+    fn unparsable_packs_go_by_their_folder() {
+        let broken = "core = [";
+        assert!(is_character_pack(Path::new("/aa/char/euclid"), broken));
+        assert!(is_character_pack(Path::new("/aa/char/euclid.zip"), broken));
+        assert!(!is_character_pack(Path::new("/aa/maps/dunes"), broken));
+        assert!(!is_character_pack(Path::new("/char/maps/dunes"), broken));
+        assert!(is_character_pack(Path::new("/aa/maps/dunes"), "[core]\n"));
+    }
+}
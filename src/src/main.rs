@@ -8,7 +8,7 @@ use std::ops::{Add, Index};
 use std::sync::Arc;
 
 use async_compat::Compat;
-use bevy::asset::AssetServerSettings;
+use bevy::asset::{AssetPlugin, AssetServerSettings};
 use bevy::diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin};
 use bevy::ecs::query::QueryEntityError;
 use bevy::ecs::system::QuerySingleError;
@@ -19,6 +19,7 @@ use bevy::reflect::List;
 use bevy::render::camera::ScalingMode;
 use bevy::sprite::{MaterialMesh2dBundle, Mesh2dHandle};
 use bevy::tasks::AsyncComputeTaskPool;
+use bevy::transform::TransformSystem;
 use bevy::window::{WindowId, WindowMode, WindowResizeConstraints};
 use bevy::winit::WinitWindows;
 use bevy_inspector_egui::widgets::ResourceInspector;
@@ -48,11 +49,11 @@ use crate::assets::{
     add_augmented_fonts, retrieve_asset_maps, update_image_sampler, update_svg_transforms, Asset,
     AssetDirectory, AssetInfoMap, AugmentedFonts, InterpolateHandles, SvgInfo,
 };
-use crate::background::modify_svg_background_transform;
+use crate::background::{modify_svg_background_transform, parallax_scroll};
 use crate::char::{
     check_cooldowns, check_victory_conditions, healthbar_update, load_character,
-    load_selected_characters, sync_objects_colliders, update_dir_look, AAPlayerDescriptor,
-    AttackIdentifierTextId, AttackInstanceDirectory, CharComponentMap,
+    load_selected_characters, percentage_hud_update, sync_objects_colliders, update_dir_look,
+    AAPlayerDescriptor, AttackIdentifierTextId, AttackInstanceDirectory, CharComponentMap,
     CharComponentPlayerIdentifier, CharEntities, Character, ConvertToRgb, CooldownMap,
     DirectionFacingFlags, MoveDirLookIdentifier, PlayerHealth, PlayerHealthBarId, PlayerIdentifier,
     SyncTransformOffset, VictoryEvent,
@@ -63,23 +64,71 @@ use crate::maps::Map;
 use crate::maps::*;
 use crate::universal::*;
 use crate::AppStates::{LoadMap, PreGame};
-use crate::CoreStage::{First, Last, Update};
+use crate::CoreStage::{First, Last, PostUpdate, PreUpdate, Update};
 use crate::KeyCode::Back;
 
 mod action_traits;
+mod agent;
+mod animation;
 mod assets;
+mod audio;
 mod background;
+mod camera;
 mod char;
 mod collider;
+mod combo;
+mod content;
+mod cpu;
 mod draw;
 mod game;
+mod headless;
+mod map_select;
+mod lint;
 mod maps;
+mod melee;
+mod netplay;
+mod pack_io;
+mod platform;
 mod post_processing;
 mod projectile;
+mod projectile_behaviour;
+mod replay;
 mod rigidbody;
+mod settings_menu;
+mod shield;
 mod universal;
 
+use crate::agent::{agent_input_system, agent_observation_system, start_agent_server};
+use crate::animation::{animate_parts, animation_state_update};
+use crate::audio::{
+    character_sound_triggers, music_update, play_character_sounds, play_ui_sound,
+    start_map_music, stop_music, CharacterSoundEvent, MusicPlayer,
+};
+use crate::camera::{offscreen_indicators, update_match_camera, MatchCamera};
 use crate::collider::{ColliderSyncEntity, SyncColliderFlags};
+use crate::content::{content_download_update, start_content_download};
+use crate::cpu::{cpu_input_system, CpuBrain, CpuJoinedText, CpuPlayers};
+use crate::headless::{
+    add_headless_plugins, headless_frame_limit, headless_requested, headless_round_limit,
+    headless_setup, Headless,
+};
+use crate::lint::{lint_requested, run_lint};
+use crate::map_select::{map_select_input, spawn_map_select, ChosenMap};
+use crate::melee::melee_hitstun_cancel;
+use crate::pack_io::PackAssetIoPlugin;
+use crate::platform::{move_platforms, platform_riders};
+use crate::projectile_behaviour::{
+    projectile_bounces, projectile_expiry_effects, projectile_homing, ProjectileExpired,
+};
+use crate::settings_menu::{settings_menu_input, spawn_settings_menu};
+use crate::shield::{shield_indicator_update, shield_regen};
+use crate::netplay::{
+    netplay_input_system, netplay_start_round, netplay_sync_update, spawn_netplay_sync,
+};
+use crate::replay::{
+    record_replay_inputs, replay_map_change, replay_playback_input_system, replay_start_round,
+    save_replay, start_replay_playback,
+};
 use crate::projectile::{
     attack_text_update, execute_unused_actions, projectile_lifetimes, ProjectileIdentifier,
 };
@@ -88,6 +137,8 @@ use futures_lite::StreamExt;
 use game::*;
 use image::{GenericImageView, ImageResult};
 use rand::prelude::SliceRandom;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use winit::window::Icon;
 
 pub const RAPIERSCALE: f32 = 40.0;
@@ -97,6 +148,10 @@ pub struct RapierScaleConfig {
 }
 
 fn main() {
+    // Linting packs doesn't need any of the game running
+    if let Some(packs) = lint_requested() {
+        std::process::exit(run_lint(packs));
+    }
 
     // create main app
 
@@ -144,23 +199,45 @@ fn main() {
         total_victories: vec![],
         previous_map_change: 0,
     });
-    // run the installer
-    let child = Command::new("assets/installer.exe")
-        .spawn();
     app.insert_resource(Msaa { samples: 4 });
     app.insert_resource(interpolate_handles);
     app.insert_resource(CharComponentMap::new());
     app.insert_resource(CharacterInputMap::new());
+    app.insert_resource(PlayerInputQueue::default());
+    app.insert_resource(GamepadInputMap::default());
     app.add_startup_system(add_augmented_fonts);
-    app.add_plugins(DefaultPlugins);
-    app.add_plugin(SvgPlugin);
-    app.add_plugin(InspectableRapierPlugin);
+
+    let headless = headless_requested();
+    if headless {
+        add_headless_plugins(&mut app, &game_settings);
+    } else {
+        app.add_plugins_with(DefaultPlugins, |group| {
+            group.add_before::<AssetPlugin, _>(PackAssetIoPlugin)
+        });
+        app.add_plugin(SvgPlugin);
+        app.add_plugin(InspectableRapierPlugin);
+    }
 
     // Physics plugin
     let mut physics_plugin = RapierPhysicsPlugin::<NoUserData>::default();
     app.add_plugin(physics_plugin.with_physics_scale(RAPIERSCALE));
 
-    if game_settings.special_settings.debug_mode {
+    // Physics moves forward by however many gameplay frames the game clock has due, accumulate_game_clock sets this every frame
+    app.insert_resource(RapierConfiguration {
+        timestep_mode: TimestepMode::Fixed {
+            dt: 1.0 / game_settings.gameplay_settings.tick_rate as f32,
+            substeps: 1,
+        },
+        ..Default::default()
+    });
+    app.insert_resource(GameClock::new(game_settings.gameplay_settings.tick_rate));
+    app.add_event::<ProjectileExpired>();
+    app.add_event::<CharacterSoundEvent>();
+    app.init_resource::<MusicPlayer>();
+    app.init_resource::<MatchCamera>();
+    app.init_resource::<HeldCollisionEvents>();
+
+    if game_settings.special_settings.debug_mode && !headless {
         app.add_plugin(RapierDebugRenderPlugin::default());
         app.add_plugin(WorldInspectorPlugin::new());
     }
@@ -175,14 +252,17 @@ fn main() {
     app.register_inspectable::<ProjectileIdentifier>();
 
     // to make a vignette 💀
-    if game_settings.special_settings.vignette {
+    if game_settings.special_settings.vignette && !headless {
         post_processing::post_processing(&mut app);
     }
 
     //app.add_startup_system(server_test);
-    app.add_startup_system(window_icon);
     app.add_startup_system(create_camera);
-    app.add_startup_system(get_winit_information.exclusive_system());
+    app.add_startup_system(start_agent_server);
+    if !headless {
+        app.add_startup_system(window_icon);
+        app.add_startup_system(get_winit_information.exclusive_system());
+    }
     app.add_system_to_stage(Update, update_svg_transforms);
     app.add_system_to_stage(Update, sync_objects_colliders);
     app.add_system_to_stage(Last, cap_velocity);
@@ -190,7 +270,14 @@ fn main() {
     let initialisation_system_set = SystemSet::on_enter(AppStates::LoadComps)
         .with_system(maps::load_maps)
         .with_system(char::load_characters);
-    app.add_state(AppStates::LoadComps);
+    app.add_state(AppStates::DownloadContent);
+
+    app.add_system_set(
+        SystemSet::on_enter(AppStates::DownloadContent).with_system(start_content_download),
+    );
+    app.add_system_set(
+        SystemSet::on_update(AppStates::DownloadContent).with_system(content_download_update),
+    );
     app.add_system_set(initialisation_system_set);
     //app.add_system(my_cursor_system);
 
@@ -199,11 +286,14 @@ fn main() {
     let selectmap_set = SystemSet::on_enter(AppStates::PreLoad).with_system(select_map);
     app.add_system_set(selectmap_set);
 
-    let load_asset_set =
-        SystemSet::on_enter(AppStates::LoadAssets).with_system(retrieve_asset_maps);
+    let load_asset_set = SystemSet::on_enter(AppStates::LoadAssets)
+        .with_system(replay_map_change.label("replay_map_change"))
+        .with_system(retrieve_asset_maps.after("replay_map_change"));
     app.add_system_set(load_asset_set);
 
-    let load_game_set = SystemSet::on_enter(AppStates::LoadMap).with_system(load_map);
+    let load_game_set = SystemSet::on_enter(AppStates::LoadMap)
+        .with_system(load_map)
+        .with_system(start_map_music);
     app.add_system_set(load_game_set);
 
     app.add_system_set(
@@ -212,7 +302,21 @@ fn main() {
             .with_system(update_selector_bar_mm),
     );
 
-    app.add_system_set(SystemSet::on_enter(AppStates::MainMenu).with_system(spawn_main_menu));
+    app.add_system_set(
+        SystemSet::on_enter(AppStates::MainMenu)
+            .with_system(spawn_main_menu)
+            .with_system(stop_music),
+    );
+
+    app.add_system_set(
+        SystemSet::on_enter(AppStates::Settings).with_system(spawn_settings_menu),
+    );
+    app.add_system_set(
+        SystemSet::on_update(AppStates::Settings).with_system(settings_menu_input),
+    );
+
+    app.add_system_set(SystemSet::on_enter(AppStates::MapSelect).with_system(spawn_map_select));
+    app.add_system_set(SystemSet::on_update(AppStates::MapSelect).with_system(map_select_input));
 
     app.add_system_set(
         SystemSet::on_enter(AppStates::LoadChar).with_system(load_selected_characters),
@@ -247,33 +351,127 @@ fn main() {
     );
 
     app.add_system_set(
-        SystemSet::on_enter(AppStates::LoadGame).with_system(resume_physics_and_movement),
+        SystemSet::on_enter(AppStates::LoadGame)
+            .with_system(resume_physics_and_movement)
+            .with_system(netplay_start_round)
+            .with_system(replay_start_round),
+    );
+
+    app.add_system_set(
+        SystemSet::on_enter(AppStates::ReplayPlayback).with_system(start_replay_playback),
+    );
+
+    app.add_system_set(SystemSet::on_enter(AppStates::HeadlessSetup).with_system(headless_setup));
+
+    if headless {
+        app.add_system_set(
+            SystemSet::on_enter(AppStates::Victory).with_system(headless_round_limit),
+        );
+        app.add_system_set(
+            SystemSet::on_update(AppStates::LoadGame).with_system(headless_frame_limit),
+        );
+    }
+
+    app.add_system_set(
+        SystemSet::on_enter(AppStates::NetplaySync).with_system(spawn_netplay_sync),
+    );
+
+    app.add_system_set(
+        SystemSet::on_update(AppStates::NetplaySync).with_system(netplay_sync_update),
     );
 
     let on_game_set = SystemSet::on_update(AppStates::LoadGame)
         .with_system(update_image_sampler)
-        .with_system(check_cooldowns)
         .with_system(modify_svg_background_transform)
         .with_system(update_dir_look)
-        .with_system(check_victory_conditions)
-        .with_system(enforce_char_collision_dominance)
+        .with_system(animation_state_update)
+        .with_system(animate_parts)
+        .with_system(character_sound_triggers)
         .with_system(healthbar_update)
+        .with_system(percentage_hud_update)
+        .with_system(shield_indicator_update)
         .with_system(hide_update_game_text)
-        .with_system(attack_text_update)
+        .with_system(attack_text_update);
+
+    // Everything that changes how the match plays out, only runs on updates that play a gameplay frame
+    let gameplay_frame_set = SystemSet::new()
+        .with_run_criteria(gameplay_frame_due)
+        .with_system(check_cooldowns)
+        .with_system(check_victory_conditions)
+        .with_system(enforce_char_collision_dominance)
+        .with_system(shield_regen)
         .with_system(execute_unused_actions)
         .with_system(projectile_lifetimes)
+        .with_system(projectile_homing)
+        .with_system(projectile_bounces)
+        .with_system(projectile_expiry_effects)
+        .with_system(melee_hitstun_cancel)
+        .with_system(platform_riders.label("platform_riders"))
+        .with_system(move_platforms.after("platform_riders"))
+        .with_system(out_of_bounds_check)
+        .with_system(stock_loss_check)
         .with_system(health_despawn_check)
         .with_system(collision_process);
 
     app.add_system_set(
         SystemSet::on_enter(AppStates::Victory)
             .with_system(victory_screen)
-            .with_system(pause_physics_and_movement),
+            .with_system(pause_physics_and_movement)
+            .with_system(save_replay),
     );
     app.add_system_set(SystemSet::on_update(AppStates::Victory).with_system(spawn_game_victory));
 
     app.add_system_set_to_stage(Update, on_game_set);
-    app.add_system_to_stage(First, movement_input_system);
+    app.add_system_set_to_stage(Update, gameplay_frame_set);
+    app.add_system_to_stage(First, accumulate_game_clock.before("device_input"));
+    app.add_system_to_stage(First, keyboard_input_system.label("device_input"));
+    app.add_system_to_stage(First, gamepad_input_system.label("device_input"));
+    app.add_system_to_stage(
+        First,
+        replay_playback_input_system
+            .label("replay_input")
+            .after("device_input"),
+    );
+    app.add_system_to_stage(First, cpu_input_system.label("cpu_input").after("device_input"));
+    // Lock step holds back the whole queue, so it has to see everything the other input systems add
+    app.add_system_to_stage(
+        First,
+        agent_input_system
+            .after("device_input")
+            .after("replay_input")
+            .after("cpu_input"),
+    );
+    app.add_system_to_stage(Last, agent_observation_system);
+    app.add_system_to_stage(PostUpdate, play_character_sounds);
+    app.add_system_to_stage(PostUpdate, music_update);
+    // Before transforms get propagated so the camera isnt a frame behind the players
+    app.add_system_to_stage(
+        PostUpdate,
+        update_match_camera
+            .label("match_camera")
+            .before(TransformSystem::TransformPropagate),
+    );
+    app.add_system_to_stage(
+        PostUpdate,
+        offscreen_indicators
+            .after("match_camera")
+            .before(TransformSystem::TransformPropagate),
+    );
+    app.add_system_to_stage(
+        PostUpdate,
+        parallax_scroll
+            .after("match_camera")
+            .before(TransformSystem::TransformPropagate),
+    );
+    // netplay swaps out the collected inputs for the ones due this frame, so it has to run after they're all in
+    app.add_system_to_stage(First, netplay_input_system.exclusive_system().at_end());
+    app.add_system_to_stage(PreUpdate, advance_game_clock.before("movement_input"));
+    app.add_system_to_stage(PreUpdate, hold_collision_events);
+    app.add_system_to_stage(PreUpdate, movement_input_system.label("movement_input"));
+    app.add_system_to_stage(PreUpdate, record_replay_inputs.before("movement_input"));
+    // Slower displays go through the whole schedule more than once an update to play every frame that's due
+    app.schedule
+        .set_run_criteria(IntoSystem::into_system(catch_up_gameplay_frames));
     //run ze app
     app.insert_resource(game_settings);
     app.run();
@@ -282,19 +480,23 @@ fn main() {
 pub fn pause_physics_and_movement(
     mut physics_config: ResMut<RapierConfiguration>,
     mut gameplay_toggles: ResMut<AAGamePlayToggle>,
+    mut clock: ResMut<GameClock>,
 ) {
     physics_config.physics_pipeline_active = false;
     gameplay_toggles.process_movement = false;
+    clock.paused = true;
 }
 
 pub fn resume_physics_and_movement(
     mut physics_config: ResMut<RapierConfiguration>,
     mut gameplay_toggles: ResMut<AAGamePlayToggle>,
     mut keycodes: ResMut<Input<KeyCode>>,
+    mut clock: ResMut<GameClock>,
 ) {
     *keycodes = Input::<KeyCode>::default();
     physics_config.physics_pipeline_active = true;
     gameplay_toggles.process_movement = true;
+    clock.paused = false;
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
@@ -313,6 +515,11 @@ pub enum AppStates {
     Settings,
     PlayersIdentify,
     SelectCharacter,
+    NetplaySync,
+    ReplayPlayback,
+    HeadlessSetup,
+    MapSelect,
+    DownloadContent,
 }
 
 pub fn quit() {
@@ -382,6 +589,7 @@ pub fn spawn_main_menu(
 
     let select_options = vec![
         "PLAY".to_string(),
+        "SETTINGS".to_string(),
         "QUIT".to_string(),
     ];
 
@@ -452,6 +660,7 @@ pub fn spawn_main_menu(
         input_scheme: reverse_char_input_purpose(&settings),
         switch_state: vec![
             AppStates::PlayersIdentify,
+            AppStates::Settings,
             AppStates::Quit,
         ],
     });
@@ -462,6 +671,9 @@ pub struct PlayerIdentify {
     pub movement_player_id: u64,
     pub assigned_player_id: u64,
     pub input_settings: CharacterInputSettings,
+
+    // Whether the gamepad's stick was pushed up last update, holding it there only joins once
+    pub stick_up_held: bool,
 }
 
 pub type PlayerIdentifierScheme = HashMap<KeyCode, PlayerIdentify>;
@@ -490,6 +702,7 @@ pub fn identifier_char_input_purpose(game_settings: &Res<GameSettings>) -> Playe
                             movement_player_id: id as u64,
                             assigned_player_id: 0,
                             input_settings: *ctrl,
+                            stick_up_held: false,
                         });
                 }
                 _ => {}
@@ -500,7 +713,7 @@ pub fn identifier_char_input_purpose(game_settings: &Res<GameSettings>) -> Playe
     input_purpose_map
 }
 
-pub fn reverse_char_input_purpose(game_settings: &Res<GameSettings>) -> MenuInputScheme {
+pub fn reverse_char_input_purpose(game_settings: &GameSettings) -> MenuInputScheme {
     let mut input_purpose_map = MenuInputScheme::new();
     let ctrls = [
         &game_settings.p1_ctrls,
@@ -542,6 +755,9 @@ pub fn input_selector(
     mut selected: ResMut<MainMenuSelected>,
     mut state: ResMut<State<AppStates>>,
     main_menu_components: Query<Entity, With<MainMenuComponent>>,
+    settings: Res<GameSettings>,
+    audio: Option<Res<Audio>>,
+    asset_server: Res<AssetServer>,
     mut commands: Commands,
 ) {
     let mut selected_id = selected.current_selected_id;
@@ -549,6 +765,16 @@ pub fn input_selector(
     for (keycode, actions) in selected.input_scheme.iter() {
         if input.just_pressed(*keycode) {
             for action in actions {
+                match action {
+                    InputPurpose::Up | InputPurpose::Down => {
+                        play_ui_sound(&audio, &asset_server, &settings, &settings.audio.ui_move);
+                    }
+                    InputPurpose::Atk1 => {
+                        play_ui_sound(&audio, &asset_server, &settings, &settings.audio.ui_select);
+                    }
+                    _ => {}
+                }
+
                 match action {
                     InputPurpose::Up => {
                         if selected_id > 0 {
@@ -611,7 +837,10 @@ pub fn spawn_player_identify(
 
     commands.spawn_bundle(Text2dBundle {
         text: Text::with_section(
-            format!("Press UP to join and Press {:?} to start", settings.player_id.start_key),
+            format!(
+                "Press UP to join, {:?} to add a CPU and {:?} to start",
+                settings.cpu.add_key, settings.player_id.start_key
+            ),
             player_tooltip_style.clone(),
             text_alignment.clone(),
         ),
@@ -660,6 +889,9 @@ pub fn spawn_player_identify(
     commands.insert_resource(CharacterInputIdentifierMap {
         map: HashMap::new(),
     });
+    commands.insert_resource(CpuPlayers {
+        players: HashMap::new(),
+    });
 }
 
 pub struct CharacterInputIdentifierMap {
@@ -668,9 +900,13 @@ pub struct CharacterInputIdentifierMap {
 
 pub fn player_identifier_adder(
     input: Res<Input<KeyCode>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
     mut player_identify_menu: ResMut<PlayerIdentifierMenu>,
     mut character_input_map: ResMut<CharacterInputIdentifierMap>,
     mut player_identify_map: ResMut<PlayerIdentifierScheme>,
+    mut cpu_players: ResMut<CpuPlayers>,
+    mut cpu_text: Query<(&CpuJoinedText, &mut Text)>,
     player_identify: Query<Entity, With<PlayerIdMenuComponent>>,
     mut state: ResMut<State<AppStates>>,
     settings: Res<GameSettings>,
@@ -679,7 +915,16 @@ pub fn player_identifier_adder(
     mut commands: Commands,
 ) {
     for (keycode, mut player_identify) in player_identify_map.iter_mut() {
-        if input.just_pressed(*keycode) {
+        let gamepad_joined = match player_identify.input_settings.gamepad {
+            None => false,
+            Some(gamepad_settings) => gamepad_settings.up_pressed(
+                &gamepad_buttons,
+                &gamepad_axes,
+                &mut player_identify.stick_up_held,
+            ),
+        };
+
+        if input.just_pressed(*keycode) || gamepad_joined {
             if !player_identify.taken {
                 player_identify.taken = true;
                 let text_alignment = TextAlignment {
@@ -723,7 +968,71 @@ pub fn player_identifier_adder(
         }
     }
 
-    if input.just_pressed(settings.player_id.start_key) & (player_identify_menu.player_count > 1) {
+    // Cpus only take up a player slot, they dont have any controls of their own. They cant be added to netplay matches
+    if input.just_pressed(settings.cpu.add_key)
+        && !settings.netplay.enabled
+        && player_identify_menu.player_count < 8
+    {
+        let player_style = TextStyle {
+            font: fonts.bold_font.clone(),
+            font_size: settings.window.height * settings.player_id.player_joined_font_size,
+            color: Color::from(settings.player_id.player_joined_font_colour.convert_to_rgb()),
+        };
+
+        player_identify_menu.player_count += 1;
+        let player_id = player_identify_menu.player_count as u64;
+        let difficulty = settings.cpu.default_difficulty;
+
+        commands
+            .spawn_bundle(Text2dBundle {
+                text: Text::with_section(
+                    format!("P{} CPU ({})", player_id, difficulty.name()),
+                    player_style,
+                    TextAlignment {
+                        vertical: VerticalAlign::Center,
+                        horizontal: HorizontalAlign::Center,
+                    },
+                ),
+                transform: Transform::from_translation(Vec3::new(
+                    0.,
+                    (settings.window.height * settings.player_id.player_joined_window_down)
+                        + ((player_id - 1) as f32
+                            * settings.window.height
+                            * settings.player_id.player_joined_individual_distance),
+                    30.,
+                )),
+                ..Default::default()
+            })
+            .insert(PlayerIdMenuComponent)
+            .insert(CpuJoinedText { player_id });
+        cpu_players.players.insert(player_id, CpuBrain::new(difficulty));
+    }
+
+    // Only the most recently added cpu can have its difficulty changed
+    if input.just_pressed(settings.cpu.difficulty_key) {
+        let last_cpu = cpu_players.players.keys().max().copied();
+        if let Some(player_id) = last_cpu {
+            let brain = cpu_players.players.get_mut(&player_id).unwrap();
+            brain.difficulty = brain.difficulty.next();
+
+            for (joined_text, mut text) in cpu_text.iter_mut() {
+                if joined_text.player_id == player_id {
+                    text.sections[0].value =
+                        format!("P{} CPU ({})", player_id, brain.difficulty.name());
+                }
+            }
+        }
+    }
+
+    // The other players of a netplay match join from the other side
+    let minimum_players = if settings.netplay.enabled { 0 } else { 1 };
+
+    let start_pressed = input.just_pressed(settings.player_id.start_key)
+        || gamepad_buttons
+            .get_just_pressed()
+            .any(|button| button.1 == GamepadButtonType::Start);
+
+    if start_pressed & (player_identify_menu.player_count > minimum_players) {
         for entity in player_identify.iter() {
             commands.entity(entity).despawn_recursive();
         }
@@ -759,7 +1068,7 @@ pub fn get_selected_character_icons(
     let mut character_icons = HashMap::new();
 
     for (index, char) in chars.iter().enumerate() {
-        let image : Handle<Image> = asset.load(&char.info.icon);
+        let image : Handle<Image> = asset.load(char.asset.icon_path(&char.info.icon, &char.base_path));
         character_icons.insert(index, image);
     }
 
@@ -886,7 +1195,10 @@ pub fn spawn_current_selected_character(
         },
         transform: Transform::from_translation(Vec3::new(0., settings.window.height * settings.select_char.icon_vertical_dist, 30.)),
         texture: selected_icons.entry(selected_character).or_insert(
-            asset.load(&chars[selected_character].info.icon)
+            asset.load(chars[selected_character].asset.icon_path(
+                &chars[selected_character].info.icon,
+                &chars[selected_character].base_path,
+            ))
         ).clone(),
         ..Default::default()
     }).insert(CurrentCharacterSelectedId);
@@ -917,7 +1229,8 @@ pub fn selected_char_selector(
     asset: Res<AssetServer>,
     current_char_components: Query<Entity, With<CurrentCharacterSelectedId>>,
     mut commands: Commands,
-    mut total_characters: ResMut<TotalCharactersSelect>
+    mut total_characters: ResMut<TotalCharactersSelect>,
+    audio: Option<Res<Audio>>,
 ) {
     let mut selected_id = selected_character_menu.currently_selected_character;
     let mut reset_state = false;
@@ -926,6 +1239,16 @@ pub fn selected_char_selector(
     for (keycode, actions) in selected_character_menu.input_scheme.iter() {
         if input.just_pressed(*keycode) {
             for action in actions {
+                match action {
+                    InputPurpose::Up | InputPurpose::Down => {
+                        play_ui_sound(&audio, &asset, &settings, &settings.audio.ui_move);
+                    }
+                    InputPurpose::Atk1 => {
+                        play_ui_sound(&audio, &asset, &settings, &settings.audio.ui_select);
+                    }
+                    _ => {}
+                }
+
                 match action {
                     InputPurpose::Up => {
                         if selected_id > 0 {
@@ -968,7 +1291,11 @@ pub fn selected_char_selector(
             commands.entity(current_char_entity).despawn_recursive()
         }
         if total_characters.next_player > total_characters.total_to_select {
-            state.set(AppStates::PreLoad);
+            if settings.netplay.enabled {
+                state.set(AppStates::NetplaySync);
+            } else {
+                state.set(AppStates::MapSelect);
+            }
             return
         }
         if reset_state {
@@ -995,20 +1322,35 @@ pub fn selected_char_selector(
 pub fn select_map(
     maps: Res<Vec<Map>>,
     chars: Res<SelectedCharacters>,
+    chosen_map: Option<Res<ChosenMap>>,
+    settings: Res<GameSettings>,
     mut commands: Commands,
     mut state: ResMut<State<AppStates>>,
 ) {
     let available_maps = maps.into_inner().clone();
-    let selected_map = available_maps
-        .choose(&mut rand::thread_rng())
-        .unwrap()
-        .clone();
+    let seed = rand::thread_rng().gen::<u64>();
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    // Always pick from the rng so it lines up with replays and netplay, even if the map was chosen
+    let random_map = available_maps.choose(&mut rng).unwrap().clone();
+    let selected_map = match chosen_map {
+        Some(chosen_map) => chosen_map.0.clone().unwrap_or(random_map),
+        None => random_map,
+    };
+    commands.remove_resource::<ChosenMap>();
+
+    let mut map_rotation = MapRotation::new(&settings.map_rotation, &available_maps);
+    map_rotation.start_from(&selected_map, &available_maps, &mut rng);
 
     let mut game = Game {
         selected_characters: chars.characters.clone(),
         available_characters: vec![],
         selected_map,
         available_maps,
+        seed,
+        rng,
+        map_rotation,
+        ruleset: settings.ruleset.clone(),
     };
 
     commands.insert_resource(game);
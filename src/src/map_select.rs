@@ -0,0 +1,396 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy::text::Text2dBounds;
+use rand::prelude::SliceRandom;
+
+use crate::assets::AugmentedFonts;
+use crate::char::ConvertToRgb;
+use crate::draw::WinitWindowsInfo;
+use crate::game::*;
+use crate::maps::Map;
+use crate::{reverse_char_input_purpose, AppStates, CharacterInputIdentifierMap, MenuInputScheme};
+
+// What came out of the map select screen before the match, None means the random entry won
+pub struct ChosenMap(pub Option<Map>);
+
+pub struct MapVoter {
+    pub player_id: u64,
+    pub ctrls: CharacterInputSettings,
+    pub current_entry: usize,
+    pub voted: bool,
+}
+
+pub struct MapSelectMenu {
+    // Entry 0 is random, every other entry is the installed map one before it
+    pub entry_count: usize,
+    pub current_entry: usize,
+    pub voting: bool,
+    pub voters: Vec<MapVoter>,
+    pub input_scheme: MenuInputScheme,
+    pub icons: HashMap<usize, Handle<Image>>,
+}
+
+#[derive(Component)]
+pub struct MapSelectComponent;
+
+fn entry_map(maps: &[Map], entry: usize) -> Option<&Map> {
+    match entry {
+        0 => None,
+        _ => maps.get(entry - 1),
+    }
+}
+
+fn entry_name(maps: &[Map], entry: usize) -> String {
+    match entry_map(maps, entry) {
+        None => "RANDOM".to_string(),
+        Some(map) => map.info.display_name.clone(),
+    }
+}
+
+fn move_entry(entry: usize, entry_count: usize, purpose: InputPurpose) -> usize {
+    match purpose {
+        InputPurpose::Up => {
+            if entry > 0 {
+                entry - 1
+            } else {
+                entry_count - 1
+            }
+        }
+        InputPurpose::Down => {
+            if entry < entry_count - 1 {
+                entry + 1
+            } else {
+                0
+            }
+        }
+        _ => entry,
+    }
+}
+
+pub fn spawn_map_select(
+    mut commands: Commands,
+    mut input: ResMut<Input<KeyCode>>,
+    settings: Res<GameSettings>,
+    maps: Res<Vec<Map>>,
+    identifier_map: Option<Res<CharacterInputIdentifierMap>>,
+    fonts: Res<AugmentedFonts>,
+    window: Res<WinitWindowsInfo>,
+    asset: Res<AssetServer>,
+) {
+    let mut voters = vec![];
+    if settings.map_rotation.mode == MapRotationMode::Vote {
+        if let Some(identifier_map) = identifier_map {
+            for (player_id, ctrls) in identifier_map.map.iter() {
+                voters.push(MapVoter {
+                    player_id: *player_id,
+                    ctrls: *ctrls,
+                    current_entry: 0,
+                    voted: false,
+                });
+            }
+        }
+    }
+    voters.sort_by_key(|voter| voter.player_id);
+
+    let mut menu = MapSelectMenu {
+        entry_count: maps.len() + 1,
+        current_entry: 0,
+        voting: !voters.is_empty(),
+        voters,
+        input_scheme: reverse_char_input_purpose(&settings),
+        icons: HashMap::new(),
+    };
+
+    // Otherwise the press that picked the last character picks the map too
+    input.clear();
+    draw_map_select(
+        &mut commands,
+        &mut menu,
+        &maps,
+        &settings,
+        &fonts,
+        &window,
+        &asset,
+    );
+    commands.insert_resource(menu);
+}
+
+pub fn draw_map_select(
+    commands: &mut Commands,
+    menu: &mut MapSelectMenu,
+    maps: &[Map],
+    settings: &GameSettings,
+    fonts: &Res<AugmentedFonts>,
+    window: &Res<WinitWindowsInfo>,
+    asset: &Res<AssetServer>,
+) {
+    let map_select = &settings.map_select;
+    let height = settings.window.height;
+
+    let text_alignment = TextAlignment {
+        vertical: VerticalAlign::Center,
+        horizontal: HorizontalAlign::Center,
+    };
+
+    let text_style = |size: f32, colour: [f32; 3]| TextStyle {
+        font: fonts.bold_font.clone(),
+        font_size: height * size,
+        color: Color::from(colour.convert_to_rgb()),
+    };
+
+    let spawn_text = |commands: &mut Commands, text: String, style: TextStyle, up_dist: f32| {
+        commands
+            .spawn_bundle(Text2dBundle {
+                text: Text::with_section(text, style, text_alignment.clone()),
+                transform: Transform::from_translation(Vec3::new(0., height * up_dist, 30.)),
+                text_2d_bounds: Text2dBounds {
+                    size: Vec2::new(settings.window.width * map_select.description_width, height),
+                },
+                ..Default::default()
+            })
+            .insert(MapSelectComponent);
+    };
+
+    let title = if menu.voting {
+        "VOTE FOR THE MAP"
+    } else {
+        "CHOOSE THE MAP"
+    };
+    spawn_text(
+        commands,
+        title.to_string(),
+        text_style(map_select.title_text_size, map_select.title_text_colour),
+        map_select.title_up_dist,
+    );
+
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: Color::from(map_select.title_highlight_colour.convert_to_rgb()),
+                custom_size: Some(Vec2::new(
+                    window.screen_dim.x,
+                    map_select.title_highlight_height * height,
+                )),
+                ..Default::default()
+            },
+            transform: Transform::from_translation(Vec3::new(
+                0.,
+                height * map_select.title_up_dist,
+                20.,
+            )),
+            ..Default::default()
+        })
+        .insert(MapSelectComponent);
+
+    let (author, description) = match entry_map(maps, menu.current_entry) {
+        None => (None, Some("ANY OF THE INSTALLED MAPS".to_string())),
+        Some(map) => {
+            if !map.info.icon.is_empty() {
+                let icon_path = map.asset.icon_path(&map.info.icon, &map.base_path);
+                let icon = menu
+                    .icons
+                    .entry(menu.current_entry)
+                    .or_insert_with(|| asset.load(icon_path))
+                    .clone();
+
+                commands
+                    .spawn_bundle(SpriteBundle {
+                        sprite: Sprite {
+                            custom_size: Some(
+                                Vec2::new(height, height) * Vec2::from(map_select.map_icon_size),
+                            ),
+                            ..Default::default()
+                        },
+                        transform: Transform::from_translation(Vec3::new(
+                            0.,
+                            height * map_select.icon_vertical_dist,
+                            30.,
+                        )),
+                        texture: icon,
+                        ..Default::default()
+                    })
+                    .insert(MapSelectComponent);
+            }
+
+            (map.info.author.clone(), map.info.description.clone())
+        }
+    };
+
+    spawn_text(
+        commands,
+        entry_name(maps, menu.current_entry),
+        text_style(map_select.map_name_text_size, map_select.map_name_colour),
+        map_select.map_name_up_dist,
+    );
+
+    if let Some(author) = author {
+        spawn_text(
+            commands,
+            format!("BY {}", author),
+            text_style(map_select.map_info_text_size, map_select.map_info_colour),
+            map_select.author_up_dist,
+        );
+    }
+
+    if let Some(description) = description {
+        spawn_text(
+            commands,
+            description,
+            text_style(map_select.map_info_text_size, map_select.map_info_colour),
+            map_select.description_up_dist,
+        );
+    }
+
+    for (index, voter) in menu.voters.iter().enumerate() {
+        let colour = if voter.voted {
+            map_select.voted_text_colour
+        } else {
+            map_select.vote_text_colour
+        };
+
+        spawn_text(
+            commands,
+            format!(
+                "P{}: {}",
+                voter.player_id,
+                entry_name(maps, voter.current_entry)
+            ),
+            text_style(map_select.vote_text_size, colour),
+            map_select.vote_up_dist + index as f32 * map_select.vote_individual_distance,
+        );
+    }
+}
+
+pub fn map_select_input(
+    mut input: ResMut<Input<KeyCode>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    mut menu: ResMut<MapSelectMenu>,
+    maps: Res<Vec<Map>>,
+    mut game: Option<ResMut<Game>>,
+    mut state: ResMut<State<AppStates>>,
+    settings: Res<GameSettings>,
+    fonts: Res<AugmentedFonts>,
+    window: Res<WinitWindowsInfo>,
+    asset: Res<AssetServer>,
+    menu_components: Query<Entity, With<MapSelectComponent>>,
+    mut commands: Commands,
+) {
+    let menu = &mut *menu;
+    let mut changed = false;
+    let mut picked_entry = None;
+
+    if menu.voting {
+        for voter in menu.voters.iter_mut() {
+            let mut pressed: Vec<InputPurpose> = voter
+                .ctrls
+                .into_iter()
+                .filter(|(keycode, _)| input.just_pressed(*keycode))
+                .map(|(_, purpose)| purpose)
+                .collect();
+
+            if let Some(gamepad) = voter.ctrls.gamepad {
+                for (button, purpose) in gamepad.buttons() {
+                    if gamepad_buttons.just_pressed(button) {
+                        pressed.push(purpose);
+                    }
+                }
+            }
+
+            for purpose in pressed {
+                match purpose {
+                    InputPurpose::Up | InputPurpose::Down if !voter.voted => {
+                        voter.current_entry =
+                            move_entry(voter.current_entry, menu.entry_count, purpose);
+                        menu.current_entry = voter.current_entry;
+                    }
+                    InputPurpose::Atk1 => voter.voted = true,
+                    // Changing your mind is allowed until everyone has voted
+                    InputPurpose::Atk2 => voter.voted = false,
+                    _ => continue,
+                }
+                changed = true;
+            }
+        }
+
+        if menu.voters.iter().all(|voter| voter.voted) {
+            let mut votes = vec![0; menu.entry_count];
+            for voter in &menu.voters {
+                votes[voter.current_entry] += 1;
+            }
+
+            let most_votes = votes.iter().max().copied().unwrap_or(0);
+            let tied: Vec<usize> = (0..menu.entry_count)
+                .filter(|entry| votes[*entry] == most_votes)
+                .collect();
+
+            // Ties get settled by the match rng when there is one so replays stay the same
+            picked_entry = match game.as_mut() {
+                Some(game) => tied.choose(&mut game.rng).copied(),
+                None => tied.choose(&mut rand::thread_rng()).copied(),
+            };
+        }
+    } else {
+        for (keycode, actions) in menu.input_scheme.iter() {
+            if !input.just_pressed(*keycode) {
+                continue;
+            }
+
+            for action in actions {
+                match action {
+                    InputPurpose::Up | InputPurpose::Down => {
+                        menu.current_entry =
+                            move_entry(menu.current_entry, menu.entry_count, *action);
+                        changed = true;
+                    }
+                    InputPurpose::Atk1 => picked_entry = Some(menu.current_entry),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    if changed || picked_entry.is_some() {
+        for entity in menu_components.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    let picked_entry = match picked_entry {
+        None => {
+            if changed {
+                draw_map_select(
+                    &mut commands,
+                    menu,
+                    &maps,
+                    &settings,
+                    &fonts,
+                    &window,
+                    &asset,
+                );
+            }
+            return;
+        }
+        Some(picked_entry) => picked_entry,
+    };
+
+    let picked_map = entry_map(&maps, picked_entry).cloned();
+    commands.remove_resource::<MapSelectMenu>();
+    input.clear();
+
+    match game {
+        // Voting between rounds, the match already exists so just load the new map
+        Some(mut game) => {
+            let game = &mut *game;
+            game.selected_map = match picked_map {
+                Some(map) => map,
+                None => game.available_maps.choose(&mut game.rng).unwrap().clone(),
+            };
+            state.set(AppStates::LoadAssets);
+        }
+        None => {
+            commands.insert_resource(ChosenMap(picked_map));
+            state.set(AppStates::PreLoad);
+        }
+    }
+}
@@ -4,6 +4,7 @@ use std::path::PathBuf;
 
 use anyhow::{Context, Result};
 use bevy::asset::HandleId;
+use bevy::ecs::system::EntityCommands;
 use bevy::log::error;
 use bevy::math::Vec2Swizzles;
 use bevy::prelude::*;
@@ -12,6 +13,8 @@ use bevy::winit::WinitWindows;
 use bevy_rapier2d::prelude::*;
 use bevy_rapier2d::rapier::prelude::ColliderType;
 use bevy_svg::prelude::Svg;
+use rand::prelude::SliceRandom;
+use rand::rngs::StdRng;
 use semver::Version;
 use serde::{Deserialize, Serialize};
 
@@ -19,12 +22,17 @@ use crate::assets::{
     get_asset, load_assets, Asset, AssetDirectory, AssetMap, AssetType, AssetVec, HandleIdVec,
     InterpolateHandles, TransmuteAsset,
 };
-use crate::background::create_bgs;
+use crate::audio::MusicTrack;
+use crate::background::{create_bgs, spawn_parallax_layers, ParallaxLayer, ParallaxMotion};
+use crate::camera::MapCamera;
 use crate::collider::*;
 use crate::draw::get_info_scale_resolution;
+use crate::game::{GameClock, MapBounds, MapRotationMode, MapRotationSettings};
+use crate::headless::Headless;
+use crate::platform::{spawn_platforms, Platform};
 use crate::universal::*;
 use crate::{
-    get_resolution, AppStates, AssetInfoMap, Game, RapierScaleConfig, WinitWindowsInfo, RAPIERSCALE,
+    get_resolution, AppStates, AssetInfoMap, Game, GameSettings, RapierScaleConfig, WinitWindowsInfo, RAPIERSCALE,
 };
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -36,6 +44,13 @@ pub struct Background {
 
     #[serde(default = "Vec::default")]
     pub bg_below: Vec<String>,
+
+    // parallax, scroll_speed and depth for bg_main and the rows above and below it, they all move together
+    #[serde(flatten)]
+    pub motion: ParallaxMotion,
+
+    #[serde(default = "Vec::default")]
+    pub layer: Vec<ParallaxLayer>,
 }
 
 impl Default for Background {
@@ -44,6 +59,8 @@ impl Default for Background {
             bg_above: Vec::default(),
             bg_main: String::default(),
             bg_below: Vec::default(),
+            motion: ParallaxMotion::default(),
+            layer: Vec::default(),
         }
     }
 }
@@ -110,6 +127,15 @@ pub struct Map {
 
     #[serde(default)]
     pub map_element_colours: MapElementColours,
+
+    #[serde(default)]
+    pub music: Vec<MusicTrack>,
+
+    #[serde(default)]
+    pub camera: MapCamera,
+
+    #[serde(default)]
+    pub platform: Vec<Platform>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default, Copy)]
@@ -142,15 +168,121 @@ impl PossibleBundleRetrieve for SpriteAsset {
     }
 }
 
-pub fn load_maps(mut commands: Commands, mut state: ResMut<State<AppStates>>) {
+pub fn load_maps(
+    mut commands: Commands,
+    mut state: ResMut<State<AppStates>>,
+    settings: Res<GameSettings>,
+    headless: Option<Res<Headless>>,
+) {
     let mut maps: Vec<Map> = vec![];
     println!("Loading maps...");
     load_directory("maps".into(), "main.toml", &mut maps);
     println!("Done loading maps...");
-    state.set(AppStates::MainMenu);
+
+    // Playing back a replay or running headless skips the menus entirely
+    if !settings.replay.playback.is_empty() {
+        state.set(AppStates::ReplayPlayback);
+    } else if headless.is_some() {
+        state.set(AppStates::HeadlessSetup);
+    } else {
+        state.set(AppStates::MainMenu);
+    }
     commands.insert_resource(maps);
 }
 
+pub fn find_map(maps: &[Map], name: &str) -> Option<Map> {
+    maps.iter().find(|map| map.info.display_name == name).cloned()
+}
+
+// Keeps track of which map comes after the current one once rounds_to_map_change is hit
+#[derive(Debug, Clone)]
+pub struct MapRotation {
+    pub mode: MapRotationMode,
+    pub order: Vec<String>,
+    pub next: usize,
+}
+
+impl MapRotation {
+    pub fn new(settings: &MapRotationSettings, maps: &[Map]) -> Self {
+        let mut order = vec![];
+
+        if settings.mode == MapRotationMode::Playlist {
+            for name in &settings.playlist {
+                match find_map(maps, name) {
+                    None => warn!("The map {} in the playlist isnt installed, skipping it", name),
+                    Some(_) => order.push(name.clone()),
+                }
+            }
+
+            if order.is_empty() {
+                order = maps.iter().map(|map| map.info.display_name.clone()).collect();
+            }
+        }
+
+        Self {
+            mode: settings.mode,
+            order,
+            next: 0,
+        }
+    }
+
+    // Called with the first map of a match so the rotation carries on from it
+    pub fn start_from(&mut self, first_map: &Map, maps: &[Map], rng: &mut StdRng) {
+        match self.mode {
+            MapRotationMode::Playlist => {
+                if let Some(index) = self
+                    .order
+                    .iter()
+                    .position(|name| name == &first_map.info.display_name)
+                {
+                    self.next = (index + 1) % self.order.len();
+                }
+            }
+            MapRotationMode::Shuffle => {
+                // The first map counts as already played this time round
+                self.order = maps
+                    .iter()
+                    .map(|map| map.info.display_name.clone())
+                    .filter(|name| name != &first_map.info.display_name)
+                    .collect();
+                self.order.shuffle(rng);
+                self.next = 0;
+            }
+            _ => {}
+        }
+    }
+
+    // None means the players have to vote for the next map
+    pub fn next_map(&mut self, maps: &[Map], current_map: &Map, rng: &mut StdRng) -> Option<Map> {
+        match self.mode {
+            MapRotationMode::Vote => None,
+            MapRotationMode::Random => maps.choose(rng).cloned(),
+            MapRotationMode::Playlist => {
+                let name = self.order[self.next % self.order.len()].clone();
+                self.next = (self.next + 1) % self.order.len();
+                find_map(maps, &name)
+            }
+            MapRotationMode::Shuffle => {
+                if self.next >= self.order.len() {
+                    self.order = maps.iter().map(|map| map.info.display_name.clone()).collect();
+                    self.order.shuffle(rng);
+                    self.next = 0;
+
+                    // Dont play the same map twice in a row when a new shuffle starts
+                    if self.order.len() > 1 && self.order[0] == current_map.info.display_name {
+                        let last = self.order.len() - 1;
+                        self.order.swap(0, last);
+                    }
+                }
+
+                let name = self.order[self.next].clone();
+                self.next += 1;
+                find_map(maps, &name)
+            }
+        }
+    }
+}
+
 impl TransmuteAsset for Map {
     fn transmute_assets(
         &self,
@@ -195,6 +327,20 @@ pub fn spawn_sprites(commands: &mut Commands, bundles: Vec<PossibleBundle>) {
 #[derive(Component)]
 pub struct MapComponent;
 
+// Jump reset and death colliders are sensors so players go through them
+pub fn insert_map_collider_type(collider: &mut EntityCommands, collider_type: AAColliderType) {
+    match collider_type {
+        AAColliderType::Solid => collider.insert(SolidColliderIdentifier {}),
+        AAColliderType::JumpReset => collider
+            .insert(JumpResetColliderIdentifier {})
+            .insert(Sensor(true)),
+        AAColliderType::Death => collider
+            .insert(DeathColliderIdentifier {})
+            .insert(Sensor(true)),
+    }
+    .insert(MapComponent);
+}
+
 pub fn load_map(
     mut game: ResMut<Game>,
     asset_server: Res<AssetServer>,
@@ -207,13 +353,15 @@ pub fn load_map(
     svg_assets: Res<Assets<Svg>>,
     window_descriptor: Res<WindowDescriptor>,
     mut state: ResMut<State<AppStates>>,
+    settings: Res<GameSettings>,
+    clock: Res<GameClock>,
 ) {
     game.selected_map.char_element_colours.health_colour_distr =
         Vec3::from(game.selected_map.char_element_colours.healthbar_min)
             - Vec3::from(game.selected_map.char_element_colours.healthbar_max);
     let map = game.selected_map.clone();
     let asset_map = asset_dir.map_assets.clone();
-    let resolution = get_resolution(windows);
+    let resolution = get_resolution(windows, &window_descriptor);
     let rescale_resolution = (Vec2::from(map.info.base_dimensions) / resolution).y;
 
     // Since we cant modify the entire scale of the engine anymore post 0.12.0 or until the author updates it
@@ -225,6 +373,16 @@ pub fn load_map(
     let (texture, texture_over, texture_under) = get_bg_textures(&map, &asset_server, &asset_map);
 
     let screen_ratio = winit_info.screen_dim;
+    let zoom_out = settings.camera.max_zoom.max(1.0);
+    spawn_parallax_layers(
+        &mut commands,
+        &map.background.layer,
+        &asset_map,
+        Vec2::from(map.info.base_dimensions),
+        rescale_resolution,
+        screen_ratio * zoom_out,
+        clock.seconds(),
+    );
     create_bgs(
         &mut commands,
         resolution,
@@ -233,6 +391,10 @@ pub fn load_map(
         texture_over,
         texture_under,
         svg_assets,
+        &map.background.motion,
+        rescale_resolution,
+        zoom_out,
+        clock.seconds(),
     );
 
     let sprites = get_sprites(
@@ -250,31 +412,26 @@ pub fn load_map(
 
     let mut collider_map = map.collider.get_hitbox_bundles(rescale_resolution);
 
+    // Same space the colliders are in, so everyone agrees on where the edges are whatever their window is
+    commands.insert_resource(MapBounds {
+        half_extents: Vec2::from(map.info.base_dimensions) / rescale_resolution / 2.0,
+    });
+
+    // Platforms take their colliders out first so they dont get left behind as part of the map
+    spawn_platforms(
+        &mut commands,
+        &map.platform,
+        &mut collider_map,
+        &asset_map,
+        rescale_resolution,
+        clock.gameplay_seconds(),
+    );
+
     println!("spawning colliders for map");
     for (key, remaining_colliders) in collider_map {
         for remaining_collider in remaining_colliders {
-            match remaining_collider.collider_type {
-                AAColliderType::Solid => {
-                    commands
-                        .spawn_bundle(remaining_collider)
-                        .insert(SolidColliderIdentifier {})
-                        .insert(MapComponent);
-                }
-                AAColliderType::JumpReset => {
-                    commands
-                        .spawn_bundle(remaining_collider)
-                        .insert(JumpResetColliderIdentifier {})
-                        .insert(MapComponent)
-                        .insert(Sensor(true));
-                }
-                AAColliderType::Death => {
-                    commands
-                        .spawn_bundle(remaining_collider)
-                        .insert(DeathColliderIdentifier {})
-                        .insert(MapComponent)
-                        .insert(Sensor(true));
-                }
-            }
+            let collider_type = remaining_collider.collider_type;
+            insert_map_collider_type(&mut commands.spawn_bundle(remaining_collider), collider_type);
         }
     }
     println!("done spawning colliders for map");
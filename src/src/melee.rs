@@ -0,0 +1,99 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::char::AAPlayerDescriptor;
+use crate::game::*;
+use crate::projectile::ProjectileIdentifier;
+use crate::projectile_behaviour::ProjectileBehaviour;
+
+// melee = { ... } on a projectile, it gets held onto the core by a joint instead of flying off on its own.
+// Where it sits is the same spawn_origin settings as any other projectile, its shape is its physobj_id's colliders
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct Melee {
+    // Frames the hitbox is out for, this replaces the projectile's lifetime
+    pub active_frames: u64,
+
+    // Frames before the same player can get hit by it again, 0 means each player only gets hit once
+    pub rehit_frames: u64,
+
+    // Getting put into hitstun takes the hitbox away
+    pub cancel_on_hitstun: bool,
+}
+
+impl Default for Melee {
+    fn default() -> Self {
+        Self {
+            active_frames: 10,
+            rehit_frames: 0,
+            cancel_on_hitstun: true,
+        }
+    }
+}
+
+impl Melee {
+    pub fn can_hit(&self, last_hit: Option<&u64>, clock: &GameClock) -> bool {
+        match last_hit {
+            None => true,
+            Some(_) if self.rehit_frames == 0 => false,
+            Some(last_hit) => clock.gameplay_frame >= last_hit + self.rehit_frames,
+        }
+    }
+}
+
+pub fn melee_hitstun_cancel(
+    clock: Res<GameClock>,
+    player_query: Query<&AAPlayerDescriptor>,
+    mut projectile_query: Query<&mut ProjectileIdentifier>,
+) {
+    for mut projectile_id in projectile_query.iter_mut() {
+        let melee = match projectile_id.melee {
+            Some(melee) => melee,
+            None => continue,
+        };
+
+        // A hitbox is nothing without someone to hold it
+        let cancelled = match player_query.get(projectile_id.parent) {
+            Ok(descriptor) => melee.cancel_on_hitstun && descriptor.in_hitstun(&clock),
+            Err(_) => true,
+        };
+
+        if cancelled && projectile_id.lifetime > 0.0 {
+            // Gone the next time projectile_lifetimes runs, a cancelled swing doesnt split or explode
+            projectile_id.lifetime = 0.0;
+            projectile_id.behaviour = ProjectileBehaviour::default();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at_frame(gameplay_frame: u64) -> GameClock {
+        let mut clock = GameClock::new(60.0);
+        clock.gameplay_frame = gameplay_frame;
+        clock
+    }
+
+    #[test]
+    fn rehit_waits_for_rehit_frames() {
+        let melee = Melee {
+            rehit_frames: 10,
+            ..Default::default()
+        };
+
+        assert!(melee.can_hit(None, &at_frame(5)));
+        assert!(!melee.can_hit(Some(&5), &at_frame(5)));
+        assert!(!melee.can_hit(Some(&5), &at_frame(14)));
+        assert!(melee.can_hit(Some(&5), &at_frame(15)));
+    }
+
+    #[test]
+    fn no_rehit_frames_only_hits_once() {
+        let melee = Melee::default();
+
+        assert!(melee.can_hit(None, &at_frame(0)));
+        assert!(!melee.can_hit(Some(&0), &at_frame(1000)));
+    }
+}
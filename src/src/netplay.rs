@@ -0,0 +1,1583 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Mutex;
+
+use bevy::ecs::event::ManualEventReader;
+use bevy::ecs::system::{CommandQueue, System, SystemState};
+use bevy::prelude::*;
+use bevy::transform::transform_propagate_system::transform_propagate_system;
+use bevy_rapier2d::plugin::systems::{
+    init_colliders, init_joints, init_rigid_bodies, sync_removals,
+};
+use bevy_rapier2d::prelude::*;
+use bevy_rapier2d::rapier::math::{Real, Vector};
+use bevy_rapier2d::rapier::prelude::{
+    ColliderSet, CollisionEvent as RapierCollisionEvent, ContactPair, EventHandler,
+    RigidBody as RapierRigidBody, RigidBodyHandle, RigidBodySet,
+};
+use rand::prelude::SliceRandom;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+use crate::assets::AugmentedFonts;
+use crate::audio::CharacterSoundEvent;
+use crate::char::{
+    check_cooldowns, AAPlayerDescriptor, AttackBuffer, AttackCooldown, AttackInstanceDirectory,
+    CharComponentMap, Character, ConvertToRgb, PlayerHealth, PlayerIdentifier, PlayerStocks,
+};
+use crate::collider::ColliderSyncEntity;
+use crate::combo::ComboId;
+use crate::game::*;
+use crate::maps::{Map, MapRotation};
+use crate::melee::melee_hitstun_cancel;
+use crate::platform::{move_platforms, platform_riders};
+use crate::projectile::{
+    execute_unused_actions, projectile_lifetimes, InstanceItems, InstanceMap,
+    ProjectileIdentifier, ProjectileSource, UnusedAction,
+};
+use crate::projectile_behaviour::{
+    projectile_bounces, projectile_expiry_effects, projectile_homing, ProjectileExpired,
+};
+use crate::shield::{shield_regen, PlayerShield};
+use crate::{AppStates, CharacterInputIdentifierMap, SelectedCharacters};
+
+// Everything that gets sent between the two sides of a netplay match
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum NetplayPacket {
+    // The characters picked on one side, in the order their players joined
+    Setup { characters: Vec<String> },
+
+    // Sent by the host once it knows about both sides, everything needed to start the same match
+    Start {
+        seed: u64,
+        map: String,
+        host_players: u64,
+        characters: Vec<(u64, String)>,
+
+        // The host's ruleset is the one both sides play by
+        ruleset: RulesetSettings,
+    },
+
+    // Every local input from first_frame onwards, next_needed_frame acks what we have from the other side
+    Inputs {
+        round: u64,
+        next_needed_frame: u64,
+        first_frame: u64,
+        frames: Vec<Vec<PlayerInput>>,
+    },
+}
+
+pub struct NetplaySocket {
+    outbound: UnboundedSender<NetplayPacket>,
+    inbound: Mutex<Receiver<NetplayPacket>>,
+}
+
+impl NetplaySocket {
+    pub fn bind(settings: &NetplaySettings) -> anyhow::Result<Self> {
+        let remote_address: SocketAddr = settings.remote_address.parse()?;
+        let local_address =
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), settings.local_port);
+
+        Self::open(std::net::UdpSocket::bind(local_address)?, remote_address)
+    }
+
+    pub fn open(socket: std::net::UdpSocket, remote_address: SocketAddr) -> anyhow::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        socket.set_nonblocking(true)?;
+        let socket = {
+            let _runtime_guard = runtime.enter();
+            UdpSocket::from_std(socket)?
+        };
+
+        let (outbound, mut outbound_receiver) = unbounded_channel::<NetplayPacket>();
+        let (inbound_sender, inbound) = channel::<NetplayPacket>();
+
+        // The socket gets its own thread so the game never has to wait on the network
+        std::thread::spawn(move || {
+            runtime.block_on(async move {
+                let mut buffer = [0u8; 8192];
+                loop {
+                    tokio::select! {
+                        packet = outbound_receiver.recv() => {
+                            let packet = match packet {
+                                // The socket resource was dropped so the match is over
+                                None => return,
+                                Some(packet) => packet,
+                            };
+
+                            match bincode::serialize(&packet) {
+                                Ok(bytes) => {
+                                    if let Err(error) = socket.send_to(&bytes, remote_address).await {
+                                        warn!("Failed to send netplay packet: {}", error);
+                                    }
+                                }
+                                Err(error) => warn!("Failed to serialize netplay packet: {}", error),
+                            }
+                        }
+                        received = socket.recv_from(&mut buffer) => {
+                            match received {
+                                Ok((length, from)) => {
+                                    if from != remote_address {
+                                        continue;
+                                    }
+
+                                    match bincode::deserialize::<NetplayPacket>(&buffer[..length]) {
+                                        Ok(packet) => {
+                                            if inbound_sender.send(packet).is_err() {
+                                                return;
+                                            }
+                                        }
+                                        Err(error) => warn!("Got a malformed netplay packet: {}", error),
+                                    }
+                                }
+                                // Usually just the other side not listening yet, they'll get the next one
+                                Err(_error) => {}
+                            }
+                        }
+                    }
+                }
+            });
+        });
+
+        Ok(Self {
+            outbound,
+            inbound: Mutex::new(inbound),
+        })
+    }
+
+    pub fn send(&self, packet: NetplayPacket) {
+        if self.outbound.send(packet).is_err() {
+            warn!("Netplay socket thread has stopped");
+        }
+    }
+
+    pub fn receive(&self) -> Vec<NetplayPacket> {
+        match self.inbound.lock() {
+            Ok(inbound) => inbound.try_iter().collect(),
+            Err(_) => vec![],
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PlayerSnapshot {
+    pub entity: Entity,
+    pub transform: Transform,
+    pub velocity: Velocity,
+    pub external_force: ExternalForce,
+    pub damping: Damping,
+    pub descriptor: AAPlayerDescriptor,
+    pub health: PlayerHealth,
+    pub attack_buffer: AttackBuffer,
+    pub stocks: Option<PlayerStocks>,
+    pub shield: Option<PlayerShield>,
+}
+
+#[derive(Clone, Copy)]
+pub struct BodySnapshot {
+    pub translation: Vector<Real>,
+    pub rotation: Real,
+    pub linvel: Vector<Real>,
+    pub angvel: Real,
+}
+
+impl BodySnapshot {
+    pub fn from_body(body: &RapierRigidBody) -> Self {
+        Self {
+            translation: *body.translation(),
+            rotation: body.rotation().angle(),
+            linvel: *body.linvel(),
+            angvel: body.angvel(),
+        }
+    }
+
+    pub fn apply(&self, body: &mut RapierRigidBody) {
+        body.set_translation(self.translation, true);
+        body.set_rotation(self.rotation, true);
+        body.set_linvel(self.linvel, true);
+        body.set_angvel(self.angvel, true);
+    }
+}
+
+#[derive(Clone)]
+pub struct ProjectileSnapshot {
+    pub entity: Entity,
+    pub identifier: ProjectileIdentifier,
+    pub source: ProjectileSource,
+    pub transform: Transform,
+    pub velocity: Velocity,
+}
+
+// Everything that inputs can change during a frame, taken before the frame's inputs are applied
+#[derive(Clone)]
+pub struct FrameSnapshot {
+    pub frame: u64,
+    pub players: Vec<PlayerSnapshot>,
+    pub projectiles: Vec<ProjectileSnapshot>,
+    pub bodies: HashMap<RigidBodyHandle, BodySnapshot>,
+    pub cooldowns: HashMap<u64, HashMap<ComboId, AttackCooldown>>,
+    // Attacks part way through their actions, with the projectiles they've spawned so far
+    pub timelines: Vec<UnusedAction>,
+    pub attack_instances: HashMap<u64, InstanceMap>,
+    pub gameplay_frame: u64,
+
+    // Collisions from the step before this frame, they get handled during it
+    pub collision_events: Vec<CollisionEvent>,
+}
+
+impl FrameSnapshot {
+    pub fn capture(world: &mut World, frame: u64, collision_events: Vec<CollisionEvent>) -> Self {
+        let mut player_query = world.query_filtered::<(
+            Entity,
+            &Transform,
+            &Velocity,
+            &ExternalForce,
+            &Damping,
+            &AAPlayerDescriptor,
+            &PlayerHealth,
+            &AttackBuffer,
+            Option<&PlayerStocks>,
+            Option<&PlayerShield>,
+        ), With<PlayerIdentifier>>();
+
+        let players = player_query
+            .iter(world)
+            .map(
+                |(
+                    entity,
+                    transform,
+                    velocity,
+                    external_force,
+                    damping,
+                    descriptor,
+                    health,
+                    attack_buffer,
+                    stocks,
+                    shield,
+                )| {
+                    PlayerSnapshot {
+                        entity,
+                        transform: *transform,
+                        velocity: *velocity,
+                        external_force: *external_force,
+                        damping: *damping,
+                        descriptor: descriptor.clone(),
+                        health: *health,
+                        attack_buffer: attack_buffer.clone(),
+                        stocks: stocks.copied(),
+                        shield: shield.copied(),
+                    }
+                },
+            )
+            .collect();
+
+        let mut projectile_query = world.query::<(
+            Entity,
+            &ProjectileIdentifier,
+            &ProjectileSource,
+            &Transform,
+            &Velocity,
+        )>();
+
+        let projectiles = projectile_query
+            .iter(world)
+            .map(
+                |(entity, identifier, source, transform, velocity)| ProjectileSnapshot {
+                    entity,
+                    identifier: identifier.clone(),
+                    source: source.clone(),
+                    transform: *transform,
+                    velocity: *velocity,
+                },
+            )
+            .collect();
+
+        let bodies = world
+            .resource::<RapierContext>()
+            .bodies
+            .iter()
+            .map(|(handle, body)| (handle, BodySnapshot::from_body(body)))
+            .collect();
+
+        let attack_directory = world.resource::<AttackInstanceDirectory>();
+        let cooldowns = attack_directory.cooldown.clone();
+        let timelines = attack_directory.unexecuted_actions.clone();
+        let attack_instances = attack_directory.attack_instances.clone();
+        let gameplay_frame = world.resource::<GameClock>().gameplay_frame;
+
+        Self {
+            frame,
+            players,
+            projectiles,
+            bodies,
+            cooldowns,
+            timelines,
+            attack_instances,
+            gameplay_frame,
+            collision_events,
+        }
+    }
+
+    pub fn restore(&self, world: &mut World) -> HashMap<Entity, Entity> {
+        let mut player_query = world.query::<(
+            &mut Transform,
+            &mut Velocity,
+            &mut ExternalForce,
+            &mut Damping,
+            &mut AAPlayerDescriptor,
+            &mut PlayerHealth,
+            &mut AttackBuffer,
+            Option<&mut PlayerStocks>,
+            Option<&mut PlayerShield>,
+        )>();
+
+        for player in &self.players {
+            // Players despawned since the snapshot cant be brought back, so rolling back past a death can desync
+            if let Ok((
+                mut transform,
+                mut velocity,
+                mut external_force,
+                mut damping,
+                mut descriptor,
+                mut health,
+                mut attack_buffer,
+                stocks,
+                shield,
+            )) = player_query.get_mut(world, player.entity)
+            {
+                *transform = player.transform;
+                *velocity = player.velocity;
+                *external_force = player.external_force;
+                *damping = player.damping;
+                *descriptor = player.descriptor.clone();
+                *health = player.health;
+                *attack_buffer = player.attack_buffer.clone();
+                if let (Some(mut stocks), Some(snapshot_stocks)) = (stocks, player.stocks) {
+                    *stocks = snapshot_stocks;
+                }
+                if let (Some(mut shield), Some(snapshot_shield)) = (shield, player.shield) {
+                    *shield = snapshot_shield;
+                }
+            }
+        }
+
+        let mut context = world.resource_mut::<RapierContext>();
+        for (handle, body_snapshot) in &self.bodies {
+            if let Some(body) = context.bodies.get_mut(*handle) {
+                body_snapshot.apply(body);
+            }
+        }
+
+        let respawned = self.restore_projectiles(world);
+
+        let mut attack_directory = world.resource_mut::<AttackInstanceDirectory>();
+        attack_directory.cooldown = self.cooldowns.clone();
+        attack_directory.unexecuted_actions = self.timelines.clone();
+        attack_directory.attack_instances = self.attack_instances.clone();
+        remap_instances(&mut attack_directory.attack_instances, &respawned);
+        world.resource_mut::<GameClock>().gameplay_frame = self.gameplay_frame;
+
+        respawned
+    }
+
+    // Projectiles that ended after the snapshot are fired again, returns the new entity for each of them
+    fn restore_projectiles(&self, world: &mut World) -> HashMap<Entity, Entity> {
+        let mut respawned = HashMap::new();
+        let mut system_state: SystemState<(
+            Commands,
+            Res<CharComponentMap>,
+            Res<GameClock>,
+            Query<(&mut ProjectileIdentifier, &mut Transform, &mut Velocity)>,
+        )> = SystemState::new(world);
+        let (mut commands, char_map, clock, mut projectile_query) = system_state.get_mut(world);
+
+        for projectile in &self.projectiles {
+            if let Ok((mut identifier, mut transform, mut velocity)) =
+                projectile_query.get_mut(projectile.entity)
+            {
+                *identifier = projectile.identifier.clone();
+                *transform = projectile.transform;
+                *velocity = projectile.velocity;
+                continue;
+            }
+
+            let char_entities = match char_map.get(&projectile.identifier.owner) {
+                Some(char_entities) => char_entities,
+                None => continue,
+            };
+
+            // Rapier builds the body from these once it picks the new entity up
+            let mut transform = projectile.transform;
+            if let Some(entity) = projectile.source.projectile.spawn_projectile(
+                &projectile.source.facing,
+                char_entities,
+                &mut AAPlayerDescriptor::default(),
+                &mut transform,
+                &mut commands,
+                &mut InstanceMap::new(),
+                &clock,
+            ) {
+                commands
+                    .entity(entity)
+                    .insert(projectile.transform)
+                    .insert(projectile.velocity)
+                    .insert(projectile.identifier.clone());
+                respawned.insert(projectile.entity, entity);
+            }
+        }
+
+        system_state.apply(world);
+        respawned
+    }
+
+    // Keeps older snapshots pointing at projectiles that have been fired again
+    fn remap_projectiles(&mut self, respawned: &HashMap<Entity, Entity>) {
+        for projectile in &mut self.projectiles {
+            if let Some(entity) = respawned.get(&projectile.entity) {
+                projectile.entity = *entity;
+            }
+        }
+        remap_instances(&mut self.attack_instances, respawned);
+    }
+}
+
+fn remap_instances(
+    attack_instances: &mut HashMap<u64, InstanceMap>,
+    respawned: &HashMap<Entity, Entity>,
+) {
+    if respawned.is_empty() {
+        return;
+    }
+
+    for instance_map in attack_instances.values_mut() {
+        for items in instance_map.values_mut() {
+            for item in items.iter_mut() {
+                if let InstanceItems::Projectile(entity) = item {
+                    if let Some(respawned_entity) = respawned.get(entity) {
+                        *entity = *respawned_entity;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Collects the collision events of resimulated frames so they can be sent on like rapier's own
+#[derive(Default)]
+pub struct ResimEventCollector {
+    pub events: Mutex<Vec<RapierCollisionEvent>>,
+}
+
+impl EventHandler for ResimEventCollector {
+    fn handle_collision_event(
+        &self,
+        _bodies: &RigidBodySet,
+        _colliders: &ColliderSet,
+        event: RapierCollisionEvent,
+        _contact_pair: Option<&ContactPair>,
+    ) {
+        if let Ok(mut events) = self.events.lock() {
+            events.push(event);
+        }
+    }
+}
+
+type BoxedSystem = Box<dyn System<In = (), Out = ()>>;
+
+fn boxed<Params>(system: impl IntoSystem<(), (), Params>) -> BoxedSystem {
+    Box::new(IntoSystem::into_system(system))
+}
+
+// Own copies of the systems that make up a frame of gameplay, so they can be run again for frames being rolled back through.
+// Kept in the same order as gameplay_frame_set so a resimulated frame plays out like it did live
+pub struct NetplayResimulator {
+    pub input_systems: Vec<BoxedSystem>,
+    pub gameplay_systems: Vec<BoxedSystem>,
+    // Gives anything spawned during the frame its rapier body before the step
+    pub physics_systems: Vec<BoxedSystem>,
+
+    // Swapped in while resimulating, collisions only get handled on the frame after their step like they are live
+    // and sounds that already played dont play again
+    pub collision_events: Events<CollisionEvent>,
+    pub sound_events: Events<CharacterSoundEvent>,
+    pub expired_events: Events<ProjectileExpired>,
+    pub initialised: bool,
+}
+
+impl NetplayResimulator {
+    pub fn new() -> Self {
+        Self {
+            input_systems: vec![boxed(movement_input_system)],
+            gameplay_systems: vec![
+                boxed(check_cooldowns),
+                boxed(enforce_char_collision_dominance),
+                boxed(shield_regen),
+                boxed(execute_unused_actions),
+                boxed(projectile_lifetimes),
+                boxed(projectile_homing),
+                boxed(projectile_bounces),
+                boxed(projectile_expiry_effects),
+                boxed(melee_hitstun_cancel),
+                boxed(platform_riders),
+                boxed(move_platforms),
+                boxed(out_of_bounds_check),
+                boxed(stock_loss_check),
+                boxed(health_despawn_check),
+                boxed(collision_process),
+            ],
+            physics_systems: vec![
+                boxed(transform_propagate_system),
+                boxed(sync_removals),
+                boxed(init_rigid_bodies),
+                boxed(init_colliders),
+                boxed(init_joints),
+            ],
+            collision_events: Events::default(),
+            sound_events: Events::default(),
+            expired_events: Events::default(),
+            initialised: false,
+        }
+    }
+
+    fn run_systems(systems: &mut [BoxedSystem], world: &mut World) {
+        for system in systems.iter_mut() {
+            system.run((), world);
+            system.apply_buffers(world);
+        }
+    }
+
+    fn swap_events(&mut self, world: &mut World) {
+        std::mem::swap(
+            &mut *world.resource_mut::<Events<CollisionEvent>>(),
+            &mut self.collision_events,
+        );
+        std::mem::swap(
+            &mut *world.resource_mut::<Events<CharacterSoundEvent>>(),
+            &mut self.sound_events,
+        );
+        std::mem::swap(
+            &mut *world.resource_mut::<Events<ProjectileExpired>>(),
+            &mut self.expired_events,
+        );
+    }
+
+    // Everything up to the physics step of a frame, collision_events are the ones from the step before it
+    pub fn run_frame(&mut self, world: &mut World, collision_events: Vec<CollisionEvent>) {
+        if !self.initialised {
+            for system in self
+                .input_systems
+                .iter_mut()
+                .chain(self.gameplay_systems.iter_mut())
+                .chain(self.physics_systems.iter_mut())
+            {
+                system.initialize(world);
+            }
+            self.initialised = true;
+        }
+
+        self.collision_events.update();
+        self.sound_events.update();
+        self.expired_events.update();
+        for event in collision_events {
+            self.collision_events.send(event);
+        }
+
+        self.swap_events(world);
+        Self::run_systems(&mut self.input_systems, world);
+        Self::run_systems(&mut self.gameplay_systems, world);
+        self.swap_events(world);
+
+        Self::run_systems(&mut self.physics_systems, world);
+    }
+}
+pub struct NetplaySession {
+    pub host: bool,
+    pub host_players: u64,
+    pub local_players: Vec<u64>,
+    pub input_delay: u64,
+    pub max_rollback_frames: u64,
+    pub timestep: f32,
+
+    // Kept around so the host can send it again if it got lost
+    pub start_packet: Option<NetplayPacket>,
+
+    pub round: u64,
+    pub current_frame: u64,
+    // Every frame before this has confirmed inputs from the other side
+    pub remote_frame: u64,
+    // Every frame before this has been received by the other side
+    pub remote_ack: u64,
+    pub rollback_frame: Option<u64>,
+
+    pub local_inputs: HashMap<u64, Vec<PlayerInput>>,
+    pub remote_inputs: HashMap<u64, Vec<PlayerInput>>,
+    pub pending_local_inputs: Vec<PlayerInput>,
+    pub snapshots: VecDeque<FrameSnapshot>,
+
+    // Collisions from physics steps since the last snapshot, they go into the next one
+    pub collision_reader: ManualEventReader<CollisionEvent>,
+    pub pending_collisions: Vec<CollisionEvent>,
+}
+
+impl NetplaySession {
+    pub fn new(settings: &GameSettings, host_players: u64, local_players: Vec<u64>) -> Self {
+        Self {
+            host: settings.netplay.host,
+            host_players,
+            local_players,
+            input_delay: settings.netplay.input_delay,
+            max_rollback_frames: settings.netplay.max_rollback_frames.max(1),
+            timestep: 1.0 / settings.gameplay_settings.tick_rate as f32,
+            start_packet: None,
+            round: 0,
+            current_frame: 0,
+            remote_frame: 0,
+            remote_ack: 0,
+            rollback_frame: None,
+            local_inputs: HashMap::new(),
+            remote_inputs: HashMap::new(),
+            pending_local_inputs: vec![],
+            snapshots: VecDeque::new(),
+            collision_reader: ManualEventReader::default(),
+            pending_collisions: vec![],
+        }
+    }
+
+    pub fn start_round(&mut self) {
+        self.round += 1;
+        self.current_frame = 0;
+        self.remote_frame = 0;
+        self.remote_ack = 0;
+        self.rollback_frame = None;
+        self.local_inputs.clear();
+        self.remote_inputs.clear();
+        self.pending_local_inputs.clear();
+        self.snapshots.clear();
+        self.pending_collisions.clear();
+    }
+
+    // Too far ahead of the other side to be able to roll back, so wait for them to catch up
+    pub fn is_stalled(&self) -> bool {
+        self.current_frame >= self.remote_frame + self.max_rollback_frames
+    }
+
+    // The host's players come first, everyone after them joined on the client
+    pub fn is_remote_player(&self, player_id: u64) -> bool {
+        let host_player = player_id <= self.host_players;
+        host_player != self.host
+    }
+
+    pub fn handle_packet(&mut self, packet: NetplayPacket, socket: &NetplaySocket) {
+        match packet {
+            NetplayPacket::Setup { .. } => {
+                // The other side is still waiting on the start of the match
+                if let Some(start_packet) = &self.start_packet {
+                    socket.send(start_packet.clone());
+                }
+            }
+            NetplayPacket::Start { .. } => {}
+            NetplayPacket::Inputs {
+                round,
+                next_needed_frame,
+                first_frame,
+                frames,
+            } => {
+                if round != self.round {
+                    return;
+                }
+
+                self.remote_ack = self.remote_ack.max(next_needed_frame);
+
+                for (offset, inputs) in frames.into_iter().enumerate() {
+                    let frame = first_frame + offset as u64;
+                    if frame < self.remote_frame {
+                        continue;
+                    }
+
+                    // The other side only gets to drive its own players
+                    let inputs: Vec<PlayerInput> = inputs
+                        .into_iter()
+                        .filter(|input| self.is_remote_player(input.player_id))
+                        .collect();
+
+                    // Frames we've already run were predicted to have no remote inputs
+                    if frame < self.current_frame && !inputs.is_empty() {
+                        self.rollback_frame = Some(match self.rollback_frame {
+                            None => frame,
+                            Some(rollback_frame) => rollback_frame.min(frame),
+                        });
+                    }
+
+                    self.remote_inputs.insert(frame, inputs);
+                }
+
+                while self.remote_inputs.contains_key(&self.remote_frame) {
+                    self.remote_frame += 1;
+                }
+            }
+        }
+    }
+
+    pub fn schedule_local_inputs(&mut self) {
+        let frame = self.current_frame + self.input_delay;
+        let inputs = std::mem::take(&mut self.pending_local_inputs);
+        self.local_inputs.entry(frame).or_insert(vec![]).extend(inputs);
+    }
+
+    pub fn send_inputs(&self, socket: &NetplaySocket) {
+        let last_frame = self.current_frame + self.input_delay;
+        let frames = (self.remote_ack..=last_frame)
+            .map(|frame| self.local_inputs.get(&frame).cloned().unwrap_or_default())
+            .collect();
+
+        socket.send(NetplayPacket::Inputs {
+            round: self.round,
+            next_needed_frame: self.remote_frame,
+            first_frame: self.remote_ack,
+            frames,
+        });
+    }
+
+    // Inputs are always applied in the same order on both sides, host players first
+    pub fn frame_inputs(&self, frame: u64) -> Vec<PlayerInput> {
+        let local = self.local_inputs.get(&frame).cloned().unwrap_or_default();
+        let remote = self.remote_inputs.get(&frame).cloned().unwrap_or_default();
+
+        if self.host {
+            local.into_iter().chain(remote).collect()
+        } else {
+            remote.into_iter().chain(local).collect()
+        }
+    }
+
+    pub fn store_snapshot(&mut self, snapshot: FrameSnapshot) {
+        self.snapshots.retain(|stored| stored.frame != snapshot.frame);
+        self.snapshots.push_back(snapshot);
+    }
+
+    pub fn snapshot(&self, frame: u64) -> Option<&FrameSnapshot> {
+        self.snapshots.iter().find(|snapshot| snapshot.frame == frame)
+    }
+
+    // Nothing before the oldest unconfirmed frame can be rolled back to anymore
+    pub fn prune(&mut self) {
+        let oldest_needed = self.remote_frame.min(self.current_frame);
+        self.snapshots.retain(|snapshot| snapshot.frame >= oldest_needed);
+
+        let oldest_unacked = self.remote_ack.min(oldest_needed);
+        self.local_inputs.retain(|frame, _| *frame >= oldest_unacked);
+        self.remote_inputs.retain(|frame, _| *frame >= oldest_needed);
+    }
+}
+
+// Runs at the end of First, swapping the locally collected inputs for the ones that are actually due this frame
+pub fn netplay_input_system(world: &mut World) {
+    if !world.contains_resource::<NetplaySession>() {
+        return;
+    }
+
+    let packets = match world.get_resource::<NetplaySocket>() {
+        None => return,
+        Some(socket) => socket.receive(),
+    };
+
+    let in_game = world.resource::<State<AppStates>>().current() == &AppStates::LoadGame;
+
+    world.resource_scope(|world, mut session: Mut<NetplaySession>| {
+        for packet in packets {
+            session.handle_packet(packet, world.resource::<NetplaySocket>());
+        }
+
+        if !in_game {
+            return;
+        }
+
+        let session = &mut *session;
+        let collision_events = world.resource::<Events<CollisionEvent>>();
+        session
+            .pending_collisions
+            .extend(session.collision_reader.iter(collision_events).cloned());
+
+        let local_inputs = std::mem::take(&mut world.resource_mut::<PlayerInputQueue>().inputs);
+        session.pending_local_inputs.extend(
+            local_inputs
+                .into_iter()
+                .filter(|input| session.local_players.contains(&input.player_id)),
+        );
+
+        // The game clock has to stop with physics, otherwise cooldowns would run on while the other side catches up
+        let stalled = session.is_stalled();
+        world.resource_mut::<RapierConfiguration>().physics_pipeline_active = !stalled;
+        world.resource_mut::<GameClock>().paused = stalled;
+
+        if stalled {
+            session.send_inputs(world.resource::<NetplaySocket>());
+            return;
+        }
+
+        // Faster displays get updates with no gameplay frame in them, local inputs wait for the next one
+        if world.resource::<GameClock>().frames_due == 0 {
+            return;
+        }
+
+        session.schedule_local_inputs();
+        session.send_inputs(world.resource::<NetplaySocket>());
+
+        if let Some(rollback_frame) = session.rollback_frame.take() {
+            resimulate(world, session, rollback_frame);
+        }
+
+        let current_frame = session.current_frame;
+        let collision_events = std::mem::take(&mut session.pending_collisions);
+        session.store_snapshot(FrameSnapshot::capture(
+            world,
+            current_frame,
+            collision_events,
+        ));
+
+        world.resource_mut::<PlayerInputQueue>().inputs = session.frame_inputs(current_frame);
+        session.current_frame += 1;
+        session.prune();
+    });
+}
+
+fn resimulate(world: &mut World, session: &mut NetplaySession, from_frame: u64) {
+    let mut snapshot = match session.snapshot(from_frame) {
+        None => {
+            warn!(
+                "Cant roll back to frame {} as its snapshot is gone, the match may desync",
+                from_frame
+            );
+            return;
+        }
+        Some(snapshot) => snapshot.clone(),
+    };
+
+    // Bodies created after the snapshot dont get stepped again, remember where they are so they can be put back
+    let live_state = FrameSnapshot::capture(world, session.current_frame, vec![]);
+    despawn_projectiles_since(world, &snapshot);
+    let respawned = snapshot.restore(world);
+    snapshot.remap_projectiles(&respawned);
+    for stored in session.snapshots.iter_mut() {
+        stored.remap_projectiles(&respawned);
+    }
+
+    let mut collision_events = snapshot.collision_events.clone();
+    world.resource_scope(|world, mut resimulator: Mut<NetplayResimulator>| {
+        for frame in from_frame..session.current_frame {
+            if frame != from_frame {
+                let snapshot = FrameSnapshot::capture(world, frame, collision_events.clone());
+                session.store_snapshot(snapshot);
+            }
+
+            // Same as advance_game_clock does right before the inputs get applied live
+            world.resource_mut::<GameClock>().gameplay_frame += 1;
+            world.resource_mut::<PlayerInputQueue>().inputs = session.frame_inputs(frame);
+            resimulator.run_frame(world, std::mem::take(&mut collision_events));
+
+            let stepped = stepped_bodies(world, &snapshot);
+            let collector = ResimEventCollector::default();
+            push_bodies(world, &stepped);
+            step_physics(world, session.timestep, &collector);
+            pull_bodies(world, &stepped);
+            collision_events = convert_resimulated_events(world, collector);
+        }
+    });
+
+    let mut context = world.resource_mut::<RapierContext>();
+    for (handle, body_snapshot) in &live_state.bodies {
+        if snapshot.bodies.contains_key(handle) {
+            continue;
+        }
+
+        if let Some(body) = context.bodies.get_mut(*handle) {
+            body_snapshot.apply(body);
+        }
+    }
+
+    // The events from the mispredicted step are replaced by the ones from stepping that frame again
+    let mut live_events = world.resource_mut::<Events<CollisionEvent>>();
+    live_events.clear();
+    for event in collision_events.iter() {
+        live_events.send(event.clone());
+    }
+    session.collision_reader = live_events.get_reader_current();
+    session.pending_collisions = collision_events;
+
+    // Anything held back from the mispredicted step was just stepped again, so it's already in there
+    world.resource_mut::<HeldCollisionEvents>().events.clear();
+}
+
+// Projectiles fired after the snapshot are fired again while resimulating, so the old ones have to go
+fn despawn_projectiles_since(world: &mut World, snapshot: &FrameSnapshot) {
+    let mut projectile_query =
+        world.query_filtered::<(Entity, &ColliderSyncEntity), With<ProjectileIdentifier>>();
+
+    let mut queue = CommandQueue::default();
+    let mut commands = Commands::new(&mut queue, world);
+
+    for (entity, sync) in projectile_query.iter(world) {
+        let existed = snapshot
+            .projectiles
+            .iter()
+            .any(|projectile| projectile.entity == entity);
+
+        if !existed {
+            sync.despawn_self(&mut commands);
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    queue.apply(world);
+}
+
+// Everything in the snapshot plus any projectiles fired since, bodies spawned by anything else are left where they are
+fn stepped_bodies(world: &mut World, snapshot: &FrameSnapshot) -> HashSet<RigidBodyHandle> {
+    let mut projectile_query =
+        world.query_filtered::<&RapierRigidBodyHandle, With<ProjectileIdentifier>>();
+
+    snapshot
+        .bodies
+        .keys()
+        .copied()
+        .chain(projectile_query.iter(world).map(|handle| handle.0))
+        .collect()
+}
+
+fn push_bodies(world: &mut World, stepped: &HashSet<RigidBodyHandle>) {
+    let mut body_query =
+        world.query::<(&RapierRigidBodyHandle, &Velocity, &ExternalForce, Option<&Damping>)>();
+
+    let updates: Vec<(RigidBodyHandle, Velocity, ExternalForce, Option<Damping>)> = body_query
+        .iter(world)
+        .filter(|(handle, ..)| stepped.contains(&handle.0))
+        .map(|(handle, velocity, external_force, damping)| {
+            (handle.0, *velocity, *external_force, damping.copied())
+        })
+        .collect();
+
+    let mut context = world.resource_mut::<RapierContext>();
+    let scale = context.physics_scale();
+
+    for (handle, velocity, external_force, damping) in updates {
+        if let Some(body) = context.bodies.get_mut(handle) {
+            body.set_linvel(
+                Vector::new(velocity.linvel.x, velocity.linvel.y) / scale,
+                true,
+            );
+            body.set_angvel(velocity.angvel, true);
+            body.reset_forces(true);
+            body.reset_torques(true);
+            body.add_force(
+                Vector::new(external_force.force.x, external_force.force.y) / scale,
+                true,
+            );
+            body.add_torque(external_force.torque / (scale * scale), true);
+
+            if let Some(damping) = damping {
+                body.set_linear_damping(damping.linear_damping);
+                body.set_angular_damping(damping.angular_damping);
+            }
+        }
+    }
+}
+
+fn step_physics(world: &mut World, timestep: f32, collector: &ResimEventCollector) {
+    let gravity = world.resource::<RapierConfiguration>().gravity;
+    let mut context = world.resource_mut::<RapierContext>();
+    let context = &mut *context;
+    let scale = context.physics_scale();
+
+    context.integration_parameters.dt = timestep;
+    context.pipeline.step(
+        &(Vector::new(gravity.x, gravity.y) / scale),
+        &context.integration_parameters,
+        &mut context.islands,
+        &mut context.broad_phase,
+        &mut context.narrow_phase,
+        &mut context.bodies,
+        &mut context.colliders,
+        &mut context.impulse_joints,
+        &mut context.multibody_joints,
+        &mut context.ccd_solver,
+        &(),
+        collector,
+    );
+}
+
+fn pull_bodies(world: &mut World, stepped: &HashSet<RigidBodyHandle>) {
+    let context = world.resource::<RapierContext>();
+    let scale = context.physics_scale();
+    let body_states: HashMap<RigidBodyHandle, BodySnapshot> = context
+        .bodies
+        .iter()
+        .filter(|(handle, _)| stepped.contains(handle))
+        .map(|(handle, body)| (handle, BodySnapshot::from_body(body)))
+        .collect();
+
+    let mut body_query =
+        world.query::<(&RapierRigidBodyHandle, &mut Transform, &mut Velocity)>();
+
+    for (handle, mut transform, mut velocity) in body_query.iter_mut(world) {
+        if let Some(state) = body_states.get(&handle.0) {
+            transform.translation.x = state.translation.x * scale;
+            transform.translation.y = state.translation.y * scale;
+            transform.rotation = Quat::from_rotation_z(state.rotation);
+            velocity.linvel = Vec2::new(state.linvel.x, state.linvel.y) * scale;
+            velocity.angvel = state.angvel;
+        }
+    }
+}
+
+fn convert_resimulated_events(
+    world: &World,
+    collector: ResimEventCollector,
+) -> Vec<CollisionEvent> {
+    let events = collector.events.into_inner().unwrap_or_default();
+    let context = world.resource::<RapierContext>();
+
+    // Colliders store the bits of the entity they belong to as their user data
+    let collider_entity = |handle| {
+        context
+            .colliders
+            .get(handle)
+            .map(|collider| Entity::from_bits(collider.user_data as u64))
+    };
+
+    events
+        .into_iter()
+        .filter_map(|event| match event {
+            RapierCollisionEvent::Started(one, two, flags) => Some(CollisionEvent::Started(
+                collider_entity(one)?,
+                collider_entity(two)?,
+                flags,
+            )),
+            RapierCollisionEvent::Stopped(one, two, flags) => Some(CollisionEvent::Stopped(
+                collider_entity(one)?,
+                collider_entity(two)?,
+                flags,
+            )),
+        })
+        .collect()
+}
+
+#[derive(Component)]
+pub struct NetplaySyncComponent;
+
+pub fn spawn_netplay_sync(
+    mut commands: Commands,
+    settings: Res<GameSettings>,
+    fonts: Res<AugmentedFonts>,
+    mut state: ResMut<State<AppStates>>,
+) {
+    match NetplaySocket::bind(&settings.netplay) {
+        Ok(socket) => commands.insert_resource(socket),
+        Err(error) => {
+            error!("Failed to open the netplay socket: {}", error);
+            state.set(AppStates::MainMenu);
+            return;
+        }
+    };
+
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::with_section(
+                format!("Waiting for {}", settings.netplay.remote_address),
+                TextStyle {
+                    font: fonts.bold_font.clone(),
+                    font_size: settings.window.height * settings.player_id.help_text_size,
+                    color: Color::from(settings.player_id.help_text_colour.convert_to_rgb()),
+                },
+                TextAlignment {
+                    vertical: VerticalAlign::Center,
+                    horizontal: HorizontalAlign::Center,
+                },
+            ),
+            transform: Transform::from_translation(Vec3::new(0., 0., 30.)),
+            ..Default::default()
+        })
+        .insert(NetplaySyncComponent);
+}
+
+pub fn netplay_sync_update(
+    socket: Option<Res<NetplaySocket>>,
+    settings: Res<GameSettings>,
+    chars: Res<Vec<Character>>,
+    maps: Res<Vec<Map>>,
+    selected: Res<SelectedCharacters>,
+    mut character_input_map: ResMut<CharacterInputIdentifierMap>,
+    sync_components: Query<Entity, With<NetplaySyncComponent>>,
+    mut state: ResMut<State<AppStates>>,
+    mut commands: Commands,
+) {
+    let socket = match socket {
+        None => return,
+        Some(socket) => socket,
+    };
+
+    let mut local_ids: Vec<u64> = selected.characters.keys().copied().collect();
+    local_ids.sort();
+    let local_characters: Vec<String> = local_ids
+        .iter()
+        .map(|id| selected.characters[id].info.display_name.clone())
+        .collect();
+
+    socket.send(NetplayPacket::Setup {
+        characters: local_characters.clone(),
+    });
+
+    for packet in socket.receive() {
+        let start_packet = match packet {
+            NetplayPacket::Setup { characters } if settings.netplay.host => {
+                let host_players = local_characters.len() as u64;
+                let mut all_characters: Vec<(u64, String)> = local_ids
+                    .iter()
+                    .copied()
+                    .zip(local_characters.iter().cloned())
+                    .collect();
+
+                for (index, name) in characters.into_iter().enumerate() {
+                    all_characters.push((host_players + index as u64 + 1, name));
+                }
+
+                // Both sides pick the map the same way from the seed, the name is only sent to check that they did
+                let seed = rand::thread_rng().gen::<u64>();
+                let map = sorted_maps(&maps)
+                    .choose(&mut StdRng::seed_from_u64(seed))
+                    .map(|map| map.info.display_name.clone())
+                    .unwrap_or_default();
+
+                let start_packet = NetplayPacket::Start {
+                    seed,
+                    map,
+                    host_players,
+                    characters: all_characters,
+                    ruleset: settings.ruleset.clone(),
+                };
+                socket.send(start_packet.clone());
+                start_packet
+            }
+            NetplayPacket::Start { .. } if !settings.netplay.host => packet,
+            _ => continue,
+        };
+
+        let (seed, map, host_players, characters, ruleset) = match &start_packet {
+            NetplayPacket::Start {
+                seed,
+                map,
+                host_players,
+                characters,
+                ruleset,
+            } => (
+                *seed,
+                map.clone(),
+                *host_players,
+                characters.clone(),
+                ruleset.clone(),
+            ),
+            _ => continue,
+        };
+
+        // Both sides need the same content for the match to play out the same
+        let mut selected_characters = HashMap::new();
+        for (player_id, name) in &characters {
+            match chars.iter().find(|char| &char.info.display_name == name) {
+                None => {
+                    error!("Netplay match needs the character {} which isnt installed", name);
+                    state.set(AppStates::MainMenu);
+                    return;
+                }
+                Some(character) => {
+                    selected_characters.insert(*player_id, character.clone());
+                }
+            }
+        }
+
+        let available_maps = sorted_maps(&maps);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let selected_map = match available_maps.choose(&mut rng) {
+            Some(selected_map) if selected_map.info.display_name == map => selected_map.clone(),
+            _ => {
+                error!("Netplay match needs the map {} which isnt installed here", map);
+                state.set(AppStates::MainMenu);
+                return;
+            }
+        };
+
+        // Everyone on the client side comes after the host's players
+        let local_players: Vec<u64> = if settings.netplay.host {
+            local_ids.clone()
+        } else {
+            local_ids.iter().map(|id| id + host_players).collect()
+        };
+
+        if !settings.netplay.host {
+            character_input_map.map = character_input_map
+                .map
+                .drain()
+                .map(|(id, input_settings)| (id + host_players, input_settings))
+                .collect();
+        }
+
+        // Both sides need the same rotation settings, there's nobody to vote so that falls back to random
+        let mut map_rotation = MapRotation::new(&settings.map_rotation, &available_maps);
+        map_rotation.start_from(&selected_map, &available_maps, &mut rng);
+
+        commands.insert_resource(Game {
+            selected_characters,
+            available_characters: vec![],
+            selected_map,
+            available_maps,
+            seed,
+            rng,
+            map_rotation,
+            ruleset,
+        });
+
+        let mut session = NetplaySession::new(&settings, host_players, local_players);
+        if settings.netplay.host {
+            session.start_packet = Some(start_packet.clone());
+        }
+        commands.insert_resource(session);
+        commands.insert_resource(NetplayResimulator::new());
+
+        for entity in sync_components.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+
+        state.set(AppStates::LoadAssets);
+        return;
+    }
+}
+
+// read_dir doesnt give the same order everywhere, so maps are sorted before the seed picks from them
+fn sorted_maps(maps: &Vec<Map>) -> Vec<Map> {
+    let mut sorted = maps.clone();
+    sorted.sort_by(|one, two| one.info.display_name.cmp(&two.info.display_name));
+    sorted
+}
+
+pub fn netplay_start_round(session: Option<ResMut<NetplaySession>>) {
+    if let Some(mut session) = session {
+        session.start_round();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn session(host: bool) -> NetplaySession {
+        let mut settings = GameSettings::default();
+        settings.netplay.host = host;
+        settings.netplay.input_delay = 0;
+
+        let local_player = if host { 1 } else { 2 };
+        let mut session = NetplaySession::new(&settings, 1, vec![local_player]);
+        session.start_round();
+        session
+    }
+
+    // Both sockets are bound before either is opened, so each knows which port the other got
+    fn loopback_sockets() -> (NetplaySocket, NetplaySocket) {
+        let host = std::net::UdpSocket::bind("127.0.0.1:0").expect("couldnt bind test socket");
+        let client = std::net::UdpSocket::bind("127.0.0.1:0").expect("couldnt bind test socket");
+        let host_address = host.local_addr().unwrap();
+        let client_address = client.local_addr().unwrap();
+
+        (
+            NetplaySocket::open(host, client_address).unwrap(),
+            NetplaySocket::open(client, host_address).unwrap(),
+        )
+    }
+
+    // A socket that never touches the network, packets for it get pushed in by hand and anything it sends is dropped
+    fn channel_socket() -> (NetplaySocket, std::sync::mpsc::Sender<NetplayPacket>) {
+        let (outbound, _outbound_receiver) = unbounded_channel();
+        let (inbound_sender, inbound) = channel();
+        let socket = NetplaySocket {
+            outbound,
+            inbound: Mutex::new(inbound),
+        };
+        (socket, inbound_sender)
+    }
+
+    fn wait_for_packets(socket: &NetplaySocket) -> Vec<NetplayPacket> {
+        let inbound = socket.inbound.lock().unwrap();
+        let first = inbound
+            .recv_timeout(Duration::from_secs(5))
+            .expect("the other side never sent anything");
+        std::iter::once(first).chain(inbound.try_iter()).collect()
+    }
+
+    fn press(player_id: u64) -> PlayerInput {
+        PlayerInput {
+            player_id,
+            purpose: InputPurpose::Left,
+            input_type: InputKeyboardType::JustPressed,
+        }
+    }
+
+    // Does what netplay_input_system does for one frame, returns whether a rollback was needed
+    fn advance(
+        session: &mut NetplaySession,
+        socket: &NetplaySocket,
+        packets: Vec<NetplayPacket>,
+        pressed: bool,
+        confirmed: &mut Vec<Vec<PlayerInput>>,
+    ) -> bool {
+        for packet in packets {
+            session.handle_packet(packet, socket);
+        }
+
+        if session.is_stalled() {
+            session.send_inputs(socket);
+            return false;
+        }
+
+        if pressed {
+            let player_id = session.local_players[0];
+            session.pending_local_inputs.push(press(player_id));
+        }
+        session.schedule_local_inputs();
+        session.send_inputs(socket);
+
+        let rolled_back = session.rollback_frame.take().is_some();
+        session.current_frame += 1;
+
+        // Inputs for frames both sides are sure of get pruned, so they're kept before that happens
+        let oldest_needed = session.remote_frame.min(session.current_frame);
+        while (confirmed.len() as u64) < oldest_needed {
+            confirmed.push(session.frame_inputs(confirmed.len() as u64));
+        }
+        session.prune();
+        rolled_back
+    }
+
+    #[test]
+    fn loopback_peers_agree_on_inputs() {
+        let (host_socket, client_socket) = loopback_sockets();
+        let mut host = session(true);
+        let mut client = session(false);
+
+        let mut host_confirmed = vec![];
+        let mut client_confirmed = vec![];
+        let mut client_rolled_back = false;
+
+        for frame in 0..120 {
+            // The client always runs a frame before the host's inputs for it can get there
+            let packets = if frame == 0 {
+                vec![]
+            } else {
+                wait_for_packets(&client_socket)
+            };
+            client_rolled_back |= advance(
+                &mut client,
+                &client_socket,
+                packets,
+                frame % 7 == 0 && frame < 60,
+                &mut client_confirmed,
+            );
+
+            let packets = wait_for_packets(&host_socket);
+            advance(
+                &mut host,
+                &host_socket,
+                packets,
+                frame % 5 == 0 && frame < 60,
+                &mut host_confirmed,
+            );
+        }
+
+        let confirmed_frames = host_confirmed.len().min(client_confirmed.len());
+        assert!(
+            confirmed_frames > 60,
+            "only {} frames got confirmed",
+            confirmed_frames
+        );
+        assert_eq!(
+            host_confirmed[..confirmed_frames],
+            client_confirmed[..confirmed_frames]
+        );
+        // Both players pressed on frame 0
+        assert_eq!(host_confirmed[0].len(), 2);
+        assert!(client_rolled_back, "the client never had to roll back");
+    }
+
+    #[test]
+    fn remote_inputs_only_drive_remote_players() {
+        let (socket, _inbound) = channel_socket();
+        let mut host = session(true);
+
+        host.handle_packet(
+            NetplayPacket::Inputs {
+                round: host.round,
+                next_needed_frame: 0,
+                first_frame: 0,
+                frames: vec![vec![press(1), press(2)]],
+            },
+            &socket,
+        );
+
+        assert_eq!(host.frame_inputs(0), vec![press(2)]);
+    }
+
+    // Moves every player left by a unit for each frame since they pressed, stands in for the real gameplay systems
+    fn test_movement(
+        inputs: Res<PlayerInputQueue>,
+        mut players: Query<(&PlayerIdentifier, &mut Transform, &mut Velocity)>,
+    ) {
+        for (player_id, mut transform, mut velocity) in players.iter_mut() {
+            let pressed = inputs
+                .inputs
+                .iter()
+                .any(|input| input.player_id == player_id.player_id);
+            if pressed {
+                velocity.linvel.x -= 1.0;
+            }
+            transform.translation.x += velocity.linvel.x;
+        }
+    }
+
+    fn netplay_world(host: bool) -> (World, std::sync::mpsc::Sender<NetplayPacket>) {
+        let mut world = World::new();
+        let (socket, inbound) = channel_socket();
+        world.insert_resource(socket);
+        world.insert_resource(session(host));
+
+        let mut resimulator = NetplayResimulator::new();
+        resimulator.input_systems = vec![boxed(test_movement)];
+        resimulator.gameplay_systems = vec![];
+        resimulator.physics_systems = vec![];
+        world.insert_resource(resimulator);
+
+        let mut clock = GameClock::new(60.0);
+        clock.paused = false;
+        clock.frames_due = 1;
+        world.insert_resource(clock);
+        world.insert_resource(State::new(AppStates::LoadGame));
+        world.insert_resource(RapierContext::default());
+        world.insert_resource(RapierConfiguration::default());
+        world.insert_resource(PlayerInputQueue::default());
+        world.insert_resource(HeldCollisionEvents::default());
+        world.insert_resource(CharComponentMap::new());
+        world.insert_resource(AttackInstanceDirectory {
+            attack_instances: HashMap::new(),
+            previous_used_id: 0,
+            unexecuted_actions: vec![],
+            cooldown: HashMap::new(),
+        });
+        world.insert_resource(Events::<CollisionEvent>::default());
+        world.insert_resource(Events::<CharacterSoundEvent>::default());
+        world.insert_resource(Events::<ProjectileExpired>::default());
+
+        for player_id in [1, 2] {
+            world
+                .spawn()
+                .insert(PlayerIdentifier { player_id })
+                .insert(Transform::default())
+                .insert(Velocity::default())
+                .insert(ExternalForce::default())
+                .insert(Damping::default())
+                .insert(AAPlayerDescriptor::default())
+                .insert(PlayerHealth::default())
+                .insert(AttackBuffer::default());
+        }
+
+        (world, inbound)
+    }
+
+    // One live frame, the inputs netplay_input_system lines up get applied by the stand in movement
+    fn live_frame(world: &mut World, movement: &mut BoxedSystem) {
+        netplay_input_system(world);
+        world.resource_mut::<GameClock>().gameplay_frame += 1;
+        movement.run((), world);
+        movement.apply_buffers(world);
+    }
+
+    fn player_x(world: &mut World, player_id: u64) -> f32 {
+        let mut players = world.query::<(&PlayerIdentifier, &Transform)>();
+        players
+            .iter(world)
+            .find(|(identifier, _)| identifier.player_id == player_id)
+            .map(|(_, transform)| transform.translation.x)
+            .unwrap()
+    }
+
+    fn remote_press_on(frame: usize, round: u64) -> NetplayPacket {
+        let mut frames = vec![vec![]; frame + 1];
+        frames[frame].push(press(2));
+        NetplayPacket::Inputs {
+            round,
+            next_needed_frame: 0,
+            first_frame: 0,
+            frames,
+        }
+    }
+
+    #[test]
+    fn restoring_a_snapshot_puts_players_back() {
+        let (mut world, _inbound) = netplay_world(true);
+        world.resource_mut::<GameClock>().gameplay_frame = 3;
+        let snapshot = FrameSnapshot::capture(&mut world, 0, vec![]);
+
+        let mut players = world.query::<(&mut Transform, &mut Velocity, &mut PlayerHealth)>();
+        for (mut transform, mut velocity, mut health) in players.iter_mut(&mut world) {
+            transform.translation.x = 50.0;
+            velocity.linvel.x = -4.0;
+            health.current_health -= 10.0;
+        }
+        world.resource_mut::<GameClock>().gameplay_frame = 9;
+
+        snapshot.restore(&mut world);
+
+        let mut players = world.query::<(&Transform, &Velocity, &PlayerHealth)>();
+        for (transform, velocity, health) in players.iter(&world) {
+            assert_eq!(transform.translation.x, 0.0);
+            assert_eq!(velocity.linvel.x, 0.0);
+            assert_eq!(health.current_health, 0.0);
+        }
+        assert_eq!(world.resource::<GameClock>().gameplay_frame, 3);
+    }
+
+    #[test]
+    fn late_input_is_rolled_back_and_resimulated() {
+        // The remote press on frame 2 is there in time
+        let (mut on_time, on_time_inbound) = netplay_world(true);
+        let mut on_time_movement = boxed(test_movement);
+        on_time_movement.initialize(&mut on_time);
+        let round = on_time.resource::<NetplaySession>().round;
+        on_time_inbound.send(remote_press_on(2, round)).unwrap();
+        for _ in 0..6 {
+            live_frame(&mut on_time, &mut on_time_movement);
+        }
+
+        // The same press only gets here after frame 4 has already been run without it
+        let (mut late, late_inbound) = netplay_world(true);
+        let mut late_movement = boxed(test_movement);
+        late_movement.initialize(&mut late);
+        for _ in 0..5 {
+            live_frame(&mut late, &mut late_movement);
+        }
+        assert_eq!(player_x(&mut late, 2), 0.0);
+
+        late_inbound.send(remote_press_on(2, round)).unwrap();
+        live_frame(&mut late, &mut late_movement);
+
+        // Pressed on frame 2, so frames 2 to 5 each moved it once
+        assert_eq!(player_x(&mut on_time, 2), -4.0);
+        assert_eq!(player_x(&mut late, 2), player_x(&mut on_time, 2));
+        assert_eq!(player_x(&mut late, 1), 0.0);
+        assert_eq!(
+            late.resource::<GameClock>().gameplay_frame,
+            on_time.resource::<GameClock>().gameplay_frame
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use bevy::asset::{create_platform_default_asset_io, AssetIo, AssetIoError, FileAssetIo};
+use bevy::prelude::*;
+use bevy::tasks::IoTaskPool;
+use bevy::utils::BoxedFuture;
+
+// Where the asset server reads relative paths from, the manifest directory under cargo and next to the executable otherwise.
+// Packs are looked for there too so a zip is found the same way whatever directory the game was started from
+pub fn asset_root() -> PathBuf {
+    FileAssetIo::get_root_path()
+}
+
+pub fn resolve_asset_path(path: &Path) -> PathBuf {
+    match path.is_absolute() {
+        true => path.to_path_buf(),
+        false => asset_root().join(path),
+    }
+}
+
+// Packs can be dropped into maps/ and char/ as zips, their files are read straight out of the archive.
+// A zipped pack's base path looks like maps/dunes_1.zip/dunes_1, everything after the .zip is inside it
+pub fn is_zip_pack(path: &Path) -> bool {
+    resolve_asset_path(path).is_file()
+        && path
+            .extension()
+            .map(|extension| extension.eq_ignore_ascii_case("zip"))
+            .unwrap_or(false)
+}
+
+// Splits a path into the zip on disk and the file inside of it, None if it doesn't go through a zip
+pub fn split_zip_path(path: &Path) -> Option<(PathBuf, PathBuf)> {
+    let mut zip_path = PathBuf::new();
+    let mut components = path.components();
+
+    while let Some(component) = components.next() {
+        zip_path.push(component);
+        if is_zip_pack(&zip_path) {
+            return Some((
+                resolve_asset_path(&zip_path),
+                components.as_path().to_path_buf(),
+            ));
+        }
+    }
+
+    None
+}
+
+// Zips always use forward slashes no matter what the platform is
+fn zip_entry_name(inner_path: &Path) -> String {
+    inner_path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub fn read_zip_file(zip_path: &Path, inner_path: &Path) -> anyhow::Result<Vec<u8>> {
+    let mut archive = zip::ZipArchive::new(File::open(resolve_asset_path(zip_path))?)?;
+    let mut file = archive.by_name(&zip_entry_name(inner_path))?;
+
+    let mut bytes = Vec::with_capacity(file.size() as usize);
+    file.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+// Works for loose files and files inside of zipped packs
+pub fn pack_file_exists(path: &Path) -> bool {
+    match split_zip_path(path) {
+        None => resolve_asset_path(path).is_file(),
+        Some((zip_path, inner_path)) => match File::open(&zip_path).map(zip::ZipArchive::new) {
+            Ok(Ok(archive)) => {
+                let entry_name = zip_entry_name(&inner_path);
+                archive.file_names().any(|name| name == entry_name)
+            }
+            _ => false,
+        },
+    }
+}
+
+// Finds the folder holding main_file, the zips from the directory wrap everything in a folder named after the pack
+pub fn find_pack_root(zip_path: &Path, main_file: &str) -> anyhow::Result<PathBuf> {
+    let archive = zip::ZipArchive::new(File::open(resolve_asset_path(zip_path))?)?;
+
+    archive
+        .file_names()
+        .map(PathBuf::from)
+        .filter(|name| {
+            name.file_name()
+                .map(|file_name| file_name == main_file)
+                .unwrap_or(false)
+        })
+        .min_by_key(|name| name.components().count())
+        .map(|name| name.parent().map(Path::to_path_buf).unwrap_or_default())
+        .ok_or_else(|| anyhow::anyhow!("{:?} has no {} in it", zip_path, main_file))
+}
+
+// Reads main_file out of a zipped pack, returning its contents and the base path for the rest of the pack
+pub fn read_zip_pack(zip_path: &Path, main_file: &str) -> anyhow::Result<(String, PathBuf)> {
+    let root = find_pack_root(zip_path, main_file)?;
+    let content = String::from_utf8(read_zip_file(zip_path, &root.join(main_file))?)?;
+    Ok((content, zip_path.join(root)))
+}
+
+pub struct PackAssetIo {
+    pub default_io: Box<dyn AssetIo>,
+}
+
+impl AssetIo for PackAssetIo {
+    fn load_path<'a>(&'a self, path: &'a Path) -> BoxedFuture<'a, Result<Vec<u8>, AssetIoError>> {
+        match split_zip_path(path) {
+            None => self.default_io.load_path(path),
+            Some((zip_path, inner_path)) => Box::pin(async move {
+                read_zip_file(&zip_path, &inner_path).map_err(|error| {
+                    warn!(
+                        "Failed to read {:?} from {:?}: {}",
+                        inner_path, zip_path, error
+                    );
+                    AssetIoError::NotFound(path.to_path_buf())
+                })
+            }),
+        }
+    }
+
+    fn read_directory(
+        &self,
+        path: &Path,
+    ) -> Result<Box<dyn Iterator<Item = PathBuf>>, AssetIoError> {
+        self.default_io.read_directory(path)
+    }
+
+    fn is_directory(&self, path: &Path) -> bool {
+        self.default_io.is_directory(path)
+    }
+
+    // Zipped packs are never hot reloaded, only loose files are watched
+    fn watch_path_for_changes(&self, path: &Path) -> Result<(), AssetIoError> {
+        match split_zip_path(path) {
+            None => self.default_io.watch_path_for_changes(path),
+            Some(_) => Ok(()),
+        }
+    }
+
+    fn watch_for_changes(&self) -> Result<(), AssetIoError> {
+        self.default_io.watch_for_changes()
+    }
+}
+
+// Has to go in before AssetPlugin so it uses this AssetServer instead of making its own
+pub struct PackAssetIoPlugin;
+
+impl Plugin for PackAssetIoPlugin {
+    fn build(&self, app: &mut App) {
+        let task_pool = app.world.resource::<IoTaskPool>().0.clone();
+        let asset_io = PackAssetIo {
+            default_io: create_platform_default_asset_io(app),
+        };
+
+        app.insert_resource(AssetServer::new(asset_io, task_pool));
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::assets::AssetMap;
+use crate::char::PlayerIdentifier;
+use crate::collider::{ColliderMap, JumpResetColliderIdentifier};
+use crate::game::GameClock;
+use crate::maps::{insert_map_collider_type, MapComponent, SpriteAsset};
+use crate::universal::{PossibleBundle, PossibleBundleRetrieve};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlatformPathMode {
+    // Goes from the last waypoint straight back to the first
+    Loop,
+    // Turns around at the last waypoint and goes back through them all
+    PingPong,
+}
+
+impl Default for PlatformPathMode {
+    fn default() -> Self {
+        PlatformPathMode::Loop
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlatformEasing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Default for PlatformEasing {
+    fn default() -> Self {
+        PlatformEasing::Linear
+    }
+}
+
+impl PlatformEasing {
+    pub fn apply(&self, progress: f32) -> f32 {
+        match self {
+            PlatformEasing::Linear => progress,
+            PlatformEasing::EaseIn => progress * progress,
+            PlatformEasing::EaseOut => 1.0 - (1.0 - progress) * (1.0 - progress),
+            PlatformEasing::EaseInOut => progress * progress * (3.0 - 2.0 * progress),
+        }
+    }
+}
+
+// [[platform.waypoint]], positions are from the platform's origin in the map's base dimensions
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PlatformWaypoint {
+    pub position: [f32; 2],
+    pub rotation: f32,
+
+    // Seconds it sits at this waypoint before moving on
+    pub wait: f32,
+
+    // Seconds it takes to get to the next waypoint and how it speeds up and slows down on the way.
+    // Going back through them on a ping_pong path uses the waypoint it's going back to
+    pub travel: f32,
+    pub easing: PlatformEasing,
+}
+
+// [[platform]] in a map's main.toml. Colliders and sprites are placed where they'd be without the platform
+// and get moved along with it, the platform turns around its origin
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Platform {
+    pub id: String,
+    pub origin: [f32; 2],
+
+    // Ids from [collider], these arent spawned with the rest of the map.
+    // Players standing in one of its jump_reset_colliders get carried along with it
+    pub colliders: Vec<String>,
+    pub sprite: Vec<SpriteAsset>,
+    pub path_mode: PlatformPathMode,
+    pub waypoint: Vec<PlatformWaypoint>,
+}
+
+#[derive(Component)]
+pub struct MovingPlatform {
+    // Waypoints with their positions already in the world
+    pub waypoints: Vec<PlatformWaypoint>,
+    pub path_mode: PlatformPathMode,
+
+    // How many of each player's colliders are touching the platform's jump reset colliders
+    pub riders: HashMap<Entity, u32>,
+
+    pub position: Vec2,
+    pub rotation: f32,
+}
+
+impl MovingPlatform {
+    fn waypoint_pose(&self, index: usize) -> (Vec2, f32) {
+        let waypoint = &self.waypoints[index];
+        (Vec2::from(waypoint.position), waypoint.rotation)
+    }
+
+    // Every leg of the path as the waypoint it leaves, the one it goes to and the one with its timing
+    fn legs(&self) -> Vec<(usize, usize, usize)> {
+        let count = self.waypoints.len();
+        match self.path_mode {
+            PlatformPathMode::Loop => (0..count)
+                .map(|index| (index, (index + 1) % count, index))
+                .collect(),
+            PlatformPathMode::PingPong => {
+                let forward = (0..count - 1).map(|index| (index, index + 1, index));
+                let back = (1..count).rev().map(|index| (index, index - 1, index - 1));
+                forward.chain(back).collect()
+            }
+        }
+    }
+
+    // Worked out from the time alone so every peer and replay puts it in the same place
+    pub fn pose(&self, seconds: f64) -> (Vec2, f32) {
+        if self.waypoints.len() < 2 {
+            return match self.waypoints.is_empty() {
+                true => (self.position, self.rotation),
+                false => self.waypoint_pose(0),
+            };
+        }
+
+        let legs = self.legs();
+        let cycle: f32 = legs
+            .iter()
+            .map(|(from, _, timing)| {
+                self.waypoints[*from].wait.max(0.0) + self.waypoints[*timing].travel.max(0.0)
+            })
+            .sum();
+        if cycle <= 0.0 {
+            return self.waypoint_pose(0);
+        }
+
+        let mut remaining = seconds.rem_euclid(cycle as f64) as f32;
+        for (from, to, timing) in legs {
+            let wait = self.waypoints[from].wait.max(0.0);
+            if remaining < wait {
+                return self.waypoint_pose(from);
+            }
+            remaining -= wait;
+
+            let travel = &self.waypoints[timing];
+            if remaining < travel.travel {
+                let progress = travel.easing.apply(remaining / travel.travel);
+                let (from_position, from_rotation) = self.waypoint_pose(from);
+                let (to_position, to_rotation) = self.waypoint_pose(to);
+                return (
+                    from_position.lerp(to_position, progress),
+                    from_rotation + (to_rotation - from_rotation) * progress,
+                );
+            }
+            remaining -= travel.travel.max(0.0);
+        }
+
+        self.waypoint_pose(0)
+    }
+}
+
+// Takes each platform's colliders out of the map's so they only get spawned onto the platform
+pub fn spawn_platforms(
+    commands: &mut Commands,
+    platforms: &[Platform],
+    collider_map: &mut ColliderMap,
+    asset_map: &AssetMap,
+    rescale_resolution: f32,
+    seconds: f64,
+) {
+    for platform in platforms {
+        let origin = Vec2::from(platform.origin);
+
+        let waypoints: Vec<PlatformWaypoint> = platform
+            .waypoint
+            .iter()
+            .map(|waypoint| PlatformWaypoint {
+                position: ((origin + Vec2::from(waypoint.position)) / rescale_resolution).into(),
+                ..waypoint.clone()
+            })
+            .collect();
+
+        let mut moving_platform = MovingPlatform {
+            waypoints,
+            path_mode: platform.path_mode,
+            riders: HashMap::new(),
+            position: origin / rescale_resolution,
+            rotation: 0.0,
+        };
+        let (position, rotation) = moving_platform.pose(seconds);
+        moving_platform.position = position;
+        moving_platform.rotation = rotation;
+
+        let transform = Transform::from_translation(position.extend(0.0))
+            .with_rotation(Quat::from_rotation_z(rotation));
+
+        let mut colliders = vec![];
+        for id in &platform.colliders {
+            match collider_map.remove(&Some(id.clone())) {
+                None => warn!("platform {} has no collider called {}", platform.id, id),
+                Some(mut platform_colliders) => colliders.append(&mut platform_colliders),
+            }
+        }
+
+        let sprites: Vec<PossibleBundle> = platform
+            .sprite
+            .iter()
+            .map(|sprite| {
+                let mut sprite = sprite.clone();
+                sprite.origin[0] -= origin.x;
+                sprite.origin[1] -= origin.y;
+                sprite.retrieve_bundle(
+                    asset_map,
+                    Vec3::new(rescale_resolution, rescale_resolution, 1.0),
+                )
+            })
+            .collect();
+
+        commands
+            .spawn_bundle((
+                RigidBody::KinematicPositionBased,
+                transform,
+                GlobalTransform::from(transform),
+            ))
+            .insert(moving_platform)
+            .insert(MapComponent)
+            .with_children(|parent| {
+                for mut collider in colliders {
+                    collider.transform.translation -= (origin / rescale_resolution).extend(0.0);
+                    let collider_type = collider.collider_type;
+                    insert_map_collider_type(&mut parent.spawn_bundle(collider), collider_type);
+                }
+
+                for sprite in sprites {
+                    match sprite {
+                        PossibleBundle::Sprite(spritebundle) => parent.spawn_bundle(spritebundle),
+                        PossibleBundle::Svg(svgbundle) => parent.spawn_bundle(svgbundle),
+                    }
+                    .insert(MapComponent);
+                }
+            });
+    }
+}
+
+// Keeps count of which players are stood on which platforms, a player has more than one collider
+// so they're only off once every one of them has left
+pub fn platform_riders(
+    mut collision_events: EventReader<CollisionEvent>,
+    parent_query: Query<&Parent>,
+    jump_reset_query: Query<&JumpResetColliderIdentifier>,
+    player_query: Query<(), With<PlayerIdentifier>>,
+    mut platform_query: Query<&mut MovingPlatform>,
+) {
+    for collision_event in collision_events.iter() {
+        let (collider_one, collider_two, started) = match collision_event {
+            CollisionEvent::Started(collider_one, collider_two, _) => {
+                (*collider_one, *collider_two, true)
+            }
+            CollisionEvent::Stopped(collider_one, collider_two, _) => {
+                (*collider_one, *collider_two, false)
+            }
+        };
+
+        for (rider_collider, platform_collider) in
+            [(collider_one, collider_two), (collider_two, collider_one)]
+        {
+            if !jump_reset_query.contains(platform_collider) {
+                continue;
+            }
+
+            let rider = match parent_query.get(rider_collider) {
+                Ok(parent) => parent.0,
+                Err(_) => rider_collider,
+            };
+            if !player_query.contains(rider) {
+                continue;
+            }
+
+            let mut platform = match parent_query.get(platform_collider) {
+                Ok(parent) => match platform_query.get_mut(parent.0) {
+                    Ok(platform) => platform,
+                    Err(_) => continue,
+                },
+                Err(_) => continue,
+            };
+
+            match (platform.riders.entry(rider), started) {
+                (Entry::Occupied(mut contacts), true) => *contacts.get_mut() += 1,
+                (Entry::Vacant(contacts), true) => {
+                    contacts.insert(1);
+                }
+                (Entry::Occupied(mut contacts), false) => match *contacts.get() <= 1 {
+                    true => {
+                        contacts.remove();
+                    }
+                    false => *contacts.get_mut() -= 1,
+                },
+                (Entry::Vacant(_), false) => {}
+            }
+        }
+    }
+}
+
+pub fn move_platforms(
+    clock: Res<GameClock>,
+    mut platform_query: Query<(&mut MovingPlatform, &mut Transform), Without<PlayerIdentifier>>,
+    mut player_query: Query<&mut Transform, With<PlayerIdentifier>>,
+) {
+    let seconds = clock.gameplay_seconds();
+
+    for (mut platform, mut transform) in platform_query.iter_mut() {
+        let (position, rotation) = platform.pose(seconds);
+        if position == platform.position && rotation == platform.rotation {
+            continue;
+        }
+
+        // Riders keep where they are on the platform, turning with it around its origin
+        let turn = Quat::from_rotation_z(rotation - platform.rotation);
+        platform
+            .riders
+            .retain(|rider, _| player_query.contains(*rider));
+        for rider in platform.riders.keys() {
+            if let Ok(mut rider_transform) = player_query.get_mut(*rider) {
+                let offset = rider_transform.translation.truncate() - platform.position;
+                let carried = position + (turn * offset.extend(0.0)).truncate();
+                rider_transform.translation.x = carried.x;
+                rider_transform.translation.y = carried.y;
+            }
+        }
+
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
+        transform.rotation = Quat::from_rotation_z(rotation);
+
+        platform.position = position;
+        platform.rotation = rotation;
+    }
+}
@@ -1,21 +1,35 @@
 use crate::assets::AssetMap;
-use crate::char::{AttackBuffer, AttackKey, MovementAction};
+use crate::char::{
+    AttackBuffer, AttackKey, DirectionFacingFlags, MovementAction, PlayerStocks,
+};
+use crate::action_traits::ScaleToRatio;
 use crate::collider::ColliderMap;
+use crate::melee::Melee;
+use crate::projectile_behaviour::{
+    Bounce, Explode, Homing, ProjectileBehaviour, ProjectileExpired, Split,
+};
 use crate::rigidbody::PhysicsSpawnExtras::SpawnTransform;
 use crate::rigidbody::{AASyncSpawn, PhysMap, PhysicsSpawnExtras};
 use crate::universal::*;
 use crate::{
     AAPlayerDescriptor, AttackIdentifierTextId, AttackInstanceDirectory, CharComponentMap,
-    CharEntities, ColliderSyncEntity, DirRotateAngles, FullDirectionFacingFlags, PlayerHealth,
+    CharEntities, ColliderSyncEntity, DirRotateAngles, FullDirectionFacingFlags, GameClock, PlayerHealth,
     PlayerIdentifier, QueryEntityError, SyncColliderFlags, SyncTransformOffset, Vec3Swizzles,
 };
 use bevy::math::Mat2;
 use bevy::prelude::*;
 use bevy_inspector_egui::Inspectable;
-use bevy_rapier2d::prelude::{Ccd, Damping, ExternalForce, GravityScale, Sensor, Velocity};
+use bevy_rapier2d::prelude::{
+    Ccd, CoefficientCombineRule, Damping, ExternalForce, FixedJointBuilder, GravityScale,
+    ImpulseJoint, Restitution, Sensor, Velocity,
+};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+pub fn default_pierce() -> u32 {
+    1
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(default)]
 pub struct Projectile {
@@ -40,6 +54,9 @@ pub struct Projectile {
 
     pub dont_phase_through: bool,
     pub damage: f32,
+
+    // How many hits it can land, left out it hits once
+    #[serde(default = "default_pierce")]
     pub pierce: u32,
     pub lifetime: f64,
     pub obey_gravity: bool,
@@ -49,12 +66,118 @@ pub struct Projectile {
 
     #[serde(default)]
     pub face_dir_looking_angles: DirRotateAngles,
+
+    pub knockback: Knockback,
+
+    // Frames the victim cant move or attack for after getting hit
+    pub hitstun_frames: u64,
+
+    pub homing: Option<Homing>,
+    pub bounce: Option<Bounce>,
+    pub split: Option<Split>,
+    pub explode: Option<Explode>,
+    pub melee: Option<Melee>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Inspectable)]
+#[serde(rename_all = "snake_case")]
+pub enum KnockbackDirection {
+    ProjectileVelocity,
+    AttackerFacing,
+}
+
+impl Default for KnockbackDirection {
+    fn default() -> Self {
+        KnockbackDirection::ProjectileVelocity
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, Inspectable)]
+#[serde(default)]
+pub struct Knockback {
+    // Speed the victim gets sent flying at, the same units as velocity_cap
+    pub base: f32,
+
+    // Extra speed for every point of damage the victim has taken so far
+    pub growth: f32,
+
+    pub direction: KnockbackDirection,
+
+    // Degrees the direction gets tilted upwards by, so hits along the ground still lift the victim off it
+    pub launch_angle: f32,
+}
+
+impl Knockback {
+    // None if there's no knockback at all, away_from_hit is used when the direction cant be worked out
+    pub fn launch_velocity(
+        &self,
+        damage_taken: f32,
+        projectile_velocity: Vec2,
+        attacker_facing: Option<DirectionFacingFlags>,
+        away_from_hit: Vec2,
+    ) -> Option<Vec2> {
+        let speed = self.base + self.growth * damage_taken.max(0.0);
+        if speed <= 0.0 {
+            return None;
+        }
+
+        let direction = match self.direction {
+            KnockbackDirection::ProjectileVelocity => projectile_velocity.try_normalize(),
+            KnockbackDirection::AttackerFacing => {
+                attacker_facing.and_then(|facing| facing_vector(facing).try_normalize())
+            }
+        }
+        .or_else(|| away_from_hit.try_normalize())
+        .unwrap_or(Vec2::Y);
+
+        let tilt = match direction.x < 0.0 {
+            true => -self.launch_angle.to_radians(),
+            false => self.launch_angle.to_radians(),
+        };
+
+        Some(Mat2::from_angle(tilt).mul_vec2(direction) * speed)
+    }
 }
+
+fn facing_vector(facing: DirectionFacingFlags) -> Vec2 {
+    let mut vector = Vec2::ZERO;
+    if facing.contains(DirectionFacingFlags::LEFT) {
+        vector.x -= 1.0;
+    }
+    if facing.contains(DirectionFacingFlags::RIGHT) {
+        vector.x += 1.0;
+    }
+    if facing.contains(DirectionFacingFlags::UP) {
+        vector.y += 1.0;
+    }
+    if facing.contains(DirectionFacingFlags::DOWN) {
+        vector.y -= 1.0;
+    }
+    vector
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Component, Default)]
 #[serde(default)]
 pub struct Attack {
     pub actions: Vec<String>,
     pub cooldown: f64,
+
+    // Getting put into hitstun stops whatever is left of the attack's actions unless this is set
+    pub uninterruptible: bool,
+
+    // The animation clip played while it's going off
+    pub animation: String,
+
+    // Played instead of the character's attack sound
+    pub sound: String,
+}
+
+// Seconds after the attack starts before an action goes off, and how many more times it goes off after that
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ActionTiming {
+    pub delay: f64,
+    pub repeat: u32,
+    pub interval: f64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -71,6 +194,12 @@ pub struct SpawnProjectileAction {
     pub down_left_id: String,
     pub down_right_id: String,
     pub instance_id: String,
+
+    // Seconds after the attack starts that this goes off
+    pub delay: f64,
+    // Extra times it goes off, interval seconds apart
+    pub repeat: u32,
+    pub interval: f64,
 }
 #[derive(Debug, Clone, Component, Inspectable)]
 pub struct ProjectileIdentifier {
@@ -78,7 +207,56 @@ pub struct ProjectileIdentifier {
     pub lifetime: f64,
     pub damage: f32,
     pub pierce: u32,
+    // Hits landed or blocked so far, once it reaches pierce it passes through players without hitting them
+    pub hits_used: u32,
     pub parent: Entity,
+    pub knockback: Knockback,
+    pub hitstun_frames: u64,
+
+    // Player id of the character that fired it, split projectiles come out of their ids
+    pub owner: u64,
+
+    #[inspectable(ignore)]
+    pub behaviour: ProjectileBehaviour,
+    pub bounces: u32,
+
+    // Set once it has hit a player, a split on_hit only happens then
+    pub hit: bool,
+
+    // Every player it has landed a hit on, for movement attack actions that target victims
+    #[inspectable(ignore)]
+    pub victims: Vec<Entity>,
+
+    #[inspectable(ignore)]
+    pub melee: Option<Melee>,
+
+    // The gameplay frame each player last got hit by it on, for melee's rehit_frames
+    #[inspectable(ignore)]
+    pub last_hits: HashMap<Entity, u64>,
+}
+
+// What a projectile was fired from, netplay fires it again from this when a rollback goes back to before it ended
+#[derive(Debug, Clone, Component)]
+pub struct ProjectileSource {
+    pub projectile: Projectile,
+    pub facing: FullDirectionFacingFlags,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AttackTarget {
+    // Whatever the attack spawned under the movement action's instance_affected_id
+    Projectiles,
+    // The attacking player's own core
+    Attacker,
+    // Every player hit so far by the projectiles under instance_affected_id
+    Victims,
+}
+
+impl Default for AttackTarget {
+    fn default() -> Self {
+        AttackTarget::Projectiles
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -95,6 +273,47 @@ pub struct MovementAttackAction {
     pub down_left_id: String,
     pub down_right_id: String,
     pub instance_id: String,
+
+    // What the movement actions get applied to
+    pub target: AttackTarget,
+
+    // Seconds after the attack starts that this goes off
+    pub delay: f64,
+    // Extra times it goes off, interval seconds apart
+    pub repeat: u32,
+    pub interval: f64,
+}
+
+impl SpawnProjectileAction {
+    pub fn directional_ids(&self) -> Vec<(&'static str, &String)> {
+        vec![
+            ("none_id", &self.none_id),
+            ("left_id", &self.left_id),
+            ("right_id", &self.right_id),
+            ("down_id", &self.down_id),
+            ("up_id", &self.up_id),
+            ("up_left_id", &self.up_left_id),
+            ("up_right_id", &self.up_right_id),
+            ("down_left_id", &self.down_left_id),
+            ("down_right_id", &self.down_right_id),
+        ]
+    }
+}
+
+impl MovementAttackAction {
+    pub fn directional_ids(&self) -> Vec<(&'static str, &String)> {
+        vec![
+            ("none_id", &self.none_id),
+            ("left_id", &self.left_id),
+            ("right_id", &self.right_id),
+            ("down_id", &self.down_id),
+            ("up_id", &self.up_id),
+            ("up_left_id", &self.up_left_id),
+            ("up_right_id", &self.up_right_id),
+            ("down_left_id", &self.down_left_id),
+            ("down_right_id", &self.down_right_id),
+        ]
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -103,7 +322,24 @@ pub enum AttackActions {
     MoveAttackAction(MovementAttackAction),
 }
 
-#[derive(Eq, Hash, PartialEq, Debug)]
+impl AttackActions {
+    pub fn timing(&self) -> ActionTiming {
+        match self {
+            AttackActions::SpawnProjectile(action) => ActionTiming {
+                delay: action.delay,
+                repeat: action.repeat,
+                interval: action.interval,
+            },
+            AttackActions::MoveAttackAction(action) => ActionTiming {
+                delay: action.delay,
+                repeat: action.repeat,
+                interval: action.interval,
+            },
+        }
+    }
+}
+
+#[derive(Eq, Hash, PartialEq, Debug, Clone)]
 pub enum InstanceIdDir {
     UpLeft(String),
     UpRight(String),
@@ -116,8 +352,10 @@ pub enum InstanceIdDir {
     None(String),
 }
 
+#[derive(Debug, Clone)]
 pub enum InstanceItems {
     Projectile(Entity),
+    Player(Entity),
 }
 
 pub type AttackMap = HashMap<String, Vec<AttackActions>>;
@@ -132,7 +370,7 @@ impl Projectile {
         transform: &mut Transform,
         commands: &mut Commands,
         instance: &mut InstanceMap,
-        time: &Res<Time>,
+        clock: &Res<GameClock>,
     ) -> Option<Entity> {
         let mut extra_phys = vec![];
 
@@ -144,6 +382,14 @@ impl Projectile {
             extra_phys.push(PhysicsSpawnExtras::GravityScale(GravityScale(0.0)));
         }
 
+        // The map has no restitution of its own, so the projectile's is the one that gets used
+        if let Some(bounce) = self.bounce {
+            extra_phys.push(PhysicsSpawnExtras::Restitution(Restitution {
+                coefficient: bounce.restitution,
+                combine_rule: CoefficientCombineRule::Max,
+            }));
+        }
+
         let mut spawn_transform = transform.clone();
         spawn_transform.rotation = Quat::from_rotation_z(self.rotation);
         spawn_transform.translation += Vec3::from(self.spawn_origin_rel_abs);
@@ -153,6 +399,22 @@ impl Projectile {
 
         spawn_transform.translation += Mat2::from_angle(angle).mul_vec2(face_rot_vec).extend(0.0);
 
+        // Melee hitboxes stay where they were spawned relative to the core for as long as they're out
+        let (lifetime, joint) = match self.melee {
+            None => (self.lifetime, None),
+            Some(melee) => {
+                let offset = (spawn_transform.translation - transform.translation).truncate();
+                let joint = FixedJointBuilder::new()
+                    .local_anchor1(offset)
+                    .local_basis1(self.rotation);
+
+                (
+                    melee.active_frames as f64 * clock.timestep,
+                    Some(ImpulseJoint::new(char_entities.core, joint)),
+                )
+            }
+        };
+
         let projectile_spawned = match self.retrieve_bundle(
             &char_entities.asset_map,
             spawn_transform.translation.xy(),
@@ -170,26 +432,48 @@ impl Projectile {
         extra_phys.push(SpawnTransform(spawn_transform));
         extra_phys.push(PhysicsSpawnExtras::ProjectileIdentifier(
             ProjectileIdentifier {
-                created_timestamp: time.seconds_since_startup(),
-                lifetime: self.lifetime,
+                created_timestamp: clock.gameplay_seconds(),
+                lifetime,
                 damage: self.damage,
                 pierce: self.pierce,
+                hits_used: 0,
                 parent: char_entities.core,
+                knockback: self.knockback,
+                hitstun_frames: self.hitstun_frames,
+                owner: char_entities.player_id,
+                behaviour: ProjectileBehaviour {
+                    homing: self.homing,
+                    bounce: self.bounce,
+                    split: self.split.clone(),
+                    explode: self.explode,
+                }
+                .scale_to_ratio(&char_entities.rescale_ratio),
+                bounces: 0,
+                hit: false,
+                victims: vec![],
+                melee: self.melee,
+                last_hits: HashMap::new(),
             },
         ));
         extra_phys.push(PhysicsSpawnExtras::ContinuousCollisionDetection(
             Ccd::enabled(),
         ));
 
-        char_entities.physics_map.spawn_physics_object_with_sync(
+        let projectile_entity = char_entities.physics_map.spawn_physics_object_with_sync(
             &self.physobj_id,
             vec![(projectile_spawned, SyncColliderFlags { rotation: true })],
             &char_entities.collider_map,
             commands,
-            None,
+            joint,
             extra_phys,
             &char_entities.rescale_ratio,
-        )
+        )?;
+
+        commands.entity(projectile_entity).insert(ProjectileSource {
+            projectile: self.clone(),
+            facing: *dir_facing,
+        });
+        Some(projectile_entity)
     }
 }
 
@@ -218,7 +502,7 @@ impl SpawnProjectileAction {
         transform: &mut Transform,
         commands: &mut Commands,
         instance: &mut InstanceMap,
-        time: &Res<Time>,
+        clock: &Res<GameClock>,
     ) {
         let dir_facing = player_descriptor.direction_facing.get_full_dir_flags();
 
@@ -250,7 +534,7 @@ impl SpawnProjectileAction {
                         transform,
                         commands,
                         instance,
-                        time,
+                        clock,
                     );
                     match projectile_entity {
                         None => {}
@@ -324,18 +608,25 @@ pub fn attack_text_update(
     }
 }
 
+// Anything with a rigid body that movement actions can push around, projectiles and player cores alike
+pub type AttackBodyQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static mut Velocity,
+        &'static mut ExternalForce,
+        &'static mut Damping,
+        Option<&'static ProjectileIdentifier>,
+    ),
+>;
+
 impl MovementAttackAction {
     pub fn execute_action(
         &self,
         char_entities: &mut CharEntities,
         player_descriptor: &mut AAPlayerDescriptor,
         instance: &InstanceMap,
-        projectiles: &mut Query<(
-            &mut Velocity,
-            &mut ExternalForce,
-            &mut ProjectileIdentifier,
-            &mut Damping,
-        )>,
+        bodies: &mut AttackBodyQuery,
     ) {
         // yumm boilerplate yay
         let dir_facing = player_descriptor.direction_facing.get_full_dir_flags();
@@ -366,49 +657,121 @@ impl MovementAttackAction {
         };
 
         for action in movement_action {
-            if action.instance_affected_id == String::default() {
-                warn!("No instance id for Movement action: {:#?}", action.id);
-            }
             let instance_dir_id = dir_facing.get_instance_id(action.instance_affected_id.clone());
 
-            match instance.get(&instance_dir_id) {
-                None => {}
-                Some(instance_items) => {
-                    for instance_item in instance_items {
-                        match instance_item {
-                            InstanceItems::Projectile(projectile) => {
-                                let (
-                                    mut velocity,
-                                    mut external_force,
-                                    mut projectile_identifier,
-                                    mut damping,
-                                ) = match projectiles.get_mut(*projectile) {
-                                    Ok(projectile_muts) => projectile_muts,
-                                    Err(err) => {
-                                        warn!(
-                                            "No projectile item found for id: {:#?}: {:#?}",
-                                            instance_dir_id, err
-                                        );
-                                        return;
-                                    }
-                                };
-
-                                action.add_values(&mut velocity, &mut external_force, &mut damping);
-
-                                action.set_values(&mut velocity, &mut external_force, &mut damping);
-                            }
-                        }
+            let instance_items = match self.target {
+                AttackTarget::Attacker => vec![InstanceItems::Player(char_entities.core)],
+                AttackTarget::Projectiles | AttackTarget::Victims => {
+                    if action.instance_affected_id == String::default() {
+                        warn!("No instance id for Movement action: {:#?}", action.id);
+                    }
+
+                    match instance.get(&instance_dir_id) {
+                        None => vec![],
+                        Some(instance_items) => instance_items.clone(),
                     }
                 }
+            };
+
+            let instance_items = match self.target {
+                AttackTarget::Victims => victims_of(&instance_items, bodies),
+                _ => instance_items,
+            };
+
+            for instance_item in instance_items {
+                let entity = match instance_item {
+                    InstanceItems::Projectile(entity) | InstanceItems::Player(entity) => entity,
+                };
+
+                let (mut velocity, mut external_force, mut damping, _) =
+                    match bodies.get_mut(entity) {
+                        Ok(body_muts) => body_muts,
+                        Err(err) => {
+                            warn!(
+                                "No body found for {:#?} under id: {:#?}: {:#?}",
+                                instance_item, instance_dir_id, err
+                            );
+                            continue;
+                        }
+                    };
+
+                action.add_values(&mut velocity, &mut external_force, &mut damping);
+
+                action.set_values(&mut velocity, &mut external_force, &mut damping);
             }
         }
     }
 }
 
+// The players the projectiles have hit, each only once however many of them hit it
+fn victims_of(instance_items: &[InstanceItems], bodies: &AttackBodyQuery) -> Vec<InstanceItems> {
+    let mut victims = vec![];
+
+    for instance_item in instance_items {
+        let projectile_id = match instance_item {
+            InstanceItems::Projectile(projectile) => match bodies.get(*projectile) {
+                Ok((_, _, _, Some(projectile_id))) => projectile_id,
+                _ => continue,
+            },
+            InstanceItems::Player(_) => continue,
+        };
+
+        for victim in &projectile_id.victims {
+            if !victims.contains(victim) {
+                victims.push(*victim);
+            }
+        }
+    }
+
+    victims.into_iter().map(InstanceItems::Player).collect()
+}
+
+// One action of an attack waiting for its frame to come around
+#[derive(Debug, Clone)]
+pub struct TimedAction {
+    pub action: AttackActions,
+    pub frame: u64,
+    pub repeats_left: u32,
+    pub interval_frames: u64,
+}
+
+impl TimedAction {
+    fn new(action: AttackActions, start_frame: u64, clock: &GameClock) -> Self {
+        let timing = action.timing();
+        let to_frames = |seconds: f64| (seconds.max(0.0) / clock.timestep).round() as u64;
+
+        Self {
+            action,
+            frame: start_frame + to_frames(timing.delay),
+            repeats_left: timing.repeat,
+            // Repeats have to be at least a frame apart or they'd all go off at once
+            interval_frames: to_frames(timing.interval).max(1),
+        }
+    }
+
+    // Moves on to the next repeat, false once there are none left
+    fn advance(&mut self) -> bool {
+        if self.repeats_left == 0 {
+            return false;
+        }
+
+        self.repeats_left -= 1;
+        self.frame += self.interval_frames;
+        true
+    }
+}
+
+// The actions of an attack that havent finished yet, its InstanceMap is kept until they all have
+#[derive(Debug, Clone)]
 pub struct UnusedAction {
-    pub actions: Vec<AttackActions>,
+    pub actions: Vec<TimedAction>,
     pub player_id: u64,
     pub instance_id: u64,
+    pub uninterruptible: bool,
+
+    // Actions go off the way the player was facing when the attack started, so turning around
+    // part way through cant lose track of the projectiles in the InstanceMap
+    pub direction_facing: DirectionFacingFlags,
 }
 
 impl Attack {
@@ -419,7 +782,7 @@ impl Attack {
         transform: &mut Transform,
         commands: &mut Commands,
         attack_directory: &mut ResMut<AttackInstanceDirectory>,
-        time: &Res<Time>,
+        clock: &Res<GameClock>,
     ) {
         let mut attack_instance = InstanceMap::new();
         let mut unexecuted_actions = vec![];
@@ -429,23 +792,31 @@ impl Attack {
                 None => {}
                 Some(actions) => {
                     for action in actions.clone() {
-                        match action {
-                            AttackActions::SpawnProjectile(projectile_action) => {
+                        let mut timed_action =
+                            TimedAction::new(action, clock.gameplay_frame, clock);
+
+                        // Projectiles due straight away get spawned now so anything moving them
+                        // this frame can find them. Movement always waits for execute_unused_actions
+                        if let AttackActions::SpawnProjectile(projectile_action) =
+                            &timed_action.action
+                        {
+                            if timed_action.frame == clock.gameplay_frame {
                                 projectile_action.execute_action(
                                     char_entities,
                                     player_descriptor,
                                     transform,
                                     commands,
                                     &mut attack_instance,
-                                    time,
+                                    clock,
                                 );
+
+                                if !timed_action.advance() {
+                                    continue;
+                                }
                             }
-                            AttackActions::MoveAttackAction(movement_attacktion) => {
-                                unexecuted_actions
-                                    .push(AttackActions::MoveAttackAction(movement_attacktion));
-                            }
-                            _ => {}
                         }
+
+                        unexecuted_actions.push(timed_action);
                     }
                 }
             }
@@ -459,6 +830,8 @@ impl Attack {
                 actions: unexecuted_actions,
                 player_id: char_entities.player_id,
                 instance_id: current_id,
+                uninterruptible: self.uninterruptible,
+                direction_facing: player_descriptor.direction_facing,
             };
 
             attack_directory
@@ -477,19 +850,48 @@ pub trait ExecuteOptionAttack {
         transform: &mut Transform,
         commands: &mut Commands,
         attack_directory: &mut ResMut<AttackInstanceDirectory>,
-        time: &Res<Time>,
+        clock: &Res<GameClock>,
     );
 }
 
+// Percentage mode puts damage onto the percentage, otherwise it comes off health
+pub fn take_damage(
+    damage: f32,
+    health: &mut Mut<PlayerHealth>,
+    stocks: Option<&mut Mut<PlayerStocks>>,
+) {
+    match stocks {
+        Some(stocks) => stocks.percentage += damage,
+        None => health.current_health -= damage,
+    }
+}
+
 impl ProjectileIdentifier {
+    pub fn pierce_left(&self) -> u32 {
+        self.pierce.saturating_sub(self.hits_used)
+    }
+
+    pub fn use_pierce(&mut self) {
+        self.hits_used += 1;
+    }
+
     pub fn apply_projectile(
         &mut self,
         health: &mut Mut<PlayerHealth>,
         player_id: &mut Mut<PlayerIdentifier>,
         player_descriptor: &mut Mut<AAPlayerDescriptor>,
+        stocks: Option<&mut Mut<PlayerStocks>>,
+        clock: &Res<GameClock>,
     ) {
-        health.current_health -= self.damage;
-        self.pierce -= 1;
+        take_damage(self.damage, health, stocks);
+        self.use_pierce();
+
+        // Getting hit again during hitstun can only make it longer
+        if self.hitstun_frames > 0 {
+            player_descriptor.hitstun_until = player_descriptor
+                .hitstun_until
+                .max(clock.gameplay_frame + self.hitstun_frames);
+        }
     }
 }
 
@@ -501,7 +903,7 @@ impl ExecuteOptionAttack for Option<Attack> {
         transform: &mut Transform,
         commands: &mut Commands,
         attack_directory: &mut ResMut<AttackInstanceDirectory>,
-        time: &Res<Time>,
+        clock: &Res<GameClock>,
     ) {
         match self {
             None => {}
@@ -512,7 +914,7 @@ impl ExecuteOptionAttack for Option<Attack> {
                     transform,
                     commands,
                     attack_directory,
-                    time,
+                    clock,
                 );
             }
         }
@@ -537,95 +939,137 @@ pub fn create_projectile_map(projectiles: &Vec<Projectile>) -> ProjectileMap {
 
 pub fn execute_unused_actions(
     mut attack_directory: ResMut<AttackInstanceDirectory>,
-    mut projectile_query: Query<(
-        &mut Velocity,
-        &mut ExternalForce,
-        &mut ProjectileIdentifier,
-        &mut Damping,
-    )>,
-    mut player_query: Query<(&mut AAPlayerDescriptor, &mut Transform)>,
+    mut bodies: AttackBodyQuery,
+    mut player_query: Query<(&AAPlayerDescriptor, &mut Transform)>,
     mut commands: Commands,
     mut char_map: ResMut<CharComponentMap>,
+    clock: Res<GameClock>,
 ) {
-    let mut remove_attack_instance = vec![];
-    for unexecuted_action in &attack_directory.unexecuted_actions {
+    let mut timelines = std::mem::take(&mut attack_directory.unexecuted_actions);
+
+    for timeline in timelines.iter_mut() {
         let instance_map = match attack_directory
             .attack_instances
-            .get(&unexecuted_action.instance_id)
+            .get_mut(&timeline.instance_id)
         {
             None => {
-                warn!(
-                    "No instance Map found for id: {:#?}",
-                    unexecuted_action.instance_id
-                );
+                warn!("No instance Map found for id: {:#?}", timeline.instance_id);
+                timeline.actions.clear();
                 continue;
             }
             Some(instance_map) => instance_map,
         };
 
-        let char_entities = match char_map.get_mut(&unexecuted_action.player_id) {
+        let char_entities = match char_map.get_mut(&timeline.player_id) {
             None => {
                 warn!(
                     "No Player CharEntities found for player id {:#?}",
-                    unexecuted_action.player_id
+                    timeline.player_id
                 );
+                timeline.actions.clear();
                 continue;
             }
             Some(char_entities) => char_entities,
         };
 
-        let (mut player_descriptor, mut transform) = match player_query.get_mut(char_entities.core)
-        {
+        let (player_descriptor, mut transform) = match player_query.get_mut(char_entities.core) {
             Err(err) => {
                 warn!(
                     "No Player Descriptor found for player id {:#?}: {:#?}",
-                    unexecuted_action.player_id, err
+                    timeline.player_id, err
                 );
+                timeline.actions.clear();
                 continue;
             }
             Ok(player_mut) => player_mut,
         };
 
-        for action in &unexecuted_action.actions {
-            match action {
-                AttackActions::SpawnProjectile(projectile) => {}
+        if !timeline.uninterruptible && player_descriptor.hitstun_until > clock.gameplay_frame {
+            timeline.actions.clear();
+            continue;
+        }
+
+        let mut attack_descriptor = player_descriptor.clone();
+        attack_descriptor.direction_facing = timeline.direction_facing;
+
+        let mut spawned_projectiles = false;
+        for timed_action in timeline.actions.iter_mut() {
+            if timed_action.frame > clock.gameplay_frame {
+                continue;
+            }
+
+            match &timed_action.action {
+                AttackActions::SpawnProjectile(projectile_action) => {
+                    projectile_action.execute_action(
+                        char_entities,
+                        &mut attack_descriptor,
+                        &mut transform,
+                        &mut commands,
+                        instance_map,
+                        &clock,
+                    );
+                    spawned_projectiles = true;
+                }
                 AttackActions::MoveAttackAction(move_action) => {
+                    // Projectiles spawned just now dont exist until the commands are applied, so wait a frame for them
+                    if spawned_projectiles {
+                        timed_action.frame = clock.gameplay_frame + 1;
+                        continue;
+                    }
+
                     move_action.execute_action(
                         char_entities,
-                        &mut player_descriptor,
+                        &mut attack_descriptor,
                         instance_map,
-                        &mut projectile_query,
+                        &mut bodies,
                     );
                 }
             }
+
+            timed_action.advance();
         }
 
-        remove_attack_instance.push(unexecuted_action.instance_id);
+        // Anything still due now or earlier has no repeats left
+        timeline
+            .actions
+            .retain(|timed_action| timed_action.frame > clock.gameplay_frame);
     }
 
-    attack_directory
-        .unexecuted_actions
-        .retain(|unexecuted_action| {
-            if remove_attack_instance.contains(&unexecuted_action.instance_id) {
-                false
-            } else {
-                true
-            }
-        });
-
-    for instance_id in remove_attack_instance {
-        attack_directory.attack_instances.remove(&instance_id);
+    for timeline in timelines {
+        if timeline.actions.is_empty() {
+            attack_directory
+                .attack_instances
+                .remove(&timeline.instance_id);
+        } else {
+            attack_directory.unexecuted_actions.push(timeline);
+        }
     }
 }
 
 pub fn projectile_lifetimes(
-    mut projectile_query: Query<(Entity, &ProjectileIdentifier, &ColliderSyncEntity)>,
+    mut projectile_query: Query<(
+        Entity,
+        &ProjectileIdentifier,
+        &ColliderSyncEntity,
+        &Transform,
+        &Velocity,
+    )>,
     mut commands: Commands,
-    time: Res<Time>,
+    clock: Res<GameClock>,
+    mut expired_events: EventWriter<ProjectileExpired>,
 ) {
-    for (entity, projectile_id, collider_entity) in projectile_query.iter() {
-        if projectile_id.created_timestamp < (time.seconds_since_startup() - projectile_id.lifetime)
+    for (entity, projectile_id, collider_entity, transform, velocity) in projectile_query.iter() {
+        if projectile_id.created_timestamp < (clock.gameplay_seconds() - projectile_id.lifetime)
         {
+            expired_events.send(ProjectileExpired {
+                owner: projectile_id.owner,
+                parent: projectile_id.parent,
+                transform: *transform,
+                linvel: velocity.linvel,
+                behaviour: projectile_id.behaviour.clone(),
+                hit: projectile_id.hit,
+            });
+
             collider_entity.despawn_self(&mut commands);
             commands.entity(entity).despawn_recursive();
         }
@@ -639,3 +1083,85 @@ impl ColliderSyncEntity {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identifier(projectile: &Projectile) -> ProjectileIdentifier {
+        ProjectileIdentifier {
+            created_timestamp: 0.0,
+            lifetime: projectile.lifetime,
+            damage: projectile.damage,
+            pierce: projectile.pierce,
+            hits_used: 0,
+            parent: Entity::from_raw(0),
+            knockback: projectile.knockback,
+            hitstun_frames: projectile.hitstun_frames,
+            owner: 1,
+            behaviour: ProjectileBehaviour::default(),
+            bounces: 0,
+            hit: false,
+            victims: vec![],
+            melee: None,
+            last_hits: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn default_projectile_damages_once() {
+        // Like every projectile Euclid ships with, pierce is left out
+        let projectile: Projectile =
+            toml::from_str("id = \"shot\"\nasset = \"shot\"\ndamage = 10.0\nlifetime = 1.0\n")
+                .unwrap();
+        assert_eq!(projectile.pierce, 1);
+
+        let mut world = World::new();
+        world.insert_resource(GameClock::new(60.0));
+        world.insert_resource(identifier(&projectile));
+        let victim = world
+            .spawn()
+            .insert(PlayerHealth {
+                maximum_health: 100.0,
+                current_health: 100.0,
+                ..Default::default()
+            })
+            .insert(PlayerIdentifier { player_id: 2 })
+            .insert(AAPlayerDescriptor::default())
+            .id();
+
+        let mut hit_stage = SystemStage::single_threaded();
+        hit_stage.add_system(
+            |mut projectile_id: ResMut<ProjectileIdentifier>,
+             mut player_query: Query<(
+                &mut PlayerHealth,
+                &mut PlayerIdentifier,
+                &mut AAPlayerDescriptor,
+            )>,
+             clock: Res<GameClock>| {
+                // The same check projectile_hit_collision_event makes before applying a hit
+                if projectile_id.pierce_left() == 0 {
+                    return;
+                }
+
+                for (mut health, mut player_id, mut descriptor) in player_query.iter_mut() {
+                    projectile_id.apply_projectile(
+                        &mut health,
+                        &mut player_id,
+                        &mut descriptor,
+                        None,
+                        &clock,
+                    );
+                }
+            },
+        );
+
+        hit_stage.run(&mut world);
+        assert_eq!(world.get::<PlayerHealth>(victim).unwrap().current_health, 90.0);
+        assert_eq!(world.resource::<ProjectileIdentifier>().pierce_left(), 0);
+
+        // Its one hit is used up so touching the player again does nothing
+        hit_stage.run(&mut world);
+        assert_eq!(world.get::<PlayerHealth>(victim).unwrap().current_health, 90.0);
+    }
+}
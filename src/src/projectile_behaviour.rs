@@ -0,0 +1,566 @@
+use bevy::math::Mat2;
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::action_traits::ScaleToRatio;
+use crate::char::{
+    AAPlayerDescriptor, AttackBuffer, CharComponentMap, PlayerHealth, PlayerIdentifier,
+    PlayerStocks,
+};
+use crate::collider::SolidColliderIdentifier;
+use crate::game::*;
+use crate::maps::MapComponent;
+use crate::projectile::{
+    take_damage, InstanceMap, Knockback, Projectile, ProjectileIdentifier, ProjectileMap,
+};
+use crate::shield::{PlayerShield, ShieldBlock};
+
+// Steers towards the nearest player that didnt fire it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct Homing {
+    // Degrees per second the projectile can turn by
+    pub turn_rate: f32,
+
+    // Players further away than this are ignored, 0 for any distance
+    pub range: f32,
+
+    // Seconds after spawning before it starts turning
+    pub delay: f64,
+}
+
+impl Default for Homing {
+    fn default() -> Self {
+        Self {
+            turn_rate: 180.0,
+            range: 0.0,
+            delay: 0.0,
+        }
+    }
+}
+
+// Only does anything for projectiles with dont_phase_through, sensors go straight through the map
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct Bounce {
+    pub restitution: f32,
+
+    // Times it can hit the map, the one after that ends it
+    pub count: u32,
+}
+
+impl Default for Bounce {
+    fn default() -> Self {
+        Self {
+            restitution: 0.8,
+            count: 3,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Split {
+    // Projectile ids to spawn where it ended, list one more than once to get more of them
+    pub ids: Vec<String>,
+
+    // Hitting a player ends the projectile straight away so it can split
+    pub on_hit: bool,
+    pub on_expire: bool,
+
+    // Degrees the children are fanned out over, centred on the way the projectile was going
+    pub spread: f32,
+
+    // Speed of the children, 0 keeps the speed the projectile had
+    pub speed: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(default)]
+pub struct Explode {
+    pub radius: f32,
+    pub damage: f32,
+
+    // The direction is ignored, explosions always push away from where they went off
+    pub knockback: Knockback,
+    pub hitstun_frames: u64,
+
+    // Hitting a player sets it off straight away instead of waiting for the lifetime to run out
+    pub on_hit: bool,
+    pub hurts_owner: bool,
+}
+
+// Everything from the projectile's toml that gets carried around on its ProjectileIdentifier
+#[derive(Debug, Clone, Default)]
+pub struct ProjectileBehaviour {
+    pub homing: Option<Homing>,
+    pub bounce: Option<Bounce>,
+    pub split: Option<Split>,
+    pub explode: Option<Explode>,
+}
+
+impl ProjectileBehaviour {
+    pub fn ends_on_hit(&self) -> bool {
+        let split_on_hit = match &self.split {
+            Some(split) => split.on_hit,
+            None => false,
+        };
+        let explode_on_hit = match &self.explode {
+            Some(explode) => explode.on_hit,
+            None => false,
+        };
+        split_on_hit || explode_on_hit
+    }
+}
+
+// Distances are in the character's base dimensions like everything else in its toml
+impl ScaleToRatio for ProjectileBehaviour {
+    fn scale_to_ratio(&self, ratio: &Vec3) -> Self {
+        Self {
+            homing: self.homing.map(|homing| Homing {
+                range: homing.range / ratio.y,
+                ..homing
+            }),
+            explode: self.explode.map(|explode| Explode {
+                radius: explode.radius / ratio.y,
+                ..explode
+            }),
+            ..self.clone()
+        }
+    }
+}
+
+// Sent by projectile_lifetimes right before a projectile is despawned
+pub struct ProjectileExpired {
+    pub owner: u64,
+    pub parent: Entity,
+    pub transform: Transform,
+    pub linvel: Vec2,
+    pub behaviour: ProjectileBehaviour,
+    pub hit: bool,
+}
+
+pub fn projectile_homing(
+    clock: Res<GameClock>,
+    player_query: Query<(Entity, &Transform), With<PlayerIdentifier>>,
+    mut projectile_query: Query<(&ProjectileIdentifier, &Transform, &mut Velocity)>,
+) {
+    if clock.paused {
+        return;
+    }
+
+    for (projectile_id, transform, mut velocity) in projectile_query.iter_mut() {
+        let homing = match projectile_id.behaviour.homing {
+            Some(homing) => homing,
+            None => continue,
+        };
+
+        if clock.gameplay_seconds() - projectile_id.created_timestamp < homing.delay {
+            continue;
+        }
+
+        // Nothing to steer until something has launched it
+        let speed = velocity.linvel.length();
+        if speed <= f32::EPSILON {
+            continue;
+        }
+
+        let position = transform.translation.truncate();
+        let target = player_query
+            .iter()
+            .filter(|(entity, _)| *entity != projectile_id.parent)
+            .map(|(_, player_transform)| player_transform.translation.truncate() - position)
+            .filter(|offset| homing.range <= 0.0 || offset.length() <= homing.range)
+            .min_by(|a, b| {
+                a.length()
+                    .partial_cmp(&b.length())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+
+        let target = match target {
+            Some(target) => target,
+            None => continue,
+        };
+
+        let max_turn = homing.turn_rate.to_radians() * clock.frame_delta() as f32;
+        let turn = velocity.linvel.angle_between(target);
+        if turn.is_nan() {
+            continue;
+        }
+
+        velocity.linvel =
+            Mat2::from_angle(turn.clamp(-max_turn, max_turn)).mul_vec2(velocity.linvel);
+    }
+}
+
+// Counts every time a bouncing projectile hits the map, it ends once it runs out of bounces
+pub fn projectile_bounces(
+    mut collision_events: EventReader<CollisionEvent>,
+    parent_query: Query<&Parent>,
+    map_query: Query<&SolidColliderIdentifier, With<MapComponent>>,
+    mut projectile_query: Query<&mut ProjectileIdentifier>,
+) {
+    for collision_event in collision_events.iter() {
+        let (collider_one, collider_two) = match collision_event {
+            CollisionEvent::Started(collider_one, collider_two, _) => {
+                (*collider_one, *collider_two)
+            }
+            CollisionEvent::Stopped(_, _, _) => continue,
+        };
+
+        for (projectile_collider, other_collider) in
+            [(collider_one, collider_two), (collider_two, collider_one)]
+        {
+            if !map_query.contains(other_collider) {
+                continue;
+            }
+
+            let projectile_entity = match parent_query.get(projectile_collider) {
+                Ok(parent) => parent.0,
+                Err(_) => continue,
+            };
+
+            if let Ok(mut projectile_id) = projectile_query.get_mut(projectile_entity) {
+                let bounce = match projectile_id.behaviour.bounce {
+                    Some(bounce) => bounce,
+                    None => continue,
+                };
+
+                projectile_id.bounces += 1;
+                if projectile_id.bounces > bounce.count {
+                    // Gone the next time projectile_lifetimes runs
+                    projectile_id.lifetime = 0.0;
+                }
+            }
+        }
+    }
+}
+
+type ExplosionQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Transform,
+        &'static mut Velocity,
+        &'static mut PlayerHealth,
+        &'static mut AAPlayerDescriptor,
+        Option<&'static mut PlayerStocks>,
+        Option<&'static mut PlayerShield>,
+        Option<&'static mut AttackBuffer>,
+    ),
+    With<PlayerIdentifier>,
+>;
+
+pub fn projectile_expiry_effects(
+    mut expired_events: EventReader<ProjectileExpired>,
+    char_map: Res<CharComponentMap>,
+    mut commands: Commands,
+    clock: Res<GameClock>,
+    mut player_query: ExplosionQuery,
+) {
+    for expired in expired_events.iter() {
+        if let Some(split) = &expired.behaviour.split {
+            if split.on_expire || (split.on_hit && expired.hit) {
+                spawn_split(expired, split, &char_map, &mut commands, &clock);
+            }
+        }
+
+        if let Some(explode) = &expired.behaviour.explode {
+            explode_projectile(expired, explode, &clock, &mut player_query);
+        }
+    }
+}
+
+fn spawn_split(
+    expired: &ProjectileExpired,
+    split: &Split,
+    char_map: &Res<CharComponentMap>,
+    commands: &mut Commands,
+    clock: &Res<GameClock>,
+) {
+    // The children come out of whoever fired the projectile in the first place
+    let char_entities = match char_map.get(&expired.owner) {
+        Some(char_entities) => char_entities,
+        None => return,
+    };
+
+    let children = split_children(split, &char_entities.projectiles);
+    let velocities = split_velocities(split, expired.linvel, children.len());
+
+    for (projectile, linvel) in children.into_iter().zip(velocities) {
+        let mut transform = expired.transform;
+        let child = projectile.spawn_projectile(
+            &FullDirectionFacingFlags::NONE,
+            char_entities,
+            &mut AAPlayerDescriptor::default(),
+            &mut transform,
+            commands,
+            &mut InstanceMap::new(),
+            clock,
+        );
+
+        if let Some(child) = child {
+            commands.entity(child).insert(Velocity {
+                linvel,
+                angvel: 0.0,
+            });
+        }
+    }
+}
+
+// Every projectile under each of the ids in order, ids the character doesnt have are skipped
+fn split_children<'a>(split: &Split, projectiles: &'a ProjectileMap) -> Vec<&'a Projectile> {
+    split
+        .ids
+        .iter()
+        .filter_map(|id| match projectiles.get(id) {
+            Some(projectiles) => Some(projectiles),
+            None => {
+                warn!("No Projectiles found under id {:#?} to split into", id);
+                None
+            }
+        })
+        .flatten()
+        .collect()
+}
+
+// Fans the children out evenly over the spread, centred on the way the projectile was going
+fn split_velocities(split: &Split, linvel: Vec2, children: usize) -> Vec<Vec2> {
+    let speed = match split.speed > 0.0 {
+        true => split.speed,
+        false => linvel.length(),
+    };
+    let direction = linvel.try_normalize().unwrap_or(Vec2::Y);
+    let spread = split.spread.to_radians();
+    let (first, step) = match children > 1 {
+        true => (-spread / 2.0, spread / (children - 1) as f32),
+        false => (0.0, 0.0),
+    };
+
+    (0..children)
+        .map(|index| {
+            let angle = first + step * index as f32;
+            Mat2::from_angle(angle).mul_vec2(direction) * speed
+        })
+        .collect()
+}
+
+fn explode_projectile(
+    expired: &ProjectileExpired,
+    explode: &Explode,
+    clock: &Res<GameClock>,
+    player_query: &mut ExplosionQuery,
+) {
+    let centre = expired.transform.translation.truncate();
+
+    for (
+        entity,
+        transform,
+        mut velocity,
+        mut health,
+        mut descriptor,
+        mut stocks,
+        player_shield,
+        attack_buffer,
+    ) in player_query.iter_mut()
+    {
+        if entity == expired.parent && !explode.hurts_owner {
+            continue;
+        }
+
+        let away_from_hit = transform.translation.truncate() - centre;
+        if away_from_hit.length() > explode.radius {
+            continue;
+        }
+
+        let shield_block = match player_shield {
+            Some(mut player_shield) => player_shield.block(explode.damage, &mut descriptor, clock),
+            None => ShieldBlock::Unblocked,
+        };
+
+        match shield_block {
+            ShieldBlock::Unblocked => {}
+            ShieldBlock::Blocked(damage) => {
+                take_damage(damage, &mut health, stocks.as_mut());
+                continue;
+            }
+            ShieldBlock::Parried(_) => continue,
+        }
+
+        take_damage(explode.damage, &mut health, stocks.as_mut());
+
+        let damage_taken = match &stocks {
+            Some(stocks) => stocks.percentage,
+            None => health.maximum_health - health.current_health,
+        };
+
+        // Always straight out from the middle, whichever way the knockback says to go
+        if let Some(launch_velocity) =
+            explode
+                .knockback
+                .launch_velocity(damage_taken, away_from_hit, None, away_from_hit)
+        {
+            velocity.linvel = launch_velocity;
+        }
+
+        if explode.hitstun_frames > 0 {
+            descriptor.hitstun_until = descriptor
+                .hitstun_until
+                .max(clock.gameplay_frame + explode.hitstun_frames);
+
+            if let Some(mut attack_buffer) = attack_buffer {
+                attack_buffer.reset();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_rapier2d::rapier::prelude::CollisionEventFlags;
+    use std::collections::HashMap;
+
+    use crate::projectile::create_projectile_map;
+
+    fn identifier(behaviour: ProjectileBehaviour) -> ProjectileIdentifier {
+        ProjectileIdentifier {
+            created_timestamp: 0.0,
+            lifetime: 1.0,
+            damage: 0.0,
+            pierce: 1,
+            hits_used: 0,
+            parent: Entity::from_raw(0),
+            knockback: Knockback::default(),
+            hitstun_frames: 0,
+            owner: 1,
+            behaviour,
+            bounces: 0,
+            hit: false,
+            victims: vec![],
+            melee: None,
+            last_hits: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn bounces_run_out_after_count() {
+        let mut world = World::new();
+        world.insert_resource(Events::<CollisionEvent>::default());
+
+        let map = world
+            .spawn()
+            .insert(SolidColliderIdentifier {})
+            .insert(MapComponent)
+            .id();
+        let projectile = world
+            .spawn()
+            .insert(identifier(ProjectileBehaviour {
+                bounce: Some(Bounce {
+                    restitution: 0.8,
+                    count: 2,
+                }),
+                ..Default::default()
+            }))
+            .id();
+        let collider = world.spawn().insert(Parent(projectile)).id();
+
+        let mut stage = SystemStage::single_threaded().with_system(projectile_bounces);
+        let mut hit_map = |world: &mut World| {
+            let mut events = world.resource_mut::<Events<CollisionEvent>>();
+            events.send(CollisionEvent::Started(
+                collider,
+                map,
+                CollisionEventFlags::empty(),
+            ));
+            // Leaving the map again isnt another bounce
+            events.send(CollisionEvent::Stopped(
+                map,
+                collider,
+                CollisionEventFlags::empty(),
+            ));
+            stage.run(world);
+            world
+                .get::<ProjectileIdentifier>(projectile)
+                .unwrap()
+                .clone()
+        };
+
+        for bounces in 1..=2 {
+            let projectile_id = hit_map(&mut world);
+            assert_eq!(projectile_id.bounces, bounces);
+            assert_eq!(projectile_id.lifetime, 1.0);
+        }
+
+        let projectile_id = hit_map(&mut world);
+        assert_eq!(projectile_id.bounces, 3);
+        assert_eq!(projectile_id.lifetime, 0.0);
+    }
+
+    fn projectile(id: &str, damage: f32) -> Projectile {
+        Projectile {
+            id: id.to_string(),
+            damage,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn split_children_follow_the_ids() {
+        let projectiles = create_projectile_map(&vec![
+            projectile("shard", 1.0),
+            projectile("spark", 2.0),
+            projectile("spark", 3.0),
+        ]);
+        let split = Split {
+            ids: vec![
+                "shard".to_string(),
+                "missing".to_string(),
+                "spark".to_string(),
+                "shard".to_string(),
+            ],
+            ..Default::default()
+        };
+
+        let children: Vec<(&str, f32)> = split_children(&split, &projectiles)
+            .into_iter()
+            .map(|child| (child.id.as_str(), child.damage))
+            .collect();
+        assert_eq!(
+            children,
+            vec![
+                ("shard", 1.0),
+                ("spark", 2.0),
+                ("spark", 3.0),
+                ("shard", 1.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn split_children_fan_out_over_the_spread() {
+        let split = Split {
+            spread: 90.0,
+            ..Default::default()
+        };
+        let velocities = split_velocities(&split, Vec2::new(0.0, 10.0), 3);
+
+        assert_eq!(velocities.len(), 3);
+        assert!(velocities[1].abs_diff_eq(Vec2::new(0.0, 10.0), 0.001));
+        assert!(velocities[0].abs_diff_eq(Vec2::new(7.071, 7.071), 0.001));
+        assert!(velocities[2].abs_diff_eq(Vec2::new(-7.071, 7.071), 0.001));
+
+        // A set speed replaces the projectile's own, one child just carries on straight
+        let split = Split {
+            spread: 90.0,
+            speed: 4.0,
+            ..Default::default()
+        };
+        assert_eq!(
+            split_velocities(&split, Vec2::new(10.0, 0.0), 1),
+            vec![Vec2::new(4.0, 0.0)]
+        );
+    }
+}
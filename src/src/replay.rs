@@ -0,0 +1,478 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+use rand::prelude::SliceRandom;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+use crate::char::Character;
+use crate::game::*;
+use crate::maps::{find_map, Map, MapRotation};
+use crate::{AppStates, CharacterInputIdentifierMap, SelectedCharacters};
+
+// Bump this whenever the layout of a replay changes, old replays wont play back properly otherwise
+pub const REPLAYVERSION: u64 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReplayInput {
+    // Rounds start at 1, frames are counted from the start of each round
+    pub round: u64,
+    pub frame: u64,
+    pub input: PlayerInput,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Replay {
+    pub version: u64,
+    pub seed: u64,
+    pub map: Map,
+
+    // The order maps were picked from, needed so map changes land on the same maps again
+    pub available_maps: Vec<String>,
+    pub characters: HashMap<u64, Character>,
+    pub inputs: Vec<ReplayInput>,
+
+    // Every map played after the first one, older replays without these pick maps from the seed instead
+    #[serde(default)]
+    pub map_changes: Vec<String>,
+
+    // Replays from before rulesets existed were all played with health
+    #[serde(default)]
+    pub ruleset: RulesetSettings,
+}
+
+impl Replay {
+    pub fn from_game(game: &Game) -> Self {
+        Self {
+            version: REPLAYVERSION,
+            seed: game.seed,
+            map: game.selected_map.clone(),
+            available_maps: game
+                .available_maps
+                .iter()
+                .map(|map| map.info.display_name.clone())
+                .collect(),
+            characters: game.selected_characters.clone(),
+            inputs: vec![],
+            map_changes: vec![],
+            ruleset: game.ruleset.clone(),
+        }
+    }
+
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let replay: Replay = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+
+        if replay.version != REPLAYVERSION {
+            anyhow::bail!(
+                "replay is version {} but only version {} can be played",
+                replay.version,
+                REPLAYVERSION
+            );
+        }
+
+        Ok(replay)
+    }
+
+    pub fn save(&self, path: &PathBuf) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+pub struct ReplayRecorder {
+    pub replay: Replay,
+    pub path: PathBuf,
+    pub round: u64,
+    pub frame: u64,
+}
+
+pub struct ReplayPlayer {
+    pub replay: Replay,
+    pub round: u64,
+    pub frame: u64,
+    pub next_input: usize,
+    pub next_map_change: usize,
+}
+
+pub fn replay_start_round(
+    mut commands: Commands,
+    settings: Res<GameSettings>,
+    game: Res<Game>,
+    recorder: Option<ResMut<ReplayRecorder>>,
+    player: Option<ResMut<ReplayPlayer>>,
+) {
+    if let Some(mut player) = player {
+        player.round += 1;
+        player.frame = 0;
+        return;
+    }
+
+    match recorder {
+        Some(mut recorder) => {
+            recorder.round += 1;
+            recorder.frame = 0;
+        }
+        None => {
+            if !settings.replay.record {
+                return;
+            }
+
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0);
+
+            commands.insert_resource(ReplayRecorder {
+                replay: Replay::from_game(&game),
+                path: PathBuf::from(&settings.replay.directory)
+                    .join(format!("{}.replay", timestamp)),
+                round: 1,
+                frame: 0,
+            });
+        }
+    }
+}
+
+// Runs right before movement_input_system so it sees the exact inputs that get applied
+pub fn record_replay_inputs(
+    clock: Res<GameClock>,
+    input_queue: Res<PlayerInputQueue>,
+    recorder: Option<ResMut<ReplayRecorder>>,
+    state: Res<State<AppStates>>,
+) {
+    let mut recorder = match recorder {
+        None => return,
+        Some(recorder) => recorder,
+    };
+
+    // Inputs stay in the queue on updates without a gameplay frame, they get recorded with the frame that applies them
+    if state.current() != &AppStates::LoadGame || clock.frames_due == 0 {
+        return;
+    }
+
+    let (round, frame) = (recorder.round, recorder.frame);
+    for input in &input_queue.inputs {
+        recorder.replay.inputs.push(ReplayInput {
+            round,
+            frame,
+            input: *input,
+        });
+    }
+    // Keyed by gameplay frame so it plays back the same whatever the frame rate was
+    recorder.frame += 1;
+}
+
+pub fn save_replay(recorder: Option<Res<ReplayRecorder>>) {
+    if let Some(recorder) = recorder {
+        match recorder.replay.save(&recorder.path) {
+            Ok(_) => println!("Saved replay to {:?}", recorder.path),
+            Err(error) => warn!("Failed to save replay to {:?}: {}", recorder.path, error),
+        }
+    }
+}
+
+pub fn start_replay_playback(
+    mut commands: Commands,
+    settings: Res<GameSettings>,
+    chars: Res<Vec<Character>>,
+    maps: Res<Vec<Map>>,
+    mut state: ResMut<State<AppStates>>,
+) {
+    let replay = match Replay::load(&settings.replay.playback) {
+        Ok(replay) => replay,
+        Err(error) => {
+            error!("Failed to load replay {}: {}", settings.replay.playback, error);
+            state.set(AppStates::MainMenu);
+            return;
+        }
+    };
+
+    // The recorded content is what gets played, the installed content is only needed for its assets
+    let mut selected_characters = HashMap::new();
+    for (player_id, character) in &replay.characters {
+        match chars
+            .iter()
+            .find(|installed| installed.info.display_name == character.info.display_name)
+        {
+            None => {
+                error!(
+                    "Replay needs the character {} which isnt installed",
+                    character.info.display_name
+                );
+                state.set(AppStates::MainMenu);
+                return;
+            }
+            Some(installed) => {
+                let mut character = character.clone();
+                character.base_path = installed.base_path.clone();
+                selected_characters.insert(*player_id, character);
+            }
+        }
+    }
+
+    let mut available_maps = vec![];
+    for name in &replay.available_maps {
+        match maps.iter().find(|map| &map.info.display_name == name) {
+            None => {
+                error!("Replay needs the map {} which isnt installed", name);
+                state.set(AppStates::MainMenu);
+                return;
+            }
+            Some(map) => available_maps.push(map.clone()),
+        }
+    }
+
+    let mut selected_map = replay.map.clone();
+    match available_maps
+        .iter()
+        .find(|map| map.info.display_name == selected_map.info.display_name)
+    {
+        None => {
+            error!(
+                "Replay needs the map {} which isnt installed",
+                selected_map.info.display_name
+            );
+            state.set(AppStates::MainMenu);
+            return;
+        }
+        Some(installed) => selected_map.base_path = installed.base_path.clone(),
+    }
+
+    // The first map was already picked from the seed when recording, so get the rng back to the same spot
+    let mut rng = StdRng::seed_from_u64(replay.seed);
+    let _ = available_maps.choose(&mut rng);
+
+    // Recorded map changes take over from this, it's only there for replays that dont have them
+    let map_rotation = MapRotation::new(&MapRotationSettings::default(), &available_maps);
+
+    commands.insert_resource(SelectedCharacters {
+        characters: selected_characters.clone(),
+    });
+    commands.insert_resource(CharacterInputIdentifierMap {
+        map: HashMap::new(),
+    });
+    commands.insert_resource(Game {
+        selected_characters,
+        available_characters: vec![],
+        selected_map,
+        available_maps,
+        seed: replay.seed,
+        rng,
+        map_rotation,
+        ruleset: replay.ruleset.clone(),
+    });
+    commands.insert_resource(ReplayPlayer {
+        replay,
+        round: 0,
+        frame: 0,
+        next_input: 0,
+        next_map_change: 0,
+    });
+
+    state.set(AppStates::LoadAssets);
+}
+
+// Runs whenever the map changes mid match, the first map is already part of the replay
+pub fn replay_map_change(
+    mut game: ResMut<Game>,
+    recorder: Option<ResMut<ReplayRecorder>>,
+    player: Option<ResMut<ReplayPlayer>>,
+) {
+    if let Some(mut player) = player {
+        if player.round == 0 {
+            return;
+        }
+
+        let next_map_change = player.next_map_change;
+        if let Some(name) = player.replay.map_changes.get(next_map_change) {
+            match find_map(&game.available_maps, name) {
+                None => warn!("Replay changes to the map {} which isnt installed", name),
+                Some(map) => game.selected_map = map,
+            }
+            player.next_map_change += 1;
+        }
+        return;
+    }
+
+    if let Some(mut recorder) = recorder {
+        recorder
+            .replay
+            .map_changes
+            .push(game.selected_map.info.display_name.clone());
+    }
+}
+
+// Feeds the recorded inputs in place of the keyboard
+pub fn replay_playback_input_system(
+    clock: Res<GameClock>,
+    player: Option<ResMut<ReplayPlayer>>,
+    mut input_queue: ResMut<PlayerInputQueue>,
+    state: Res<State<AppStates>>,
+) {
+    let mut player = match player {
+        None => return,
+        Some(player) => player,
+    };
+
+    if state.current() != &AppStates::LoadGame {
+        return;
+    }
+
+    input_queue.inputs.clear();
+
+    // Each recorded frame's inputs go in on the update that plays that frame
+    if clock.frames_due == 0 {
+        return;
+    }
+
+    while let Some(recorded) = player.replay.inputs.get(player.next_input) {
+        if (recorded.round, recorded.frame) > (player.round, player.frame) {
+            break;
+        }
+
+        if recorded.round == player.round {
+            input_queue.inputs.push(recorded.input);
+        }
+        player.next_input += 1;
+    }
+
+    player.frame += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(player_id: u64) -> PlayerInput {
+        PlayerInput {
+            player_id,
+            purpose: InputPurpose::Left,
+            input_type: InputKeyboardType::JustPressed,
+        }
+    }
+
+    fn empty_replay() -> Replay {
+        let map: Map = toml::from_str(
+            "[info]\ndisplay_name = \"Test\"\nbase_dimensions = [1920.0, 1080.0]\n",
+        )
+        .unwrap();
+
+        Replay {
+            version: REPLAYVERSION,
+            seed: 0,
+            map,
+            available_maps: vec![],
+            characters: HashMap::new(),
+            inputs: vec![],
+            map_changes: vec![],
+            ruleset: RulesetSettings::default(),
+        }
+    }
+
+    fn game_world() -> World {
+        let mut world = World::new();
+        let mut clock = GameClock::new(60.0);
+        clock.paused = false;
+        world.insert_resource(clock);
+        world.insert_resource(PlayerInputQueue::default());
+        world.insert_resource(State::new(AppStates::LoadGame));
+        world
+    }
+
+    // One update with frames_due frames in it, returns the inputs movement_input_system would have applied and on what frame
+    fn update(
+        world: &mut World,
+        stage: &mut SystemStage,
+        frames_due: u64,
+        device_input: Option<PlayerInput>,
+    ) -> Vec<(u64, PlayerInput)> {
+        world.resource_mut::<GameClock>().frames_due = frames_due;
+        if let Some(input) = device_input {
+            world.resource_mut::<PlayerInputQueue>().inputs.push(input);
+        }
+
+        stage.run(world);
+        world.resource_mut::<GameClock>().advance();
+
+        if frames_due == 0 {
+            return vec![];
+        }
+        let frame = world.resource::<GameClock>().gameplay_frame;
+        std::mem::take(&mut world.resource_mut::<PlayerInputQueue>().inputs)
+            .into_iter()
+            .map(|input| (frame, input))
+            .collect()
+    }
+
+    #[test]
+    fn playback_lands_on_recorded_frames() {
+        let mut world = game_world();
+        world.insert_resource(ReplayRecorder {
+            replay: empty_replay(),
+            path: PathBuf::new(),
+            round: 1,
+            frame: 0,
+        });
+        let mut record_stage = SystemStage::single_threaded();
+        record_stage.add_system(record_replay_inputs);
+
+        // A display that keeps missing frames, presses on updates without a frame wait for the next one
+        let recording_pattern = [1, 0, 0, 1, 1, 0, 1, 0, 0, 0, 1, 1];
+        let mut recorded = vec![];
+        for (update_index, frames_due) in recording_pattern.iter().cycle().take(120).enumerate() {
+            let device_input = match update_index % 5 {
+                0 => Some(press(1)),
+                3 => Some(press(2)),
+                _ => None,
+            };
+            recorded.extend(update(
+                &mut world,
+                &mut record_stage,
+                *frames_due,
+                device_input,
+            ));
+        }
+        let recorded_frames = world.resource::<GameClock>().gameplay_frame;
+        let replay = world.remove_resource::<ReplayRecorder>().unwrap().replay;
+
+        let mut world = game_world();
+        world.insert_resource(ReplayPlayer {
+            replay,
+            round: 1,
+            frame: 0,
+            next_input: 0,
+            next_map_change: 0,
+        });
+        let mut playback_stage = SystemStage::single_threaded();
+        playback_stage.add_system(replay_playback_input_system);
+
+        // Played back on a display that's fast some of the time, keys pressed while watching dont count
+        let playback_pattern = [1, 1, 0];
+        let mut played = vec![];
+        for (update_index, frames_due) in playback_pattern.iter().cycle().enumerate() {
+            if world.resource::<GameClock>().gameplay_frame >= recorded_frames {
+                break;
+            }
+
+            let device_input = match update_index % 7 {
+                0 => Some(press(3)),
+                _ => None,
+            };
+            played.extend(update(
+                &mut world,
+                &mut playback_stage,
+                *frames_due,
+                device_input,
+            ));
+        }
+
+        assert!(!recorded.is_empty());
+        assert_eq!(recorded, played);
+    }
+}
@@ -7,12 +7,14 @@ use bevy_rapier2d::prelude::*;
 use serde::{Deserialize, Serialize};
 
 use crate::action_traits::ScaleToRatio;
-use crate::char::{AttackBuffer, PlayerHealth, PlayerIdentifier};
+use crate::animation::PlayerAnimation;
+use crate::char::{AttackBuffer, PlayerHealth, PlayerIdentifier, PlayerStocks};
 use crate::collider::{
     AAColliderType, AugmentedCollider, ColliderMap, ColliderSyncEntity, DeathColliderIdentifier,
     JumpResetColliderIdentifier, SolidColliderIdentifier,
 };
 use crate::projectile::ProjectileIdentifier;
+use crate::shield::PlayerShield;
 use crate::universal::*;
 
 pub type PhysMap = HashMap<String, AAPhysicsObject>;
@@ -94,11 +96,15 @@ pub enum PhysicsSpawnExtras {
     VelocityCap(VelocityForceCap),
     SpawnTransform(Transform),
     PlayerHP(PlayerHealth),
+    PlayerStocks(PlayerStocks),
+    PlayerShield(PlayerShield),
+    PlayerAnimation(PlayerAnimation),
     AttackBuffer(AttackBuffer),
     Sensor(Sensor),
     GravityScale(GravityScale),
     ContinuousCollisionDetection(Ccd),
     ProjectileIdentifier(ProjectileIdentifier),
+    Restitution(Restitution),
 }
 
 pub trait AASyncSpawn {
@@ -119,6 +125,7 @@ pub enum ExtraInserts {
     JumpResetColliderIdentifier(JumpResetColliderIdentifier),
     DeathColliderIdentifier(DeathColliderIdentifier),
     Sensor(Sensor),
+    Restitution(Restitution),
 }
 
 impl AASyncSpawn for PhysMap {
@@ -166,6 +173,7 @@ impl AASyncSpawn for PhysMap {
                 }
 
                 let mut collider_sensor = false;
+                let mut collider_restitution = None;
 
                 for extra in extras {
                     match extra {
@@ -190,6 +198,15 @@ impl AASyncSpawn for PhysMap {
                         PhysicsSpawnExtras::PlayerHP(player_health) => {
                             phys_entity.insert(player_health);
                         }
+                        PhysicsSpawnExtras::PlayerStocks(player_stocks) => {
+                            phys_entity.insert(player_stocks);
+                        }
+                        PhysicsSpawnExtras::PlayerShield(player_shield) => {
+                            phys_entity.insert(player_shield);
+                        }
+                        PhysicsSpawnExtras::PlayerAnimation(player_animation) => {
+                            phys_entity.insert(player_animation);
+                        }
                         PhysicsSpawnExtras::AttackBuffer(buffer) => {
                             phys_entity.insert(buffer);
                         }
@@ -203,6 +220,9 @@ impl AASyncSpawn for PhysMap {
                         PhysicsSpawnExtras::GravityScale(scale) => {
                             phys_entity.insert(scale);
                         }
+                        PhysicsSpawnExtras::Restitution(restitution) => {
+                            collider_restitution = Some(restitution)
+                        }
                         _ => {}
                     }
                 }
@@ -239,6 +259,10 @@ impl AASyncSpawn for PhysMap {
                                 extra_inserts.push(ExtraInserts::Sensor(Sensor(true)));
                             }
 
+                            if let Some(restitution) = collider_restitution {
+                                extra_inserts.push(ExtraInserts::Restitution(restitution));
+                            }
+
                             let mut collider_commands = parent.spawn_bundle(collider);
 
                             for extra in extra_inserts {
@@ -255,6 +279,9 @@ impl AASyncSpawn for PhysMap {
                                     ExtraInserts::Sensor(sensor) => {
                                         collider_commands.insert(sensor);
                                     }
+                                    ExtraInserts::Restitution(restitution) => {
+                                        collider_commands.insert(restitution);
+                                    }
                                 }
                             }
                         }
@@ -0,0 +1,542 @@
+use bevy::prelude::*;
+
+use crate::assets::AugmentedFonts;
+use crate::char::ConvertToRgb;
+use crate::draw::WinitWindowsInfo;
+use crate::game::*;
+use crate::{reverse_char_input_purpose, AppStates, MenuInputScheme};
+
+// The order keys get asked for when rebinding a player's controls
+pub const REBIND_ORDER: [InputPurpose; 7] = [
+    InputPurpose::Up,
+    InputPurpose::Down,
+    InputPurpose::Left,
+    InputPurpose::Right,
+    InputPurpose::Atk1,
+    InputPurpose::Atk2,
+    InputPurpose::Reset,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SettingsOption {
+    WindowWidth,
+    WindowHeight,
+    Vignette,
+    DebugMode,
+    AttackBufferResetTime,
+    CountdownToStartTime,
+    RoundsToMapChange,
+    Ruleset,
+    Stocks,
+    MasterVolume,
+    MusicVolume,
+    SfxVolume,
+    Rebind(u64),
+    Save,
+    Back,
+}
+
+pub struct Rebinding {
+    pub player_id: u64,
+    pub next_purpose: usize,
+    pub ctrls: CharacterInputSettings,
+}
+
+pub struct SettingsMenu {
+    // Everything is changed on a copy, nothing happens to the real settings until they're saved
+    pub editing: GameSettings,
+    pub options: Vec<SettingsOption>,
+    pub current_selected_id: usize,
+    // First option on screen, there are more options than fit so the list scrolls with the selection
+    pub scroll: usize,
+    pub input_scheme: MenuInputScheme,
+    pub rebinding: Option<Rebinding>,
+    pub message: String,
+}
+
+#[derive(Component)]
+pub struct SettingsMenuComponent;
+
+impl SettingsMenu {
+    pub fn select(&mut self, selected_id: usize, visible_options: usize) {
+        self.current_selected_id = selected_id;
+        if selected_id < self.scroll {
+            self.scroll = selected_id;
+        } else if selected_id >= self.scroll + visible_options {
+            self.scroll = selected_id + 1 - visible_options;
+        }
+    }
+}
+
+// How many options fit between the top of the menu and the message line, leaving a gap above the message
+pub fn visible_option_count(menu_config: &SettingsMenuConfig) -> usize {
+    let spacing = menu_config.option_individual_distance.abs();
+    if spacing <= 0.0 {
+        return usize::MAX;
+    }
+
+    let space = menu_config.option_window_down - menu_config.message_window_down;
+    ((space / spacing).floor() as usize).saturating_sub(1).max(1)
+}
+
+pub fn settings_options() -> Vec<SettingsOption> {
+    let mut options = vec![
+        SettingsOption::WindowWidth,
+        SettingsOption::WindowHeight,
+        SettingsOption::Vignette,
+        SettingsOption::DebugMode,
+        SettingsOption::AttackBufferResetTime,
+        SettingsOption::CountdownToStartTime,
+        SettingsOption::RoundsToMapChange,
+        SettingsOption::Ruleset,
+        SettingsOption::Stocks,
+        SettingsOption::MasterVolume,
+        SettingsOption::MusicVolume,
+        SettingsOption::SfxVolume,
+    ];
+
+    for player_id in 1..=8 {
+        options.push(SettingsOption::Rebind(player_id));
+    }
+
+    options.push(SettingsOption::Save);
+    options.push(SettingsOption::Back);
+    options
+}
+
+pub fn option_text(option: SettingsOption, settings: &GameSettings) -> String {
+    let on_off = |toggle: bool| if toggle { "ON" } else { "OFF" };
+
+    match option {
+        SettingsOption::WindowWidth => format!("WINDOW WIDTH: {}", settings.window.width),
+        SettingsOption::WindowHeight => format!("WINDOW HEIGHT: {}", settings.window.height),
+        SettingsOption::Vignette => {
+            format!("VIGNETTE: {}", on_off(settings.special_settings.vignette))
+        }
+        SettingsOption::DebugMode => {
+            format!(
+                "DEBUG MODE: {}",
+                on_off(settings.special_settings.debug_mode)
+            )
+        }
+        SettingsOption::AttackBufferResetTime => format!(
+            "ATTACK BUFFER RESET TIME: {:.1}",
+            settings.gameplay_settings.attack_buffer_reset_time
+        ),
+        SettingsOption::CountdownToStartTime => format!(
+            "COUNTDOWN TO START: {:.1}",
+            settings.gameplay_settings.countdown_to_start_time
+        ),
+        SettingsOption::RoundsToMapChange => format!(
+            "ROUNDS TO MAP CHANGE: {}",
+            settings.gameplay_settings.rounds_to_map_change
+        ),
+        SettingsOption::Ruleset => format!(
+            "RULESET: {}",
+            match settings.ruleset.ruleset {
+                Ruleset::Health => "HEALTH",
+                Ruleset::Percentage => "PERCENTAGE",
+            }
+        ),
+        SettingsOption::Stocks => format!("STOCKS: {}", settings.ruleset.stocks),
+        SettingsOption::MasterVolume => {
+            format!("MASTER VOLUME: {:.0}%", settings.audio.master_volume * 100.0)
+        }
+        SettingsOption::MusicVolume => {
+            format!("MUSIC VOLUME: {:.0}%", settings.audio.music_volume * 100.0)
+        }
+        SettingsOption::SfxVolume => {
+            format!("SFX VOLUME: {:.0}%", settings.audio.sfx_volume * 100.0)
+        }
+        SettingsOption::Rebind(player_id) => {
+            let ctrls = settings.player_ctrls(player_id);
+            format!(
+                "P{} CONTROLS: {:?} {:?} {:?} {:?} {:?} {:?} {:?}",
+                player_id,
+                ctrls.up_button,
+                ctrls.down_button,
+                ctrls.left_button,
+                ctrls.right_button,
+                ctrls.attack_one,
+                ctrls.attack_two,
+                ctrls.reset
+            )
+        }
+        SettingsOption::Save => "SAVE".to_string(),
+        SettingsOption::Back => "BACK".to_string(),
+    }
+}
+
+// Left and right change values, -1.0 for left and 1.0 for right
+pub fn adjust_option(option: SettingsOption, settings: &mut GameSettings, direction: f32) {
+    match option {
+        SettingsOption::WindowWidth => {
+            settings.window.width = (settings.window.width + 80.0 * direction).max(640.0)
+        }
+        SettingsOption::WindowHeight => {
+            settings.window.height = (settings.window.height + 45.0 * direction).max(360.0)
+        }
+        SettingsOption::Vignette => {
+            settings.special_settings.vignette = !settings.special_settings.vignette
+        }
+        SettingsOption::DebugMode => {
+            settings.special_settings.debug_mode = !settings.special_settings.debug_mode
+        }
+        SettingsOption::AttackBufferResetTime => {
+            let reset_time = &mut settings.gameplay_settings.attack_buffer_reset_time;
+            *reset_time = (*reset_time + 0.1 * direction as f64).max(0.1);
+        }
+        SettingsOption::CountdownToStartTime => {
+            let countdown = &mut settings.gameplay_settings.countdown_to_start_time;
+            *countdown = (*countdown + 0.5 * direction as f64).max(0.0);
+        }
+        SettingsOption::RoundsToMapChange => {
+            let rounds = &mut settings.gameplay_settings.rounds_to_map_change;
+            *rounds = if direction < 0.0 {
+                rounds.saturating_sub(1).max(1)
+            } else {
+                *rounds + 1
+            };
+        }
+        SettingsOption::Ruleset => {
+            settings.ruleset.ruleset = match settings.ruleset.ruleset {
+                Ruleset::Health => Ruleset::Percentage,
+                Ruleset::Percentage => Ruleset::Health,
+            }
+        }
+        SettingsOption::Stocks => {
+            let stocks = &mut settings.ruleset.stocks;
+            *stocks = if direction < 0.0 {
+                stocks.saturating_sub(1).max(1)
+            } else {
+                *stocks + 1
+            };
+        }
+        SettingsOption::MasterVolume => {
+            let volume = &mut settings.audio.master_volume;
+            *volume = (*volume + 0.1 * direction).clamp(0.0, 1.0);
+        }
+        SettingsOption::MusicVolume => {
+            let volume = &mut settings.audio.music_volume;
+            *volume = (*volume + 0.1 * direction).clamp(0.0, 1.0);
+        }
+        SettingsOption::SfxVolume => {
+            let volume = &mut settings.audio.sfx_volume;
+            *volume = (*volume + 0.1 * direction).clamp(0.0, 1.0);
+        }
+        _ => {}
+    }
+}
+
+// Finds who is already using a key, the player being rebound only counts the keys they've picked so far
+pub fn find_key_conflict(
+    settings: &GameSettings,
+    rebinding: &Rebinding,
+    key: KeyCode,
+) -> Option<String> {
+    if key == settings.player_id.start_key {
+        return Some("START".to_string());
+    }
+    if key == settings.cpu.add_key {
+        return Some("ADD CPU".to_string());
+    }
+    if key == settings.cpu.difficulty_key {
+        return Some("CPU DIFFICULTY".to_string());
+    }
+
+    for player_id in 1..=8 {
+        let ctrls = if player_id == rebinding.player_id {
+            rebinding.ctrls
+        } else {
+            *settings.player_ctrls(player_id)
+        };
+
+        for (bound_key, purpose) in ctrls {
+            if player_id == rebinding.player_id
+                && !REBIND_ORDER[..rebinding.next_purpose].contains(&purpose)
+            {
+                continue;
+            }
+
+            if bound_key == key {
+                return Some(format!("P{} {:?}", player_id, purpose));
+            }
+        }
+    }
+
+    None
+}
+
+pub fn spawn_settings_menu(
+    mut commands: Commands,
+    mut input: ResMut<Input<KeyCode>>,
+    settings: Res<GameSettings>,
+    fonts: Res<AugmentedFonts>,
+    window: Res<WinitWindowsInfo>,
+) {
+    let menu = SettingsMenu {
+        editing: (*settings).clone(),
+        options: settings_options(),
+        current_selected_id: 0,
+        scroll: 0,
+        input_scheme: reverse_char_input_purpose(&settings),
+        rebinding: None,
+        message: String::new(),
+    };
+
+    // The press that opened the menu would otherwise change the first option straight away
+    input.clear();
+    draw_settings_menu(&mut commands, &menu, &settings, &fonts, &window);
+    commands.insert_resource(menu);
+}
+
+pub fn draw_settings_menu(
+    commands: &mut Commands,
+    menu: &SettingsMenu,
+    settings: &GameSettings,
+    fonts: &Res<AugmentedFonts>,
+    window: &Res<WinitWindowsInfo>,
+) {
+    let menu_config = &settings.settings_menu;
+
+    let text_alignment = TextAlignment {
+        vertical: VerticalAlign::Center,
+        horizontal: HorizontalAlign::Center,
+    };
+
+    let option_style = TextStyle {
+        font: fonts.bold_font.clone(),
+        font_size: settings.window.height * menu_config.option_font_size,
+        color: Color::from(menu_config.option_font_colour.convert_to_rgb()),
+    };
+
+    let option_height = |index: usize| {
+        settings.window.height
+            * (menu_config.option_window_down
+                + index as f32 * menu_config.option_individual_distance)
+    };
+
+    let visible_options = visible_option_count(menu_config);
+    let shown_options = menu.options.iter().enumerate().skip(menu.scroll);
+    for (index, option) in shown_options.take(visible_options) {
+        commands
+            .spawn_bundle(Text2dBundle {
+                text: Text::with_section(
+                    option_text(*option, &menu.editing),
+                    option_style.clone(),
+                    text_alignment.clone(),
+                ),
+                transform: Transform::from_translation(Vec3::new(
+                    0.,
+                    option_height(index - menu.scroll),
+                    50.,
+                )),
+                ..Default::default()
+            })
+            .insert(SettingsMenuComponent);
+    }
+
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: Color::from(menu_config.highlight_colour.convert_to_rgb()),
+                custom_size: Some(Vec2::new(
+                    window.screen_dim.x,
+                    menu_config.highlight_height * settings.window.height,
+                )),
+                ..Default::default()
+            },
+            transform: Transform::from_translation(Vec3::new(
+                0.,
+                option_height(menu.current_selected_id - menu.scroll),
+                20.,
+            )),
+            ..Default::default()
+        })
+        .insert(SettingsMenuComponent);
+
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::with_section(
+                menu.message.clone(),
+                TextStyle {
+                    color: Color::from(menu_config.message_font_colour.convert_to_rgb()),
+                    ..option_style.clone()
+                },
+                text_alignment.clone(),
+            ),
+            transform: Transform::from_translation(Vec3::new(
+                0.,
+                settings.window.height * menu_config.message_window_down,
+                50.,
+            )),
+            ..Default::default()
+        })
+        .insert(SettingsMenuComponent);
+}
+
+pub fn settings_menu_input(
+    mut input: ResMut<Input<KeyCode>>,
+    mut menu: ResMut<SettingsMenu>,
+    mut settings: ResMut<GameSettings>,
+    mut windows: ResMut<Windows>,
+    mut state: ResMut<State<AppStates>>,
+    fonts: Res<AugmentedFonts>,
+    window: Res<WinitWindowsInfo>,
+    menu_components: Query<Entity, With<SettingsMenuComponent>>,
+    mut commands: Commands,
+) {
+    let menu = &mut *menu;
+    let mut changed = false;
+
+    if let Some(mut rebinding) = menu.rebinding.take() {
+        let key = match input.get_just_pressed().next() {
+            None => {
+                menu.rebinding = Some(rebinding);
+                return;
+            }
+            Some(key) => *key,
+        };
+
+        if key == KeyCode::Escape {
+            menu.message = String::new();
+        } else {
+            match find_key_conflict(&menu.editing, &rebinding, key) {
+                Some(conflict) => {
+                    menu.message = format!("{:?} IS ALREADY USED BY {}", key, conflict);
+                    menu.rebinding = Some(rebinding);
+                }
+                None => {
+                    let purpose = REBIND_ORDER[rebinding.next_purpose];
+                    rebinding.ctrls.set_key(purpose, key);
+                    rebinding.next_purpose += 1;
+
+                    if rebinding.next_purpose >= REBIND_ORDER.len() {
+                        *menu.editing.player_ctrls_mut(rebinding.player_id) = rebinding.ctrls;
+                        menu.message = String::new();
+                    } else {
+                        menu.message = rebind_message(&rebinding);
+                        menu.rebinding = Some(rebinding);
+                    }
+                }
+            }
+        }
+
+        changed = true;
+    } else {
+        let mut selected_id = menu.current_selected_id;
+        let option = menu.options[selected_id];
+        let mut saved = false;
+
+        if input.just_pressed(KeyCode::Escape) {
+            leave_settings_menu(&mut input, &mut state, &menu_components, &mut commands);
+            return;
+        }
+
+        for (keycode, actions) in menu.input_scheme.iter() {
+            if !input.just_pressed(*keycode) {
+                continue;
+            }
+
+            for action in actions {
+                match action {
+                    InputPurpose::Up => {
+                        selected_id = if selected_id > 0 {
+                            selected_id - 1
+                        } else {
+                            menu.options.len() - 1
+                        };
+                    }
+                    InputPurpose::Down => {
+                        selected_id = if selected_id < menu.options.len() - 1 {
+                            selected_id + 1
+                        } else {
+                            0
+                        };
+                    }
+                    InputPurpose::Left => adjust_option(option, &mut menu.editing, -1.0),
+                    InputPurpose::Right => adjust_option(option, &mut menu.editing, 1.0),
+                    InputPurpose::Atk1 => match option {
+                        SettingsOption::Rebind(player_id) => {
+                            let rebinding = Rebinding {
+                                player_id,
+                                next_purpose: 0,
+                                ctrls: *menu.editing.player_ctrls(player_id),
+                            };
+                            menu.message = rebind_message(&rebinding);
+                            menu.rebinding = Some(rebinding);
+                        }
+                        SettingsOption::Save => {
+                            match save_settings(&menu.editing) {
+                                Ok(_) => {
+                                    if let Some(primary_window) = windows.get_primary_mut() {
+                                        primary_window.set_resolution(
+                                            menu.editing.window.width,
+                                            menu.editing.window.height,
+                                        );
+                                    }
+                                    *settings = menu.editing.clone();
+                                    saved = true;
+                                    menu.message = "SAVED".to_string();
+                                }
+                                Err(error) => {
+                                    warn!("Failed to save settings: {}", error);
+                                    menu.message = "FAILED TO SAVE SETTINGS".to_string();
+                                }
+                            };
+                        }
+                        SettingsOption::Back => {
+                            leave_settings_menu(
+                                &mut input,
+                                &mut state,
+                                &menu_components,
+                                &mut commands,
+                            );
+                            return;
+                        }
+                        _ => adjust_option(option, &mut menu.editing, 1.0),
+                    },
+                    _ => {}
+                }
+            }
+
+            changed = true;
+        }
+
+        // The menu keys can have been rebound, they only change once saved
+        if saved {
+            menu.input_scheme = reverse_char_input_purpose(&settings);
+        }
+        menu.select(selected_id, visible_option_count(&settings.settings_menu));
+    }
+
+    if changed {
+        for entity in menu_components.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        draw_settings_menu(&mut commands, menu, &settings, &fonts, &window);
+    }
+}
+
+fn rebind_message(rebinding: &Rebinding) -> String {
+    format!(
+        "PRESS THE KEY FOR P{} {:?} (ESCAPE TO CANCEL)",
+        rebinding.player_id, REBIND_ORDER[rebinding.next_purpose]
+    )
+}
+
+fn leave_settings_menu(
+    input: &mut ResMut<Input<KeyCode>>,
+    state: &mut ResMut<State<AppStates>>,
+    menu_components: &Query<Entity, With<SettingsMenuComponent>>,
+    commands: &mut Commands,
+) {
+    for entity in menu_components.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<SettingsMenu>();
+
+    // Otherwise the main menu sees the same press and picks whatever is highlighted
+    input.clear();
+    state.set(AppStates::MainMenu);
+}
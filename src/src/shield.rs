@@ -0,0 +1,319 @@
+use bevy::prelude::*;
+use bevy::sprite::MaterialMesh2dBundle;
+use serde::{Deserialize, Serialize};
+
+use crate::char::{
+    AAPlayerDescriptor, CharComponentMap, Character, ConvertToRgb, SyncTransformOffset,
+};
+use crate::game::*;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ParryEffect {
+    // The projectile is just gone
+    Negate,
+    // The projectile turns around and counts as the parrying player's own
+    Reflect,
+}
+
+// [shield] in a character's main.toml, held up with down. Characters without any shield health cant block
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct Shield {
+    pub health: f32,
+
+    // Shield health gained back every second while it isnt up
+    pub regen: f32,
+
+    // Fraction of a blocked hit that never reaches the player, the shield takes all of it either way
+    pub damage_reduction: f32,
+
+    // Frames from raising the shield where hits get parried instead of blocked
+    pub parry_frames: u64,
+    pub parry_effect: ParryEffect,
+
+    // Hitstun for getting the shield broken
+    pub break_stun_frames: u64,
+
+    // The bubble drawn around the core, radius is in the character's base dimensions
+    pub radius: f32,
+    pub colour: [f32; 3],
+    pub parry_colour: [f32; 3],
+    pub opacity: f32,
+}
+
+impl Default for Shield {
+    fn default() -> Self {
+        Self {
+            health: 0.0,
+            regen: 10.0,
+            damage_reduction: 1.0,
+            parry_frames: 6,
+            parry_effect: ParryEffect::Negate,
+            break_stun_frames: 90,
+            radius: 60.0,
+            colour: [120.0, 180.0, 255.0],
+            parry_colour: [255.0, 255.0, 255.0],
+            opacity: 0.4,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ShieldBlock {
+    Unblocked,
+    // The damage that still gets through
+    Blocked(f32),
+    Parried(ParryEffect),
+}
+
+#[derive(Debug, Clone, Copy, Component)]
+pub struct PlayerShield {
+    pub shield: Shield,
+    pub health: f32,
+    pub raised: bool,
+    pub raised_frame: u64,
+    pub broken_until: u64,
+}
+
+impl PlayerShield {
+    pub fn new(shield: Shield) -> Self {
+        Self {
+            shield,
+            health: shield.health,
+            raised: false,
+            raised_frame: 0,
+            broken_until: 0,
+        }
+    }
+
+    pub fn is_broken(&self, clock: &GameClock) -> bool {
+        clock.gameplay_frame < self.broken_until
+    }
+
+    pub fn raise(&mut self, clock: &GameClock) {
+        if self.raised || self.health <= 0.0 || self.is_broken(clock) {
+            return;
+        }
+
+        self.raised = true;
+        self.raised_frame = clock.gameplay_frame;
+    }
+
+    pub fn lower(&mut self) {
+        self.raised = false;
+    }
+
+    pub fn in_parry_window(&self, clock: &GameClock) -> bool {
+        self.raised && clock.gameplay_frame < self.raised_frame + self.shield.parry_frames
+    }
+
+    pub fn block(
+        &mut self,
+        damage: f32,
+        player_descriptor: &mut AAPlayerDescriptor,
+        clock: &GameClock,
+    ) -> ShieldBlock {
+        if !self.raised {
+            return ShieldBlock::Unblocked;
+        }
+
+        if self.in_parry_window(clock) {
+            return ShieldBlock::Parried(self.shield.parry_effect);
+        }
+
+        self.health -= damage;
+        if self.health <= 0.0 {
+            self.health = 0.0;
+            self.raised = false;
+            self.broken_until = clock.gameplay_frame + self.shield.break_stun_frames;
+            player_descriptor.hitstun_until =
+                player_descriptor.hitstun_until.max(self.broken_until);
+        }
+
+        ShieldBlock::Blocked(damage * (1.0 - self.shield.damage_reduction.clamp(0.0, 1.0)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Component)]
+pub struct PlayerShieldIndicatorId {
+    pub player_id: u64,
+}
+
+pub fn create_shield_indicator(
+    char: &Character,
+    commands: &mut Commands,
+    ratio: Vec3,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<ColorMaterial>>,
+    player_id: u64,
+) -> Entity {
+    let colour = char.shield.colour.convert_to_rgb();
+
+    commands
+        .spawn_bundle(MaterialMesh2dBundle {
+            mesh: meshes
+                .add(Mesh::from(shape::Circle::new(char.shield.radius / ratio.y)))
+                .into(),
+            transform: Transform::from_translation(Vec3::new(0.0, 0.0, 996.0)),
+            material: materials.add(ColorMaterial::from(Color::rgba(
+                colour[0],
+                colour[1],
+                colour[2],
+                char.shield.opacity,
+            ))),
+            visibility: Visibility { is_visible: false },
+            ..Default::default()
+        })
+        .insert(SyncTransformOffset::default())
+        .insert(PlayerShieldIndicatorId { player_id })
+        .id()
+}
+
+pub fn shield_regen(clock: Res<GameClock>, mut shield_query: Query<&mut PlayerShield>) {
+    if clock.paused {
+        return;
+    }
+
+    for mut player_shield in shield_query.iter_mut() {
+        if player_shield.raised || player_shield.is_broken(&clock) {
+            continue;
+        }
+
+        let maximum = player_shield.shield.health;
+        let regen = player_shield.shield.regen * clock.frame_delta() as f32;
+        player_shield.health = (player_shield.health + regen).min(maximum);
+    }
+}
+
+pub fn shield_indicator_update(
+    clock: Res<GameClock>,
+    char_map: Res<CharComponentMap>,
+    shield_query: Query<&PlayerShield>,
+    mut indicator_query: Query<(
+        &PlayerShieldIndicatorId,
+        &mut Visibility,
+        &mut Transform,
+        &Handle<ColorMaterial>,
+    )>,
+    mut colour_material_assets: ResMut<Assets<ColorMaterial>>,
+) {
+    for (indicator, mut visibility, mut transform, colour_material) in indicator_query.iter_mut() {
+        let player_shield = match char_map
+            .get(&indicator.player_id)
+            .map(|character_entity| shield_query.get(character_entity.core))
+        {
+            Some(Ok(player_shield)) => player_shield,
+            _ => continue,
+        };
+
+        visibility.is_visible = player_shield.raised;
+        if !player_shield.raised {
+            continue;
+        }
+
+        // Shrinks down to half its size as it runs out of health
+        let health_ratio = player_shield.health / player_shield.shield.health.max(f32::EPSILON);
+        transform.scale = Vec3::splat(0.5 + 0.5 * health_ratio.clamp(0.0, 1.0));
+
+        let colour = match player_shield.in_parry_window(&clock) {
+            true => player_shield.shield.parry_colour,
+            false => player_shield.shield.colour,
+        }
+        .convert_to_rgb();
+
+        if let Some(colour_material_asset) = colour_material_assets.get_mut(colour_material) {
+            colour_material_asset.color = Color::rgba(
+                colour[0],
+                colour[1],
+                colour[2],
+                player_shield.shield.opacity,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at_frame(gameplay_frame: u64) -> GameClock {
+        let mut clock = GameClock::new(60.0);
+        clock.gameplay_frame = gameplay_frame;
+        clock
+    }
+
+    fn raised_shield(health: f32, raised_frame: u64) -> PlayerShield {
+        let mut player_shield = PlayerShield::new(Shield {
+            health,
+            damage_reduction: 0.75,
+            parry_frames: 6,
+            parry_effect: ParryEffect::Reflect,
+            break_stun_frames: 90,
+            ..Default::default()
+        });
+        player_shield.raise(&at_frame(raised_frame));
+        player_shield
+    }
+
+    #[test]
+    fn parry_window_covers_the_first_frames() {
+        let player_shield = raised_shield(100.0, 10);
+
+        assert!(player_shield.in_parry_window(&at_frame(10)));
+        assert!(player_shield.in_parry_window(&at_frame(15)));
+        assert!(!player_shield.in_parry_window(&at_frame(16)));
+    }
+
+    #[test]
+    fn parried_hits_leave_the_shield_alone() {
+        let mut player_shield = raised_shield(100.0, 10);
+        let mut descriptor = AAPlayerDescriptor::default();
+
+        assert_eq!(
+            player_shield.block(20.0, &mut descriptor, &at_frame(12)),
+            ShieldBlock::Parried(ParryEffect::Reflect)
+        );
+        assert_eq!(player_shield.health, 100.0);
+    }
+
+    #[test]
+    fn blocked_hits_come_off_the_shield() {
+        let mut player_shield = raised_shield(100.0, 10);
+        let mut descriptor = AAPlayerDescriptor::default();
+
+        // The shield takes all of it and a quarter still gets through
+        assert_eq!(
+            player_shield.block(20.0, &mut descriptor, &at_frame(30)),
+            ShieldBlock::Blocked(5.0)
+        );
+        assert_eq!(player_shield.health, 80.0);
+        assert!(player_shield.raised);
+    }
+
+    #[test]
+    fn breaking_the_shield_stuns() {
+        let mut player_shield = raised_shield(10.0, 10);
+        let mut descriptor = AAPlayerDescriptor::default();
+
+        assert_eq!(
+            player_shield.block(20.0, &mut descriptor, &at_frame(30)),
+            ShieldBlock::Blocked(5.0)
+        );
+        assert_eq!(player_shield.health, 0.0);
+        assert!(!player_shield.raised);
+        assert_eq!(player_shield.broken_until, 120);
+        assert_eq!(descriptor.hitstun_until, 120);
+
+        // It cant go back up until the stun is over, and nothing is blocked while it's down
+        player_shield.health = 10.0;
+        player_shield.raise(&at_frame(119));
+        assert!(!player_shield.raised);
+        assert_eq!(
+            player_shield.block(20.0, &mut descriptor, &at_frame(119)),
+            ShieldBlock::Unblocked
+        );
+        player_shield.raise(&at_frame(120));
+        assert!(player_shield.raised);
+    }
+}
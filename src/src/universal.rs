@@ -1,7 +1,7 @@
 use std::collections::HashMap;
-use std::fs::{DirEntry, ReadDir};
+use std::fs::ReadDir;
 use std::num::NonZeroU8;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use bevy::asset::LoadState;
@@ -17,6 +17,7 @@ use tokio::sync::RwLock;
 
 use crate::assets::{get_asset, AssetInfoType, AssetMap, AssetType};
 use crate::draw::get_info_scale_resolution;
+use crate::pack_io::{is_zip_pack, read_zip_pack, resolve_asset_path};
 use crate::{error, AssetInfoMap};
 
 // The sync map
@@ -40,7 +41,9 @@ pub enum PossibleBundle {
     Svg(AASvg2dBundle),
 }
 
+// Relative to the same place the asset server reads from, so the paths handed to it point at the same packs
 pub fn read_game_dir(dir: String) -> ReadDir {
+    let dir = resolve_asset_path(Path::new(&dir));
     match std::fs::read_dir(&dir) {
         Ok(game_dir) => game_dir,
         Err(angry) => {
@@ -52,18 +55,20 @@ pub fn read_game_dir(dir: String) -> ReadDir {
 }
 
 pub fn load_item<T: serde::de::DeserializeOwned + PathAdjust>(
-    possible_map: std::io::Result<DirEntry>,
+    path: &Path,
     main_file: &str,
 ) -> Result<T, anyhow::Error> {
-    let item = possible_map?;
+    // Zipped packs get read without extracting them, the asset server reads the rest of them the same way
+    let (content, base_path) = if is_zip_pack(path) {
+        read_zip_pack(path, main_file)?
+    } else {
+        let main_path = resolve_asset_path(&path.join(main_file));
+        (std::fs::read_to_string(main_path)?, path.to_path_buf())
+    };
 
-    let mut path = item.path();
-    path.push(main_file);
-
-    let content = std::fs::read_to_string(path)?;
     let mut data: T = toml::from_str(&content).unwrap();
 
-    data.change_path(item.path());
+    data.change_path(base_path);
     Ok(data)
 }
 
@@ -72,10 +77,30 @@ pub fn load_directory<T: serde::de::DeserializeOwned + PathAdjust>(
     main_file: &str,
     load_type: &mut Vec<T>,
 ) {
-    let read_dir = read_game_dir(dir);
+    let mut pack_paths = vec![];
+    for possible_item in read_game_dir(dir.clone()) {
+        match possible_item {
+            Ok(item) => pack_paths.push(PathBuf::from(&dir).join(item.file_name())),
+            Err(angry) => error!("{}", angry),
+        }
+    }
+
+    // A pack thats there as both a folder and a zip would get loaded twice, the folder is the one that gets used
+    pack_paths.retain(|path| {
+        let duplicate = is_zip_pack(path)
+            && path
+                .file_stem()
+                .map(|name| resolve_asset_path(&PathBuf::from(&dir).join(name)).is_dir())
+                .unwrap_or(false);
+
+        if duplicate {
+            warn!("{:?} is also there as a folder, only the folder gets loaded", path);
+        }
+        !duplicate
+    });
 
-    for possible_item in read_dir {
-        load_type.push(match load_item(possible_item, main_file) {
+    for path in pack_paths {
+        load_type.push(match load_item(&path, main_file) {
             Ok(item) => item,
             Err(angry) => {
                 error!("{}", angry);